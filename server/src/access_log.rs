@@ -0,0 +1,122 @@
+use hyper::StatusCode;
+use std::io::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::{Instant, SystemTime};
+
+use crate::server::{Connection, Request};
+
+/// The authenticated identity of a request.
+///
+/// This is stored in the request extensions and filled in when the request is authenticated.
+#[derive(Debug, Clone, Default)]
+pub struct Identity(Arc<OnceLock<String>>);
+
+impl Identity {
+	/// Set the identity.
+	///
+	/// If the identity was already set, it is left unchanged.
+	pub fn set(&self, user: &str) {
+		self.0.get_or_init(|| user.to_string());
+	}
+
+	/// Get the identity, if it was set.
+	pub fn get(&self) -> Option<&str> {
+		self.0.get().map(|x| x.as_str())
+	}
+}
+
+/// Generate a new request ID.
+///
+/// The ID consists of the start time of the server and a counter,
+/// so it is unique within the lifetime of the server and unlikely to be repeated after a restart.
+pub fn next_request_id() -> String {
+	static START: OnceLock<u64> = OnceLock::new();
+	static COUNTER: AtomicU64 = AtomicU64::new(0);
+	let start = START.get_or_init(|| {
+		SystemTime::now()
+			.duration_since(SystemTime::UNIX_EPOCH)
+			.map(|x| x.as_secs())
+			.unwrap_or(0)
+	});
+	format!("{:x}-{:x}", start, COUNTER.fetch_add(1, Ordering::Relaxed))
+}
+
+/// An entry for the access log.
+///
+/// The entry is written as a single JSON line to standard output when it is dropped.
+pub struct Entry {
+	request_id: String,
+	connection: Connection,
+	method: String,
+	path: String,
+	identity: Identity,
+	start: Instant,
+	status: StatusCode,
+	bytes_sent: AtomicU64,
+}
+
+impl Entry {
+	/// Create a new access log entry for a request.
+	pub fn new(request_id: String, connection: Connection, request: &Request, identity: Identity, start: Instant) -> Self {
+		Self {
+			request_id,
+			connection,
+			method: request.method().to_string(),
+			path: request.uri().path().to_string(),
+			identity,
+			start,
+			status: StatusCode::INTERNAL_SERVER_ERROR,
+			bytes_sent: AtomicU64::new(0),
+		}
+	}
+
+	/// Set the status code of the response.
+	pub fn with_status(mut self, status: StatusCode) -> Self {
+		self.status = status;
+		self
+	}
+
+	/// Add to the number of response body bytes sent.
+	pub fn add_bytes_sent(&self, bytes: u64) {
+		self.bytes_sent.fetch_add(bytes, Ordering::Relaxed);
+	}
+}
+
+impl Drop for Entry {
+	fn drop(&mut self) {
+		#[derive(serde::Serialize)]
+		struct Line<'a> {
+			time: String,
+			request_id: &'a str,
+			remote_address: String,
+			method: &'a str,
+			path: &'a str,
+			status: u16,
+			response_size: u64,
+			latency_seconds: f64,
+			tls: bool,
+			identity: Option<&'a str>,
+		}
+
+		let line = Line {
+			time: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+			request_id: &self.request_id,
+			remote_address: self.connection.address.to_string(),
+			method: &self.method,
+			path: &self.path,
+			status: self.status.as_u16(),
+			response_size: self.bytes_sent.load(Ordering::Relaxed),
+			latency_seconds: self.start.elapsed().as_secs_f64(),
+			tls: self.connection.tls,
+			identity: self.identity.get(),
+		};
+
+		// Unwrap should be fine: the line is always JSON encodable.
+		let mut data = serde_json::to_vec(&line).unwrap();
+		data.push(b'\n');
+		if let Err(e) = std::io::stdout().lock().write_all(&data) {
+			log::warn!("Failed to write access log entry: {}", e);
+		}
+	}
+}
@@ -1,25 +1,32 @@
-use hyper::{header, Method};
+use http_body_util::BodyExt as _;
+use hyper::body::{Bytes, Incoming};
+use hyper::{header, Method, StatusCode};
+use palletizer::Registry;
+use sha2::{Digest, Sha256};
 use std::borrow::Cow;
 use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, RwLock};
+use tokio::io::AsyncWriteExt as _;
 
-use crate::Registry;
-use crate::server::{self, Request, Response, HttpError};
+use crate::metrics::{Operation, METRICS};
+use crate::server::{self, AuthError, Request, Response, HttpError};
 
-pub async fn handle_request(registry: Arc<RwLock<Registry>>, request: Request, api_path: &str) -> Result<Response, HttpError> {
+pub async fn handle_request(registry: Arc<RwLock<Registry>>, request: Request, api_path: &str, max_crate_size: u64) -> Result<Response, HttpError> {
 	if api_path == "crates" {
-		search(registry, request.uri().query())
+		search(registry, &request)
 	} else if let Some(api_path) = api_path.strip_prefix("crates/") {
-		handle_crate_request(registry, request, api_path).await
+		handle_crate_request(registry, request, api_path, max_crate_size).await
 	} else {
 		log::warn!("Got request for unknown or unimplemented API V1 endpoint: {}", api_path);
 		server::not_found()
 	}
 }
 
-async fn handle_crate_request(registry: Arc<RwLock<Registry>>, request: Request, api_path: &str) -> Result<Response, HttpError> {
+async fn handle_crate_request(registry: Arc<RwLock<Registry>>, request: Request, api_path: &str, max_crate_size: u64) -> Result<Response, HttpError> {
 	if api_path == "new" {
-		publish_crate(registry, request).await
+		publish_crate(registry, request, max_crate_size).await
 	} else {
 		let (name, rest) = match api_path.split_once('/') {
 			Some(x) => x,
@@ -28,6 +35,9 @@ async fn handle_crate_request(registry: Arc<RwLock<Registry>>, request: Request,
 				return server::not_found();
 			},
 		};
+		if rest == "owners" {
+			return handle_owners_request(registry, request, name).await;
+		}
 		let (version, action) = match rest.split_once('/') {
 			Some(x) => x,
 			None => {
@@ -36,8 +46,8 @@ async fn handle_crate_request(registry: Arc<RwLock<Registry>>, request: Request,
 			},
 		};
 		match action {
-			"yank" => yank_crate(registry, name, version, request.method()),
-			"unyank" => unyank_crate(registry, name, version, request.method()),
+			"yank" => yank_crate(registry, name, version, &request).await,
+			"unyank" => unyank_crate(registry, name, version, &request).await,
 			_ => {
 				log::warn!("Got request for unknown or unimplemented crate action: {}", action);
 				server::not_found()
@@ -46,54 +56,108 @@ async fn handle_crate_request(registry: Arc<RwLock<Registry>>, request: Request,
 	}
 }
 
-async fn publish_crate(registry: Arc<RwLock<Registry>>, request: Request) -> Result<Response, HttpError> {
+async fn publish_crate(registry: Arc<RwLock<Registry>>, request: Request, max_crate_size: u64) -> Result<Response, HttpError> {
 	if let Some(response) = server::check_supported_method(request.method(), &[Method::PUT]) {
 		log::warn!("Unsupported request method for v1/crates/new: {}", request.method());
 		return response;
 	}
+	let operation = METRICS.start_operation(Operation::Publish);
 
-	let body = match server::collect_body(request.into_body()).await {
+	let user = match check_token(&registry.read().unwrap(), &request) {
 		Ok(x) => x,
-		Err(e) => {
-			log::error!("Failed to read request body: {}", e);
-			return server::internal_server_error("Failed to read response body");
-		}
+		Err(response) => return response,
 	};
 
-	let (metadata, crate_data) = match parse_crate(&body) {
+	// Reject uploads that can not fit within the limits before reading anything.
+	// The body holds two length fields, the metadata and the crate tarball.
+	let max_body_size = max_crate_size.saturating_mul(2).saturating_add(8);
+	if let Some(length) = content_length(&request) && length > max_body_size {
+		log::warn!("Refused upload of {} bytes: exceeds the maximum crate size of {} bytes", length, max_crate_size);
+		return error_response_with_status(StatusCode::PAYLOAD_TOO_LARGE, format!("upload is too large: the maximum crate size is {} bytes", max_crate_size));
+	}
+
+	let temp_dir = registry.read().unwrap().temp_dir();
+	let Upload { metadata, tarball, sha256 } = match read_upload(request.into_body(), max_crate_size, &temp_dir).await {
 		Ok(x) => x,
-		Err(e) => {
+		Err(UploadError::Read(e)) => {
+			log::error!("Failed to read request body: {}", e);
+			return server::internal_server_error("Failed to read request body");
+		},
+		Err(UploadError::Spool(e)) => {
+			log::error!("Failed to write crate tarball to temporary file: {}", e);
+			return server::internal_server_error("Failed to store uploaded crate");
+		},
+		Err(UploadError::TooLarge(e)) => {
+			log::warn!("Refused upload: {}", e);
+			return error_response_with_status(StatusCode::PAYLOAD_TOO_LARGE, e);
+		},
+		Err(UploadError::Invalid(e)) => {
 			log::error!("Failed to parse request body: {}", e);
 			return error_response(e);
-		}
+		},
 	};
 
-	let crate_sha256 = compute_sha256_hex(crate_data);
-	let index_entry = metadata.into_index_entry(crate_sha256);
+	let crate_metadata = metadata.to_metadata();
+	let index_entry = metadata.into_index_entry(sha256);
 
-	let mut registry = registry.write().unwrap();
-	match registry.add_crate_with_metadata(&index_entry, crate_data) {
-		Ok(()) => (),
-		Err(e) => {
-			log::error!("Failed to publish crate {}-{}: {}", index_entry.name, index_entry.version, e);
-			return error_response(e);
-		},
-	}
+	with_registry_mut(registry, move |registry| {
+		let owners = match check_owner(registry, &index_entry.name, &user) {
+			Ok(x) => x,
+			Err(response) => return response,
+		};
+
+		// The first publisher of a crate becomes the owner.
+		// The owner is recorded before the crate is added, so a published crate never ends up without owners.
+		let new_crate = owners.is_empty();
+		if new_crate && let Err(e) = registry.add_crate_owners(&index_entry.name, &[&user]) {
+			log::error!("Failed to add {} as owner of {}: {}", user, index_entry.name, e);
+			return server::internal_server_error("Failed to record crate owner");
+		}
+
+		match registry.add_crate_file_with_metadata(&index_entry, &tarball.path) {
+			Ok(()) => (),
+			Err(e) => {
+				log::error!("Failed to publish crate {}-{}: {}", index_entry.name, index_entry.version, e);
+				if new_crate && let Err(e) = registry.clear_crate_owners(&index_entry.name) {
+					log::error!("Failed to remove {} as owner of unpublished crate {}: {}", user, index_entry.name, e);
+				}
+				return error_response(e);
+			},
+		}
+		if new_crate {
+			log::info!("Added {} as owner of {}", user, index_entry.name);
+		}
 
-	log::info!("Published {}-{} with sha256 checksum {}", index_entry.name, index_entry.version, index_entry.checksum_sha256);
-	json_response("{\"warnings\":{\"invalid_categories\":[],\"invalid_badges\":[],\"other\":[]}}")
+		if let Err(e) = registry.store_metadata(&crate_metadata) {
+			log::error!("Failed to store metadata for {}-{}: {}", index_entry.name, index_entry.version, e);
+		}
+
+		log::info!("User {} published {}-{} with sha256 checksum {}", user, index_entry.name, index_entry.version, index_entry.checksum_sha256);
+		operation.succeeded();
+		json_response("{\"warnings\":{\"invalid_categories\":[],\"invalid_badges\":[],\"other\":[]}}")
+	}).await
 }
 
 /// Compute the sha256sum of some data and return it as lowercase hex string.
 pub fn compute_sha256_hex(data: impl AsRef<[u8]>) -> String {
-	use std::fmt::Write;
-	use sha2::{Digest, Sha256};
-	let digest = Sha256::digest(data.as_ref());
-	let mut output = String::with_capacity(digest.len() * 2);
-	for byte in digest {
-		write!(output, "{byte:02X}").unwrap();
+	palletizer::to_hex(Sha256::digest(data.as_ref()))
+}
+
+/// Run a function with exclusive access to the registry on a blocking thread.
+///
+/// Modifying the registry writes to the index repository and may upload to the storage backend.
+/// Doing that on the async runtime would block every other request that needs the registry.
+async fn with_registry_mut<F>(registry: Arc<RwLock<Registry>>, function: F) -> Result<Response, HttpError>
+where
+	F: FnOnce(&mut Registry) -> Result<Response, HttpError> + Send + 'static,
+{
+	match tokio::task::spawn_blocking(move || function(&mut registry.write().unwrap())).await {
+		Ok(x) => x,
+		Err(e) => {
+			log::error!("Failed to join background task: {}", e);
+			server::internal_server_error("Failed to update registry")
+		},
 	}
-	output
 }
 
 #[derive(serde::Deserialize)]
@@ -108,9 +172,37 @@ struct NewCrateMeta {
 
 	features: BTreeMap<String, Vec<String>>,
 
-	links: Option<String>
+	links: Option<String>,
+
+	rust_version: Option<String>,
+
+	// Fields below are not needed for the index,
+	// but they are kept in the metadata store.
 
-	// Other fields ignored, because not needed for the index.
+	#[serde(default)]
+	authors: Vec<String>,
+
+	description: Option<String>,
+
+	documentation: Option<String>,
+
+	homepage: Option<String>,
+
+	readme: Option<String>,
+
+	readme_file: Option<String>,
+
+	#[serde(default)]
+	keywords: Vec<String>,
+
+	#[serde(default)]
+	categories: Vec<String>,
+
+	license: Option<String>,
+
+	license_file: Option<String>,
+
+	repository: Option<String>,
 }
 
 #[derive(Debug, Clone, serde::Deserialize)]
@@ -157,132 +249,465 @@ impl NewCrateDependency {
 }
 
 impl NewCrateMeta {
+	fn to_metadata(&self) -> palletizer::metadata::Metadata {
+		palletizer::metadata::Metadata {
+			name: self.name.clone(),
+			version: self.version.clone(),
+			authors: self.authors.clone(),
+			description: self.description.clone(),
+			keywords: self.keywords.clone(),
+			categories: self.categories.clone(),
+			license: self.license.clone(),
+			license_file: self.license_file.clone(),
+			repository: self.repository.clone(),
+			homepage: self.homepage.clone(),
+			documentation: self.documentation.clone(),
+			readme: self.readme.clone(),
+			readme_file: self.readme_file.clone(),
+		}
+	}
+
 	fn into_index_entry(self, crate_sha256: String) -> palletizer::index::Entry {
 		let dependencies = self.dependencies
 			.into_iter()
 			.map(NewCrateDependency::into_index_dependency)
 			.collect();
+		let (features, features2) = palletizer::index::split_features(self.features);
 		palletizer::index::Entry {
 			name: self.name,
 			version: self.version,
 			dependencies,
-			features: self.features,
+			v: palletizer::index::format_version(&features2),
+			features,
+			features2,
 			checksum_sha256: crate_sha256,
 			yanked: false,
 			links: self.links,
+			rust_version: self.rust_version,
 		}
 	}
 }
 
-fn parse_crate(data: &[u8]) -> Result<(NewCrateMeta, &[u8]), String> {
-	if data.len() < 4 {
-		return Err("missing metadata JSON length".into());
+/// A crate uploaded with a publish request.
+struct Upload {
+	/// The metadata sent along with the crate.
+	metadata: NewCrateMeta,
+
+	/// The temporary file holding the crate tarball.
+	tarball: TempFile,
+
+	/// The sha256 checksum of the crate tarball as lowercase hex string.
+	sha256: String,
+}
+
+enum UploadError {
+	/// Failed to read the request body.
+	Read(hyper::Error),
+
+	/// Failed to write the crate tarball to the temporary file.
+	Spool(std::io::Error),
+
+	/// The metadata or crate tarball exceeds the maximum crate size.
+	TooLarge(String),
+
+	/// The request body is malformed.
+	Invalid(String),
+}
+
+impl From<hyper::Error> for UploadError {
+	fn from(other: hyper::Error) -> Self {
+		Self::Read(other)
 	}
+}
 
-	let json_len = usize::from(data[0]) + (usize::from(data[1])<< 8) + (usize::from(data[2]) << 16) + (usize::from(data[3]) << 24);
-	let data = &data[4..];
+/// Read the body of a publish request.
+///
+/// The metadata is parsed and the crate tarball is written to a temporary file in `temp_dir` while it is hashed.
+/// The lengths in the body are checked against `max_crate_size` before the data is read.
+async fn read_upload(body: Incoming, max_crate_size: u64, temp_dir: &Path) -> Result<Upload, UploadError> {
+	let mut body = BodyReader::new(body);
 
-	if data.len() < json_len {
-		return Err(format!("expected {} bytes of metadata, got only {} bytes remaining", json_len, data.len()));
+	let json_len = body.read_u32_le().await?
+		.ok_or_else(|| UploadError::Invalid("missing metadata JSON length".into()))?;
+	if u64::from(json_len) > max_crate_size {
+		return Err(UploadError::TooLarge(format!("crate metadata is too large: {} bytes exceeds the maximum of {} bytes", json_len, max_crate_size)));
+	}
+	let json = body.read_exact(json_len as usize).await?;
+	if json.len() != json_len as usize {
+		return Err(UploadError::Invalid(format!("expected {} bytes of metadata, got only {} bytes", json_len, json.len())));
 	}
+	let metadata = serde_json::from_slice(&json)
+		.map_err(|e| UploadError::Invalid(format!("failed to parse crate metadata: {}", e)))?;
 
-	let (json, data) = data.split_at(json_len);
+	let tarball_len = body.read_u32_le().await?
+		.ok_or_else(|| UploadError::Invalid("missing crate tarball length".into()))?;
+	if u64::from(tarball_len) > max_crate_size {
+		return Err(UploadError::TooLarge(format!("crate is too large: {} bytes exceeds the maximum of {} bytes", tarball_len, max_crate_size)));
+	}
 
-	if data.len() < 4 {
-		return Err("missing crate tarball length".into());
+	let (tarball, mut file) = TempFile::create(temp_dir).await.map_err(UploadError::Spool)?;
+	let mut hasher = Sha256::new();
+	let mut remaining = tarball_len as usize;
+	while remaining > 0 {
+		let chunk = match body.next_chunk(remaining).await? {
+			Some(x) => x,
+			None => return Err(UploadError::Invalid(format!("expected exactly {} bytes of crate tarball, got only {} bytes", tarball_len, tarball_len as usize - remaining))),
+		};
+		hasher.update(&chunk);
+		file.write_all(&chunk).await.map_err(UploadError::Spool)?;
+		remaining -= chunk.len();
 	}
-	let tarball_len = usize::from(data[0]) + (usize::from(data[1])<< 8) + (usize::from(data[2]) << 16) + (usize::from(data[3]) << 24);
-	let tarball = &data[4..];
+	file.flush().await.map_err(UploadError::Spool)?;
 
-	if tarball.len() != tarball_len {
-		return Err(format!("expected {} exactly bytes of crate tarball, got {} bytes remaining", tarball_len, tarball.len()));
+	if body.next_chunk(1).await?.is_some() {
+		return Err(UploadError::Invalid(format!("unexpected data after {} bytes of crate tarball", tarball_len)));
 	}
 
-	let meta = serde_json::from_slice(json)
-		.map_err(|e| format!("failed to parse crate metadata: {}", e))?;
+	Ok(Upload {
+		metadata,
+		tarball,
+		sha256: palletizer::to_hex(hasher.finalize()),
+	})
+}
 
-	Ok((meta, tarball))
+/// Incremental reader for a request body.
+struct BodyReader {
+	body: Incoming,
+	buffer: Bytes,
 }
 
-fn yank_crate(registry: Arc<RwLock<Registry>>, name: &str, version: &str, method: &Method) -> Result<Response, HttpError> {
-	if let Some(response) = server::check_supported_method(method, &[Method::DELETE]) {
-		return response;
+impl BodyReader {
+	fn new(body: Incoming) -> Self {
+		Self {
+			body,
+			buffer: Bytes::new(),
+		}
 	}
 
-	let mut registry = registry.write().unwrap();
-	match registry.yank_crate(name, version) {
-		Err(e) => {
-			log::info!("Failed to yank {}-{}: {}", name, version, e);
-			error_response(e)
-		},
-		Ok(true) => {
-			log::info!("Yanked {}-{}", name, version);
-			json_response("{\"ok\":true}")
-		},
-		Ok(false) => {
-			log::info!("Ignored yank request for {}-{} (already yanked)", name, version);
-			json_response("{\"ok\":true}")
-		},
+	/// Get the next chunk of at most `max_len` bytes.
+	///
+	/// Returns None at the end of the body.
+	async fn next_chunk(&mut self, max_len: usize) -> hyper::Result<Option<Bytes>> {
+		while self.buffer.is_empty() {
+			match self.body.frame().await {
+				None => return Ok(None),
+				Some(frame) => {
+					if let Ok(data) = frame?.into_data() {
+						self.buffer = data;
+					}
+				},
+			}
+		}
+		let len = self.buffer.len().min(max_len);
+		Ok(Some(self.buffer.split_to(len)))
+	}
+
+	/// Read up to `len` bytes.
+	///
+	/// Less data is returned only if the end of the body is reached.
+	async fn read_exact(&mut self, len: usize) -> hyper::Result<Vec<u8>> {
+		let mut data = Vec::with_capacity(len);
+		while data.len() < len {
+			match self.next_chunk(len - data.len()).await? {
+				Some(chunk) => data.extend_from_slice(&chunk),
+				None => break,
+			}
+		}
+		Ok(data)
+	}
+
+	/// Read a little endian 32 bit integer.
+	///
+	/// Returns None if the body ends before the integer is complete.
+	async fn read_u32_le(&mut self) -> hyper::Result<Option<u32>> {
+		let data = self.read_exact(4).await?;
+		Ok(data.try_into().ok().map(u32::from_le_bytes))
 	}
 }
 
-fn unyank_crate(registry: Arc<RwLock<Registry>>, name: &str, version: &str, method: &Method) -> Result<Response, HttpError> {
-	if let Some(response) = server::check_supported_method(method, &[Method::PUT]) {
+/// A temporary file that is deleted when dropped.
+struct TempFile {
+	path: PathBuf,
+}
+
+impl TempFile {
+	/// Create a new temporary file in a directory and open it for writing.
+	///
+	/// The directory is created if it does not exist yet.
+	async fn create(dir: &Path) -> std::io::Result<(Self, tokio::fs::File)> {
+		static COUNTER: AtomicU64 = AtomicU64::new(0);
+		tokio::fs::create_dir_all(dir).await?;
+		let name = format!("upload-{}-{}.crate", std::process::id(), COUNTER.fetch_add(1, Ordering::Relaxed));
+		let path = dir.join(name);
+		let file = tokio::fs::OpenOptions::new()
+			.write(true)
+			.create_new(true)
+			.open(&path)
+			.await?;
+		Ok((Self { path }, file))
+	}
+}
+
+impl Drop for TempFile {
+	fn drop(&mut self) {
+		if let Err(e) = std::fs::remove_file(&self.path) {
+			log::warn!("Failed to remove temporary file {}: {}", self.path.display(), e);
+		}
+	}
+}
+
+/// Get the value of the Content-Length header of a request, if it is present and valid.
+fn content_length(request: &Request) -> Option<u64> {
+	request.headers()
+		.get(header::CONTENT_LENGTH)?
+		.to_str()
+		.ok()?
+		.parse()
+		.ok()
+}
+
+async fn yank_crate(registry: Arc<RwLock<Registry>>, name: &str, version: &str, request: &Request) -> Result<Response, HttpError> {
+	if let Some(response) = server::check_supported_method(request.method(), &[Method::DELETE]) {
 		return response;
 	}
+	let operation = METRICS.start_operation(Operation::Yank);
 
-	let mut registry = registry.write().unwrap();
-	match registry.unyank_crate(name, version) {
-		Err(e) => {
-			log::info!("Failed to yank {}-{}: {}", name, version, e);
-			error_response(e)
-		},
-		Ok(true) => {
-			log::info!("Unyanked {}-{}", name, version);
-			json_response("{\"ok\":true}")
-		},
-		Ok(false) => {
-			log::info!("Ignored unyank request for {}-{} (not yanked)", name, version);
-			json_response("{\"ok\":true}")
-		},
+	let user = match check_token(&registry.read().unwrap(), request) {
+		Ok(x) => x,
+		Err(response) => return response,
+	};
+
+	let name = name.to_string();
+	let version = version.to_string();
+	with_registry_mut(registry, move |registry| {
+		if let Err(response) = check_owner(registry, &name, &user) {
+			return response;
+		}
+		match registry.yank_crate(&name, &version) {
+			Err(e) => {
+				log::info!("Failed to yank {}-{}: {}", name, version, e);
+				error_response(e)
+			},
+			Ok(true) => {
+				operation.succeeded();
+				log::info!("User {} yanked {}-{}", user, name, version);
+				json_response("{\"ok\":true}")
+			},
+			Ok(false) => {
+				operation.succeeded();
+				log::info!("Ignored yank request for {}-{} (already yanked)", name, version);
+				json_response("{\"ok\":true}")
+			},
+		}
+	}).await
+}
+
+async fn unyank_crate(registry: Arc<RwLock<Registry>>, name: &str, version: &str, request: &Request) -> Result<Response, HttpError> {
+	if let Some(response) = server::check_supported_method(request.method(), &[Method::PUT]) {
+		return response;
 	}
+	let operation = METRICS.start_operation(Operation::Unyank);
+
+	let user = match check_token(&registry.read().unwrap(), request) {
+		Ok(x) => x,
+		Err(response) => return response,
+	};
+
+	let name = name.to_string();
+	let version = version.to_string();
+	with_registry_mut(registry, move |registry| {
+		if let Err(response) = check_owner(registry, &name, &user) {
+			return response;
+		}
+		match registry.unyank_crate(&name, &version) {
+			Err(e) => {
+				log::info!("Failed to yank {}-{}: {}", name, version, e);
+				error_response(e)
+			},
+			Ok(true) => {
+				operation.succeeded();
+				log::info!("User {} unyanked {}-{}", user, name, version);
+				json_response("{\"ok\":true}")
+			},
+			Ok(false) => {
+				operation.succeeded();
+				log::info!("Ignored unyank request for {}-{} (not yanked)", name, version);
+				json_response("{\"ok\":true}")
+			},
+		}
+	}).await
 }
 
-fn search(registry: Arc<RwLock<Registry>>, url_query: Option<&str>) -> Result<Response, HttpError> {
+async fn handle_owners_request(registry: Arc<RwLock<Registry>>, request: Request, name: &str) -> Result<Response, HttpError> {
+	if let Some(response) = server::check_supported_method(request.method(), &[Method::GET, Method::PUT, Method::DELETE]) {
+		return response;
+	}
+
+	if request.method() == Method::GET {
+		return list_owners(registry, &request, name);
+	}
+
+	let user = match check_token(&registry.read().unwrap(), &request) {
+		Ok(x) => x,
+		Err(response) => return response,
+	};
+
 	#[derive(serde::Deserialize)]
-	struct Params<'a> {
-		q: Option<Cow<'a, str>>,
-		per_page: Option<usize>,
+	struct Params {
+		users: Vec<String>,
 	}
 
+	let add = request.method() == Method::PUT;
+	let body = match server::collect_body(request.into_body()).await {
+		Ok(x) => x,
+		Err(e) => {
+			log::error!("Failed to read request body: {}", e);
+			return server::internal_server_error("Failed to read response body");
+		}
+	};
+
+	let params: Params = match serde_json::from_slice(&body) {
+		Ok(x) => x,
+		Err(e) => {
+			log::error!("Failed to parse request body: {}", e);
+			return error_response(format!("failed to parse request body: {}", e));
+		},
+	};
+
+	let name = name.to_string();
+	with_registry_mut(registry, move |registry| {
+		if !registry.contains_crate(&name) {
+			return error_response_with_status(StatusCode::NOT_FOUND, format!("crate `{}` does not exist", name));
+		}
+		if let Err(response) = check_owner(registry, &name, &user) {
+			return response;
+		}
+
+		if add {
+			for new_owner in &params.users {
+				match registry.is_known_user(new_owner) {
+					Ok(true) => (),
+					Ok(false) => return error_response(format!("unknown user: `{}`", new_owner)),
+					Err(e) => {
+						log::error!("Failed to look up user {}: {}", new_owner, e);
+						return error_response(e);
+					}
+				}
+			}
+			if let Err(e) = registry.add_crate_owners(&name, &params.users) {
+				log::error!("Failed to add owners to {}: {}", name, e);
+				return error_response(e);
+			}
+			log::info!("User {} added {} as owner of {}", user, params.users.join(", "), name);
+			owners_ok_response(format!("user(s) {} added as owner(s) of crate {}", params.users.join(", "), name))
+		} else {
+			if let Err(e) = registry.remove_crate_owners(&name, &params.users) {
+				log::info!("Failed to remove owners from {}: {}", name, e);
+				return error_response(e);
+			}
+			log::info!("User {} removed {} as owner of {}", user, params.users.join(", "), name);
+			owners_ok_response(format!("user(s) {} removed as owner(s) of crate {}", params.users.join(", "), name))
+		}
+	}).await
+}
+
+fn list_owners(registry: Arc<RwLock<Registry>>, request: &Request, name: &str) -> Result<Response, HttpError> {
 	#[derive(serde::Serialize)]
-	struct FoundCrate {
-		name: String,
-		max_version: String,
-		description: String,
+	struct User<'a> {
+		id: usize,
+		login: &'a str,
+		name: &'a str,
 	}
 
 	#[derive(serde::Serialize)]
-	struct SearchResultsMeta {
-		total: usize,
+	struct Users<'a> {
+		users: Vec<User<'a>>,
+	}
+
+	let registry = registry.read().unwrap();
+	if registry.auth_required() && let Err(response) = check_token(&registry, request) {
+		return response;
 	}
 
+	if !registry.contains_crate(name) {
+		return error_response_with_status(StatusCode::NOT_FOUND, format!("crate `{}` does not exist", name));
+	}
+
+	let owners = match registry.crate_owners(name) {
+		Ok(x) => x,
+		Err(e) => {
+			log::error!("Failed to read owners of {}: {}", name, e);
+			return error_response(e);
+		},
+	};
+
+	let users = owners.iter()
+		.enumerate()
+		.map(|(i, owner)| User { id: i + 1, login: owner, name: owner })
+		.collect();
+
+	let json = serde_json::to_string(&Users { users }).unwrap();
+	json_response(json)
+}
+
+fn owners_ok_response(message: String) -> Result<Response, HttpError> {
 	#[derive(serde::Serialize)]
-	struct SearchResults {
-		crates: Vec<FoundCrate>,
-		meta: SearchResultsMeta,
+	struct OwnersResponse {
+		ok: bool,
+		msg: String,
 	}
 
-	let params: Params = match serde_urlencoded::from_str(url_query.unwrap_or("")) {
+	let json = serde_json::to_string(&OwnersResponse { ok: true, msg: message }).unwrap();
+	json_response(json)
+}
+
+fn search(registry: Arc<RwLock<Registry>>, request: &Request) -> Result<Response, HttpError> {
+	let params: SearchParams = match serde_urlencoded::from_str(request.uri().query().unwrap_or("")) {
 		Err(e) => return error_response(e),
 		Ok(params) => params,
 	};
 
-	let query = params.q.unwrap_or_else(|| "".into());
-	let query = query.as_ref();
-	let max_results = params.per_page.unwrap_or(10);
-
 	let registry = registry.read().unwrap();
+	if registry.auth_required() && let Err(response) = check_token(&registry, request) {
+		return response;
+	}
+
+	let json = serde_json::to_string(&search_crates(&registry, &params)).unwrap();
+	json_response(json)
+}
+
+#[derive(serde::Deserialize)]
+struct SearchParams<'a> {
+	q: Option<Cow<'a, str>>,
+	page: Option<usize>,
+	per_page: Option<usize>,
+}
+
+#[derive(serde::Serialize)]
+struct FoundCrate {
+	name: String,
+	max_version: String,
+	description: String,
+}
+
+#[derive(serde::Serialize)]
+struct SearchResultsMeta {
+	total: usize,
+}
+
+#[derive(serde::Serialize)]
+struct SearchResults {
+	crates: Vec<FoundCrate>,
+	meta: SearchResultsMeta,
+}
+
+/// Find the crates matching a search query.
+///
+/// The results are sorted by [`SearchRank`] and then by name.
+fn search_crates(registry: &Registry, params: &SearchParams) -> SearchResults {
+	let query = normalize_search_term(params.q.as_deref().unwrap_or(""));
+	let per_page = params.per_page.unwrap_or(10).clamp(1, MAX_SEARCH_RESULTS_PER_PAGE);
+	let page = params.page.unwrap_or(1).max(1);
 
 	let mut crates: Vec<_> = registry.iter_crate_names()
 		.filter_map(|name| {
@@ -293,9 +718,7 @@ fn search(registry: Arc<RwLock<Registry>>, url_query: Option<&str>) -> Result<Re
 					return None;
 				},
 			};
-			if !name.contains(query) {
-				return None;
-			}
+
 			let entries = match registry.read_index(&name) {
 				Ok(x) => x,
 				Err(e) => {
@@ -304,34 +727,189 @@ fn search(registry: Arc<RwLock<Registry>>, url_query: Option<&str>) -> Result<Re
 				}
 			};
 
-			entries
-				.iter()
-				.filter_map(|entry| semver::Version::parse(&entry.version).ok())
-				.max_by_key(|version| version.clone())
-				.map(|version| {
-					FoundCrate {
-						name,
-						max_version: version.to_string(),
-						description: "".into(), // TODO: omfg, got to read the compressed crate file to extract the manifest
-					}
-				})
+			// Like crates.io, show the highest version that is not yanked,
+			// unless all versions are yanked.
+			let max_version = |include_yanked: bool| {
+				entries.iter()
+					.filter(|entry| include_yanked || !entry.yanked)
+					.filter_map(|entry| semver::Version::parse(&entry.version).ok())
+					.max()
+			};
+			let max_version = max_version(false).or_else(|| max_version(true))?;
+
+			// Only read the metadata if the name does not match.
+			let mut metadata = None;
+			let rank = match SearchRank::from_name(&name, &query) {
+				Some(rank) => rank,
+				None => {
+					metadata = read_version_metadata(registry, &name, &max_version);
+					SearchRank::from_metadata(metadata.as_ref()?, &query)?
+				},
+			};
+
+			let metadata = metadata.or_else(|| read_version_metadata(registry, &name, &max_version));
+			let description = metadata.and_then(|x| x.description);
+
+			Some((rank, FoundCrate {
+				name,
+				max_version: max_version.to_string(),
+				description: description.unwrap_or_default(),
+			}))
 		})
 		.collect();
 
+	crates.sort_by(|(rank_a, crate_a), (rank_b, crate_b)| {
+		rank_a.cmp(rank_b).then_with(|| crate_a.name.cmp(&crate_b.name))
+	});
+
 	let total = crates.len();
-	crates.truncate(max_results);
+	let crates = crates.into_iter()
+		.map(|(_rank, found)| found)
+		.skip((page - 1).saturating_mul(per_page))
+		.take(per_page)
+		.collect();
 
-	let json = serde_json::to_string(&SearchResults {
+	SearchResults {
 		crates,
 		meta: SearchResultsMeta {
 			total,
 		}
-	}).unwrap();
+	}
+}
 
-	json_response(json)
+/// The maximum number of search results per page.
+const MAX_SEARCH_RESULTS_PER_PAGE: usize = 100;
+
+/// How well a crate matches a search query.
+///
+/// Better matches compare as less than worse matches.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd)]
+enum SearchRank {
+	/// The query is exactly the crate name.
+	ExactName,
+
+	/// The crate name starts with the query.
+	NamePrefix,
+
+	/// The crate name contains the query.
+	NameSubstring,
+
+	/// A keyword of the crate matches the query exactly.
+	Keyword,
+
+	/// The description of the crate contains the query.
+	Description,
+}
+
+impl SearchRank {
+	/// Determine the rank of a crate based on the name.
+	///
+	/// The query must already be normalized with [`normalize_search_term()`].
+	fn from_name(name: &str, query: &str) -> Option<Self> {
+		let name = normalize_search_term(name);
+		if name == query {
+			Some(Self::ExactName)
+		} else if name.starts_with(query) {
+			Some(Self::NamePrefix)
+		} else if name.contains(query) {
+			Some(Self::NameSubstring)
+		} else {
+			None
+		}
+	}
+
+	/// Determine the rank of a crate based on the metadata.
+	///
+	/// The query must already be normalized with [`normalize_search_term()`].
+	fn from_metadata(metadata: &palletizer::metadata::Metadata, query: &str) -> Option<Self> {
+		if metadata.keywords.iter().any(|x| normalize_search_term(x) == query) {
+			Some(Self::Keyword)
+		} else if metadata.description.as_deref().is_some_and(|x| normalize_search_term(x).contains(query)) {
+			Some(Self::Description)
+		} else {
+			None
+		}
+	}
+}
+
+/// Normalize a search term or crate name for comparison.
+///
+/// This makes the term lowercase and treats `-` and `_` as the same character,
+/// like [`palletizer::canonical_crate_name()`].
+fn normalize_search_term(term: &str) -> String {
+	palletizer::canonical_crate_name(&term.trim().to_lowercase())
+}
+
+/// Read the stored metadata for a specific version of a crate.
+///
+/// Errors are logged and result in `None`.
+fn read_version_metadata(registry: &Registry, name: &str, version: &semver::Version) -> Option<palletizer::metadata::Metadata> {
+	let metadata = match registry.read_metadata(name) {
+		Ok(x) => x,
+		Err(e) => {
+			log::warn!("{}", e);
+			return None;
+		},
+	};
+	metadata.into_iter()
+		.find(|x| semver::Version::parse(&x.version).is_ok_and(|x| x == *version))
+}
+
+/// Check the API token in the `Authorization` header of a request.
+///
+/// Returns the user that the token belongs to,
+/// or an error response if the token is missing or unknown.
+#[allow(clippy::result_large_err)]
+fn check_token(registry: &Registry, request: &Request) -> Result<String, Result<Response, HttpError>> {
+	match server::authenticate(registry, request) {
+		Ok(user) => Ok(user),
+		Err(e @ (AuthError::MissingToken | AuthError::InvalidToken)) => {
+			log::warn!("Refused {} request for {}: {}", request.method(), request.uri(), e);
+			Err(error_response_with_status(StatusCode::FORBIDDEN, e))
+		},
+		Err(e @ AuthError::Other(_)) => {
+			log::error!("{}", e);
+			Err(server::internal_server_error("Failed to check API token"))
+		},
+	}
+}
+
+/// Check if a user is allowed to modify a crate.
+///
+/// Only owners can modify a crate.
+/// Any authenticated user is allowed to publish a new crate.
+/// Existing crates without owners (for example, added with `palletizer add`) can not be modified by anyone,
+/// until an administrator adds owners with `palletizer owner`.
+///
+/// Returns the current owners of the crate, or an error response if the user is not allowed to modify the crate.
+#[allow(clippy::result_large_err)]
+fn check_owner(registry: &Registry, crate_name: &str, user: &str) -> Result<Vec<String>, Result<Response, HttpError>> {
+	let owners = match registry.crate_owners(crate_name) {
+		Ok(x) => x,
+		Err(e) => {
+			log::error!("Failed to read owners of {}: {}", crate_name, e);
+			return Err(server::internal_server_error("Failed to read crate owners"));
+		},
+	};
+
+	if owners.is_empty() && !registry.contains_crate(crate_name) {
+		Ok(owners)
+	} else if owners.is_empty() {
+		log::warn!("Refused modification of {} by {}: crate has no owners", crate_name, user);
+		Err(error_response_with_status(StatusCode::FORBIDDEN, format!("crate `{}` has no owners: ask an administrator to add owners with `palletizer owner`", crate_name)))
+	} else if owners.iter().any(|x| x == user) {
+		Ok(owners)
+	} else {
+		log::warn!("Refused modification of {} by {}: not an owner", crate_name, user);
+		Err(error_response_with_status(StatusCode::FORBIDDEN, format!("user `{}` is not an owner of crate `{}`", user, crate_name)))
+	}
 }
 
 fn error_response(message: impl std::fmt::Display) -> Result<Response, HttpError> {
+	error_response_with_status(StatusCode::OK, message)
+}
+
+fn error_response_with_status(status: StatusCode, message: impl std::fmt::Display) -> Result<Response, HttpError> {
 	#[derive(serde::Serialize)]
 	struct ErrorResponse {
 		errors: Vec<Error>,
@@ -349,7 +927,10 @@ fn error_response(message: impl std::fmt::Display) -> Result<Response, HttpError
 	};
 
 	let body = serde_json::to_vec(&response).unwrap();
-	json_response(body)
+	server::response_no_cache()
+		.status(status)
+		.header(header::CONTENT_TYPE, "application/json")
+		.body(body.into())
 }
 
 fn json_response(json: impl Into<crate::server::Body>) -> Result<Response, HttpError> {
@@ -357,3 +938,161 @@ fn json_response(json: impl Into<crate::server::Body>) -> Result<Response, HttpE
 		.header(header::CONTENT_TYPE, "application/json")
 		.body(json.into())
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_util;
+
+	fn owners(result: Result<Vec<String>, Result<Response, HttpError>>) -> Vec<String> {
+		result.ok().unwrap()
+	}
+
+	fn status(result: Result<Vec<String>, Result<Response, HttpError>>) -> StatusCode {
+		result.err().unwrap().ok().unwrap().status()
+	}
+
+	fn add_crate(registry: &mut Registry, name: &str, version: &str, description: &str, keywords: &[&str]) {
+		test_util::add_crate(registry, name, version, version.as_bytes());
+		registry.store_metadata(&palletizer::metadata::Metadata {
+			name: name.into(),
+			version: version.into(),
+			description: Some(description.into()),
+			keywords: keywords.iter().map(|x| x.to_string()).collect(),
+			..Default::default()
+		}).unwrap();
+	}
+
+	fn search(registry: &Registry, query: &str, page: Option<usize>, per_page: Option<usize>) -> (Vec<String>, usize) {
+		let params = SearchParams { q: Some(query.into()), page, per_page };
+		let results = search_crates(registry, &params);
+		(results.crates.into_iter().map(|x| x.name).collect(), results.meta.total)
+	}
+
+	#[test]
+	fn search_ranking() {
+		let dir = tempfile::tempdir().unwrap();
+		let mut registry = test_util::init_registry(dir.path());
+		add_crate(&mut registry, "serde", "1.0.0", "A serialization framework.", &[]);
+		add_crate(&mut registry, "serde_json", "1.0.0", "JSON support for serde.", &[]);
+		add_crate(&mut registry, "my-serde", "1.0.0", "Something else.", &[]);
+		add_crate(&mut registry, "json", "1.0.0", "Another JSON library.", &["serde"]);
+		add_crate(&mut registry, "toml", "1.0.0", "TOML with serde support.", &["config"]);
+		add_crate(&mut registry, "unrelated", "1.0.0", "Nothing to see here.", &[]);
+
+		let (names, total) = search(&registry, "serde", None, None);
+		assert_eq!(names, ["serde", "serde_json", "my-serde", "json", "toml"]);
+		assert_eq!(total, 5);
+
+		let (names, _) = search(&registry, "SERDE ", None, None);
+		assert_eq!(names, ["serde", "serde_json", "my-serde", "json", "toml"]);
+	}
+
+	#[test]
+	fn search_normalizes_separators() {
+		let dir = tempfile::tempdir().unwrap();
+		let mut registry = test_util::init_registry(dir.path());
+		add_crate(&mut registry, "serde_json", "1.0.0", "JSON support for serde.", &[]);
+		add_crate(&mut registry, "tokio-util", "1.0.0", "Utilities for tokio.", &[]);
+
+		assert_eq!(search(&registry, "serde-json", None, None).0, ["serde_json"]);
+		assert_eq!(search(&registry, "tokio_util", None, None).0, ["tokio-util"]);
+		assert_eq!(search(&registry, "Tokio_Util", None, None).0, ["tokio-util"]);
+	}
+
+	#[test]
+	fn search_pages() {
+		let dir = tempfile::tempdir().unwrap();
+		let mut registry = test_util::init_registry(dir.path());
+		for i in 0..105 {
+			test_util::add_crate(&mut registry, &format!("crate{:03}", i), "1.0.0", i.to_string().as_bytes());
+		}
+
+		let (names, total) = search(&registry, "crate", None, None);
+		assert_eq!(names.len(), 10);
+		assert_eq!(names[0], "crate000");
+		assert_eq!(total, 105);
+
+		let (names, total) = search(&registry, "crate", Some(2), Some(50));
+		assert_eq!(names.first().unwrap(), "crate050");
+		assert_eq!(names.last().unwrap(), "crate099");
+		assert_eq!(total, 105);
+
+		let (names, _) = search(&registry, "crate", Some(3), Some(50));
+		assert_eq!(names, ["crate100", "crate101", "crate102", "crate103", "crate104"]);
+
+		let (names, total) = search(&registry, "crate", Some(4), Some(50));
+		assert!(names.is_empty());
+		assert_eq!(total, 105);
+
+		// Page 0 is treated as page 1.
+		assert_eq!(search(&registry, "crate", Some(0), Some(5)).0, search(&registry, "crate", Some(1), Some(5)).0);
+
+		// At most 100 results are returned per page, and at least one.
+		assert_eq!(search(&registry, "crate", None, Some(1000)).0.len(), MAX_SEARCH_RESULTS_PER_PAGE);
+		assert_eq!(search(&registry, "crate", None, Some(0)).0, ["crate000"]);
+	}
+
+	#[test]
+	fn search_uses_max_version_that_is_not_yanked() {
+		let dir = tempfile::tempdir().unwrap();
+		let mut registry = test_util::init_registry(dir.path());
+		add_crate(&mut registry, "foo", "1.0.0", "Version one.", &[]);
+		add_crate(&mut registry, "foo", "2.0.0", "Version two.", &[]);
+		add_crate(&mut registry, "foo", "0.9.0", "Added last.", &[]);
+		registry.yank_crate("foo", "2.0.0").unwrap();
+
+		let results = search_crates(&registry, &SearchParams { q: Some("foo".into()), page: None, per_page: None });
+		assert_eq!(results.crates[0].max_version, "1.0.0");
+		assert_eq!(results.crates[0].description, "Version one.");
+
+		// Keywords and descriptions of older or yanked versions do not match.
+		assert_eq!(search(&registry, "two", None, None).1, 0);
+		assert_eq!(search(&registry, "last", None, None).1, 0);
+		assert_eq!(search(&registry, "one", None, None).0, ["foo"]);
+
+		// If all versions are yanked, the highest version is used.
+		registry.yank_crate("foo", "1.0.0").unwrap();
+		registry.yank_crate("foo", "0.9.0").unwrap();
+		let results = search_crates(&registry, &SearchParams { q: Some("foo".into()), page: None, per_page: None });
+		assert_eq!(results.crates[0].max_version, "2.0.0");
+		assert_eq!(results.crates[0].description, "Version two.");
+	}
+
+	#[test]
+	fn check_owner_allows_new_crates() {
+		let dir = tempfile::tempdir().unwrap();
+		let registry = test_util::init_registry(dir.path());
+		assert_eq!(owners(check_owner(&registry, "foo", "alice")), Vec::<String>::new());
+	}
+
+	#[test]
+	fn check_owner_allows_owners() {
+		let dir = tempfile::tempdir().unwrap();
+		let mut registry = test_util::init_registry(dir.path());
+		test_util::add_crate(&mut registry, "foo", "1.0.0", b"foo");
+		registry.add_crate_owners("foo", &["alice", "bob"]).unwrap();
+		assert_eq!(owners(check_owner(&registry, "foo", "bob")), ["alice", "bob"]);
+	}
+
+	#[test]
+	fn check_owner_refuses_non_owners() {
+		let dir = tempfile::tempdir().unwrap();
+		let mut registry = test_util::init_registry(dir.path());
+		test_util::add_crate(&mut registry, "foo", "1.0.0", b"foo");
+		registry.add_crate_owners("foo", &["alice"]).unwrap();
+		assert_eq!(status(check_owner(&registry, "foo", "mallory")), StatusCode::FORBIDDEN);
+	}
+
+	#[test]
+	fn check_owner_refuses_crates_without_owners() {
+		let dir = tempfile::tempdir().unwrap();
+		let mut registry = test_util::init_registry(dir.path());
+		test_util::add_crate(&mut registry, "foo", "1.0.0", b"foo");
+		assert_eq!(status(check_owner(&registry, "foo", "alice")), StatusCode::FORBIDDEN);
+
+		registry.add_crate_owners("foo", &["alice"]).unwrap();
+		registry.clear_crate_owners("foo").unwrap();
+		assert_eq!(status(check_owner(&registry, "foo", "alice")), StatusCode::FORBIDDEN);
+	}
+}
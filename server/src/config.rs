@@ -10,12 +10,77 @@ pub struct Config {
 
 	#[serde(rename = "listener")]
 	pub listeners: Vec<Listener>,
+
+	/// Upstream registry to forward requests for unknown crates to.
+	pub upstream: Option<Upstream>,
+
+	/// The maximum size in bytes of a published crate.
+	///
+	/// The same limit applies to the metadata that cargo sends along with the crate.
+	#[serde(default = "default_max_crate_size")]
+	pub max_crate_size: u64,
+
+	/// Serve Prometheus metrics on a separate listener.
+	pub metrics: Option<Metrics>,
+
+	/// The access log mode.
+	///
+	/// The access log is written to standard output.
+	#[serde(default)]
+	pub access_log: AccessLog,
+
+	/// The maximum time in seconds to wait for running requests when shutting down.
+	#[serde(default = "default_shutdown_timeout")]
+	pub shutdown_timeout: u64,
 }
 
 fn default_registry() -> PathBuf {
 	PathBuf::from(".")
 }
 
+fn default_max_crate_size() -> u64 {
+	10 * 1024 * 1024
+}
+
+fn default_shutdown_timeout() -> u64 {
+	30
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Upstream {
+	/// The URL of the sparse index of the upstream registry.
+	///
+	/// This may also be the path of a local directory with the same layout as a sparse index.
+	pub index: String,
+
+	/// The download URL template for crates of the upstream registry.
+	///
+	/// This uses the same format as the `dl` field in `config.json` of a registry index.
+	/// The expanded template may also be the path of a local file.
+	pub download: String,
+}
+
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AccessLog {
+	/// Do not write an access log.
+	#[default]
+	Off,
+
+	/// Write one JSON object per line for each request.
+	Json,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Metrics {
+	/// The bind address for the metrics listener.
+	///
+	/// The metrics listener only serves `/metrics` over plain HTTP.
+	pub bind: String,
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(deny_unknown_fields)]
 pub struct Listener {
@@ -23,11 +88,11 @@ pub struct Listener {
 	pub bind: String,
 
 	/// TLS options.
-	#[cfg(feature = "tls")]
+	#[cfg(any(feature = "tls", feature = "rustls"))]
 	pub tls: Option<Tls>,
 }
 
-#[cfg(feature = "tls")]
+#[cfg(any(feature = "tls", feature = "rustls"))]
 #[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(deny_unknown_fields)]
 pub struct Tls {
@@ -40,4 +105,78 @@ pub struct Tls {
 	/// followed by all parent certificates in order,
 	/// up to a certificate signed by a trusted root.
 	pub certificate_chain: PathBuf,
+
+	/// Additional certificates, selected by the server name that the client requests with SNI.
+	///
+	/// The certificate above is used if the client does not send a server name,
+	/// or if no certificate matches the requested server name.
+	#[serde(default, rename = "certificate")]
+	pub certificates: Vec<Certificate>,
+
+	/// Authenticate clients with client certificates.
+	pub client_auth: Option<ClientAuth>,
+}
+
+#[cfg(any(feature = "tls", feature = "rustls"))]
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Certificate {
+	/// The server names to use this certificate for.
+	///
+	/// A name may start with `*.` to match any single label in that position.
+	pub server_names: Vec<String>,
+
+	/// The path to the private key in PEM form.
+	pub private_key: PathBuf,
+
+	/// The path to the certificate chain in PEM form.
+	pub certificate_chain: PathBuf,
+}
+
+#[cfg(any(feature = "tls", feature = "rustls"))]
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ClientAuth {
+	/// The path to the CA certificates in PEM form.
+	///
+	/// Client certificates must chain to one of these certificates.
+	pub ca_certificates: PathBuf,
+
+	/// The paths to certificate revocation lists in PEM form.
+	///
+	/// If any are configured, a revocation list must be available for every CA in the chain of a client certificate.
+	#[serde(default)]
+	pub revocation_lists: Vec<PathBuf>,
+
+	/// Also accept connections without a client certificate.
+	///
+	/// Such connections can still authenticate with an API token.
+	#[serde(default)]
+	pub optional: bool,
+
+	/// The mapping from client certificates to registry users.
+	///
+	/// Client certificates that do not match any identity are accepted,
+	/// but the connection is not authenticated.
+	#[serde(default, rename = "identity")]
+	pub identities: Vec<ClientIdentity>,
+}
+
+#[cfg(any(feature = "tls", feature = "rustls"))]
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ClientIdentity {
+	/// The registry user to authenticate matching clients as.
+	pub user: String,
+
+	/// Match the full subject of the certificate, like `CN=ci,O=Example`.
+	///
+	/// The order of the attributes does not matter.
+	/// Special characters in values must be escaped as described in RFC 4514, like `O=Foo\, Inc`.
+	pub subject: Option<String>,
+
+	/// Match a subject alternative name of the certificate.
+	///
+	/// The name must have a `DNS:`, `email:`, `URI:` or `IP:` prefix.
+	pub subject_alt_name: Option<String>,
 }
@@ -3,14 +3,23 @@ use hyper::{header, Method, StatusCode};
 use std::path::Path;
 use tokio::process::Command;
 
+use crate::metrics::METRICS;
 use crate::server::{self, Body, HttpError, Request, Response};
+use crate::shutdown::Shutdown;
+
+/// Check if a path relative to the repository root is handled by the git smart HTTP transport.
+pub fn is_git_path(rel_path: &str) -> bool {
+	matches!(rel_path, "info/refs" | "git-upload-pack" | "git-receive-pack")
+}
 
 /// Handle requests for the git smart HTTP transport.
-pub async fn handle_request(repo_path: &Path, request: Request, rel_path: &str) -> Result<Response, HttpError> {
+///
+/// The shutdown handle is kept alive by background tasks, so a graceful shutdown waits for them.
+pub async fn handle_request(repo_path: &Path, shutdown: &Shutdown, request: Request, rel_path: &str) -> Result<Response, HttpError> {
 	if rel_path == "info/refs" {
 		handle_info(repo_path, request).await
 	} else if rel_path == "git-upload-pack" {
-		handle_upload_pack(repo_path, request).await
+		handle_upload_pack(repo_path, shutdown, request).await
 	} else if rel_path == "git-receive-pack" {
 		simple_response(StatusCode::FORBIDDEN, "This repository is read-only")
 	} else {
@@ -61,6 +70,7 @@ async fn handle_upload_pack_info(repo_path: &Path) -> Result<Response, HttpError
 			return internal_server_error("internal server error");
 		},
 	};
+	let _process = METRICS.track_git_upload_pack();
 
 	let output = match child.wait_with_output().await {
 		Ok(x) => x,
@@ -96,7 +106,7 @@ async fn handle_upload_pack_info(repo_path: &Path) -> Result<Response, HttpError
 /// Handle the request for 'git-upload-pack' service.
 ///
 /// This delegates to the system `git` command for the actual work.
-async fn handle_upload_pack(repo_path: &Path, mut request: Request) -> Result<Response, HttpError> {
+async fn handle_upload_pack(repo_path: &Path, shutdown: &Shutdown, mut request: Request) -> Result<Response, HttpError> {
 	use tokio::io::AsyncWriteExt;
 	use tokio::io::AsyncBufReadExt;
 
@@ -125,6 +135,7 @@ async fn handle_upload_pack(repo_path: &Path, mut request: Request) -> Result<Re
 			return internal_server_error("internal server error");
 		},
 	};
+	let process = METRICS.track_git_upload_pack();
 
 	let mut stdin = child.stdin.take().unwrap();
 	let stdout = child.stdout.take().unwrap();
@@ -152,6 +163,7 @@ async fn handle_upload_pack(repo_path: &Path, mut request: Request) -> Result<Re
 	drop(stdin);
 
 	// Monitor output and exit status in a background task.
+	let shutdown = shutdown.clone();
 	tokio::spawn(async move {
 		let mut stderr = tokio::io::BufReader::new(stderr).lines();
 		loop {
@@ -172,6 +184,8 @@ async fn handle_upload_pack(repo_path: &Path, mut request: Request) -> Result<Re
 			},
 			Err(e) => log::error!("Failed to wait for git-upload-pack --stateless-rpc: {}", e),
 		}
+		drop(process);
+		drop(shutdown);
 	});
 
 	// Forward the stdout to the response body.
@@ -0,0 +1,405 @@
+use palletizer::Registry;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::sync::{mpsc, oneshot, watch};
+
+use crate::config;
+use crate::metrics;
+use crate::server::{self, State};
+use crate::shutdown::Shutdown;
+use crate::upstream::Upstream;
+
+#[cfg(any(feature = "tls", feature = "rustls"))]
+use crate::tls;
+
+/// The running listeners of the server.
+///
+/// The listeners can be updated to match a new configuration without interrupting existing connections.
+pub struct Listeners {
+	/// The directory of the configuration file.
+	///
+	/// Relative paths in the configuration are resolved relative to this directory.
+	config_dir: PathBuf,
+
+	/// The state used for new connections.
+	state: watch::Sender<Arc<State>>,
+
+	/// The running listeners by bind address.
+	///
+	/// Dropping the sender stops the listener.
+	listeners: BTreeMap<String, mpsc::UnboundedSender<ListenerUpdate>>,
+
+	/// The bind address of the metrics listener and the sender to stop it.
+	metrics: Option<(String, oneshot::Sender<()>)>,
+
+	/// The maximum time to wait for running requests when shutting down.
+	shutdown_timeout: Duration,
+
+	/// Handle to watch for a graceful shutdown, used for every new state.
+	shutdown: Shutdown,
+
+	/// Sender used by listeners to report a fatal error.
+	failed: mpsc::UnboundedSender<()>,
+}
+
+/// New settings for a running listener.
+struct ListenerUpdate {
+	#[cfg(any(feature = "tls", feature = "rustls"))]
+	tls_acceptor: Option<tls::TlsAcceptor>,
+}
+
+impl ListenerUpdate {
+	fn from_config(config: &config::Listener, config_dir: &Path) -> Result<Self, ()> {
+		#[cfg(not(any(feature = "tls", feature = "rustls")))]
+		let _ = (config, config_dir);
+		Ok(Self {
+			#[cfg(any(feature = "tls", feature = "rustls"))]
+			tls_acceptor: match &config.tls {
+				None => None,
+				Some(tls) => Some(tls::TlsAcceptor::from_config(tls, config_dir)?),
+			},
+		})
+	}
+}
+
+impl Listeners {
+	/// Start all listeners from a configuration.
+	///
+	/// Listeners report fatal errors by sending a message on the `failed` channel.
+	pub async fn start(config_dir: &Path, config: config::Config, shutdown: Shutdown, failed: mpsc::UnboundedSender<()>) -> Result<Self, ()> {
+		let state = Arc::new(create_state(config_dir, &config, shutdown.clone(), None)?);
+		let mut listeners = Self {
+			config_dir: config_dir.to_path_buf(),
+			state: watch::Sender::new(state.clone()),
+			listeners: BTreeMap::new(),
+			metrics: None,
+			shutdown_timeout: Duration::from_secs(config.shutdown_timeout),
+			shutdown,
+			failed,
+		};
+		listeners.apply(config, state).await?;
+		Ok(listeners)
+	}
+
+	/// Get the maximum time to wait for running requests when shutting down.
+	pub fn shutdown_timeout(&self) -> Duration {
+		self.shutdown_timeout
+	}
+
+	/// Reload the configuration file and update the listeners to match.
+	///
+	/// If the new configuration is invalid, the old configuration is kept.
+	pub async fn reload(&mut self, config_path: &Path) {
+		log::info!("Reloading configuration from {}", config_path.display());
+		let current = self.state.borrow().clone();
+		let result = match crate::load_config(config_path) {
+			Ok(config) => match create_state(&self.config_dir, &config, self.shutdown.clone(), Some(&current)) {
+				Ok(state) => self.apply(config, Arc::new(state)).await,
+				Err(()) => Err(()),
+			},
+			Err(()) => Err(()),
+		};
+		match result {
+			Ok(()) => log::info!("Configuration reloaded"),
+			Err(()) => log::error!("Failed to reload configuration, keeping the old configuration"),
+		}
+	}
+
+	/// Update the listeners and the request handling state to match a new configuration.
+	///
+	/// Everything that can fail is done before anything is changed,
+	/// so on error the old configuration remains in effect.
+	async fn apply(&mut self, config: config::Config, state: Arc<State>) -> Result<(), ()> {
+		// Reloading the TLS acceptors also re-reads the keys and certificates.
+		let mut updates = BTreeMap::new();
+		let mut sockets = BTreeMap::new();
+		for listener in &config.listeners {
+			if updates.contains_key(&listener.bind) {
+				log::error!("Duplicate listener for {}", listener.bind);
+				return Err(());
+			}
+			updates.insert(listener.bind.clone(), ListenerUpdate::from_config(listener, &self.config_dir)?);
+			if !self.listeners.contains_key(&listener.bind) {
+				sockets.insert(listener.bind.clone(), bind(&listener.bind).await?);
+			}
+		}
+
+		let metrics_socket = match (&config.metrics, &self.metrics) {
+			(Some(new), Some((old, _))) if new.bind == *old => None,
+			(Some(new), _) => Some((new.bind.clone(), bind(&new.bind).await?)),
+			(None, _) => None,
+		};
+
+		// From here on, nothing can fail anymore.
+		self.state.send_replace(state);
+		self.shutdown_timeout = Duration::from_secs(config.shutdown_timeout);
+
+		self.listeners.retain(|bind, _| {
+			let keep = updates.contains_key(bind);
+			if !keep {
+				log::info!("Stopping listener on {}", bind);
+			}
+			keep
+		});
+
+		for (bind, update) in updates {
+			match sockets.remove(&bind) {
+				Some(socket) => {
+					let (update_tx, update_rx) = mpsc::unbounded_channel();
+					let state = self.state.subscribe();
+					let failed = self.failed.clone();
+					self.listeners.insert(bind.clone(), update_tx);
+					tokio::spawn(async move {
+						if run_listener(bind, socket, state, update, update_rx).await.is_err() {
+							failed.send(()).ok();
+						}
+					});
+				},
+				None => {
+					// Ignore errors: if the listener failed, the server is shutting down anyway.
+					self.listeners[&bind].send(update).ok();
+				},
+			}
+		}
+
+		if config.metrics.is_none() && let Some((bind, _)) = self.metrics.take() {
+			log::info!("Stopping metrics listener on {}", bind);
+		}
+		if let Some((bind, socket)) = metrics_socket {
+			if let Some((old_bind, _)) = self.metrics.take() {
+				log::info!("Stopping metrics listener on {}", old_bind);
+			}
+			let (stop_tx, stop_rx) = oneshot::channel();
+			let failed = self.failed.clone();
+			self.metrics = Some((bind.clone(), stop_tx));
+			tokio::spawn(async move {
+				if run_metrics_listener(bind, socket, stop_rx).await.is_err() {
+					failed.send(()).ok();
+				}
+			});
+		}
+
+		Ok(())
+	}
+}
+
+/// Create the state for handling requests from a configuration.
+///
+/// If the registry path did not change, the registry of the current state is shared with the new state.
+/// Requests that are still running with the old state then use the same lock as new requests.
+fn create_state(config_dir: &Path, config: &config::Config, shutdown: Shutdown, current: Option<&State>) -> Result<State, ()> {
+	let registry_path = config_dir.join(&config.registry);
+	let registry = match current {
+		Some(current) if current.registry.read().unwrap().path() == registry_path => current.registry.clone(),
+		_ => {
+			let registry = Registry::open(&registry_path)
+				.map_err(|e| log::error!("{}", e))?;
+			// Settings like `auth_required` must also be in `config.json` for Cargo.
+			match registry.update_cargo_config() {
+				Ok(true) => log::info!("Updated config.json in {} to match the registry configuration", registry.index_dir().display()),
+				Ok(false) => (),
+				Err(e) => log::error!("Failed to update config.json of the registry index: {}", e),
+			}
+			Arc::new(RwLock::new(registry))
+		},
+	};
+	let index_repo_path = registry.read().unwrap().index_dir();
+	let upstream = config.upstream.as_ref()
+		.map(|upstream| Arc::new(Upstream::from_config(upstream, config_dir)));
+	Ok(State {
+		registry,
+		upstream,
+		index_repo_path,
+		max_crate_size: config.max_crate_size,
+		access_log: config.access_log,
+		shutdown,
+	})
+}
+
+async fn bind(address: &str) -> Result<TcpListener, ()> {
+	TcpListener::bind(address)
+		.await
+		.map_err(|e| log::error!("Failed to listen on {}: {}", address, e))
+}
+
+async fn run_listener(
+	bind: String,
+	listener: TcpListener,
+	state: watch::Receiver<Arc<State>>,
+	settings: ListenerUpdate,
+	mut updates: mpsc::UnboundedReceiver<ListenerUpdate>,
+) -> Result<(), ()> {
+	log::info!("Server listening on {}", bind);
+
+	#[cfg(any(feature = "tls", feature = "rustls"))]
+	let mut tls_acceptor = settings.tls_acceptor;
+	#[cfg(not(any(feature = "tls", feature = "rustls")))]
+	let _ = settings;
+
+	loop {
+		let (connection, address) = tokio::select! {
+			accepted = listener.accept() => {
+				accepted.map_err(|e| log::error!("Failed to accept connection on {}: {}", bind, e))?
+			},
+			update = updates.recv() => match update {
+				Some(update) => {
+					#[cfg(any(feature = "tls", feature = "rustls"))]
+					{
+						tls_acceptor = update.tls_acceptor;
+					}
+					#[cfg(not(any(feature = "tls", feature = "rustls")))]
+					let _ = update;
+					continue;
+				},
+				None => return Ok(()),
+			},
+		};
+		log::debug!("Accepted connection from {}", address);
+
+		// New connections use the latest state, existing connections keep their state.
+		let state = state.borrow().clone();
+
+		#[cfg(any(feature = "tls", feature = "rustls"))]
+		if let Some(tls_acceptor) = &mut tls_acceptor {
+			// Errors are already logged, and a failed handshake should not stop the listener.
+			let Ok(connection) = tls_acceptor.accept(connection).await else {
+				continue;
+			};
+			let client_identity = tls_acceptor.client_identity(&connection).map(Arc::from);
+			tokio::spawn(serve_connection(connection, server::Connection { address, tls: true, client_identity }, state));
+			continue;
+		}
+
+		tokio::spawn(serve_connection(connection, server::Connection { address, tls: false, client_identity: None }, state));
+	}
+}
+
+async fn run_metrics_listener(bind: String, listener: TcpListener, mut stop: oneshot::Receiver<()>) -> Result<(), ()> {
+	log::info!("Metrics server listening on {}", bind);
+
+	loop {
+		let (connection, address) = tokio::select! {
+			accepted = listener.accept() => {
+				accepted.map_err(|e| log::error!("Failed to accept connection on {}: {}", bind, e))?
+			},
+			_ = &mut stop => return Ok(()),
+		};
+		log::debug!("Accepted metrics connection from {}", address);
+
+		tokio::spawn(async move {
+			let service = hyper::service::service_fn(metrics::handle_request);
+			let connection = hyper_util::rt::TokioIo::new(connection);
+			let result = hyper_util::server::conn::auto::Builder::new(hyper_util::rt::TokioExecutor::new())
+				.serve_connection(connection, service)
+				.await;
+			if let Err(e) = result {
+				log::error!("Error in connection with {address}: {e}");
+			}
+		});
+	}
+}
+
+async fn serve_connection<S>(connection: S, info: server::Connection, state: Arc<State>)
+where
+	S: tokio::io::AsyncRead + tokio::io::AsyncWrite + std::marker::Unpin + 'static,
+{
+	let mut shutdown = state.shutdown.clone();
+	let address = info.address;
+	let service = hyper::service::service_fn(move |request| {
+		server::handle_request(state.clone(), info.clone(), request)
+	});
+	let connection = hyper_util::rt::TokioIo::new(connection);
+	let mut builder = hyper_util::server::conn::auto::Builder::new(hyper_util::rt::TokioExecutor::new());
+	builder.http1().keep_alive(true);
+	let mut connection = std::pin::pin!(builder.serve_connection(connection, service));
+
+	// On shutdown, finish the running requests but do not accept new requests on the connection.
+	let result = tokio::select! {
+		result = connection.as_mut() => result,
+		() = shutdown.requested() => {
+			connection.as_mut().graceful_shutdown();
+			connection.await
+		},
+	};
+	if let Err(e) = result {
+		log::error!("Error in connection with {}: {}", address, e);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{shutdown, test_util};
+
+	fn server_config(registry: &Path, listeners: &[&str], extra: &str) -> config::Config {
+		let mut config = format!("registry = {:?}\n{}\n", registry, extra);
+		for bind in listeners {
+			config += &format!("[[listener]]\nbind = {:?}\n", bind);
+		}
+		toml::from_str(&config).unwrap()
+	}
+
+	async fn start(registry: &Path) -> (Listeners, shutdown::Trigger) {
+		let (trigger, shutdown) = shutdown::channel();
+		let (failed, _) = mpsc::unbounded_channel();
+		let listeners = Listeners::start(registry, server_config(registry, &["127.0.0.1:0"], ""), shutdown, failed).await.unwrap();
+		(listeners, trigger)
+	}
+
+	/// Apply a configuration that should fail, and check that nothing changed.
+	async fn apply_invalid(listeners: &mut Listeners, config: config::Config) {
+		let old_state = listeners.state.borrow().clone();
+		let new_state = Arc::new(create_state(&listeners.config_dir, &config, listeners.shutdown.clone(), Some(&old_state)).unwrap());
+		assert!(listeners.apply(config, new_state).await.is_err());
+		assert!(Arc::ptr_eq(&listeners.state.borrow(), &old_state));
+		assert_eq!(listeners.listeners.keys().collect::<Vec<_>>(), ["127.0.0.1:0"]);
+		assert!(!listeners.listeners["127.0.0.1:0"].is_closed());
+		assert!(listeners.metrics.is_none());
+		assert_eq!(listeners.shutdown_timeout, Duration::from_secs(30));
+	}
+
+	#[tokio::test]
+	async fn apply_duplicate_bind_keeps_old_config() {
+		let dir = tempfile::tempdir().unwrap();
+		test_util::init_registry(dir.path());
+		let (mut listeners, _trigger) = start(dir.path()).await;
+
+		let config = server_config(dir.path(), &["127.0.0.1:0", "127.0.0.1:0"], "shutdown_timeout = 5");
+		apply_invalid(&mut listeners, config).await;
+	}
+
+	#[tokio::test]
+	async fn apply_bind_failure_keeps_old_config() {
+		let dir = tempfile::tempdir().unwrap();
+		test_util::init_registry(dir.path());
+		let (mut listeners, _trigger) = start(dir.path()).await;
+
+		let in_use = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+		let in_use = in_use.local_addr().unwrap().to_string();
+		let config = server_config(dir.path(), &["127.0.0.1:0", &in_use], "shutdown_timeout = 5");
+		apply_invalid(&mut listeners, config).await;
+
+		// A metrics listener that can not bind must not change anything either.
+		let metrics = format!("shutdown_timeout = 5\n[metrics]\nbind = {:?}", in_use);
+		let config = server_config(dir.path(), &["127.0.0.1:0"], &metrics);
+		apply_invalid(&mut listeners, config).await;
+	}
+
+	#[cfg(any(feature = "tls", feature = "rustls"))]
+	#[tokio::test]
+	async fn apply_bad_tls_path_keeps_old_config() {
+		let dir = tempfile::tempdir().unwrap();
+		test_util::init_registry(dir.path());
+		let (mut listeners, _trigger) = start(dir.path()).await;
+
+		let mut config = server_config(dir.path(), &["127.0.0.1:0"], "shutdown_timeout = 5");
+		config.listeners[0].tls = Some(toml::from_str(r#"
+			private_key = "missing.key"
+			certificate_chain = "missing.pem"
+		"#).unwrap());
+		apply_invalid(&mut listeners, config).await;
+	}
+}
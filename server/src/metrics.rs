@@ -0,0 +1,375 @@
+use hyper::{header, Method, StatusCode};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use crate::server::{self, HttpError, Request, Response};
+
+/// The global metrics of the server.
+pub static METRICS: Metrics = Metrics::new();
+
+/// The upper bounds of the latency histogram buckets in seconds.
+const LATENCY_BUCKETS: [f64; 11] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// The routes that metrics are collected for.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd)]
+pub enum Route {
+	CrateDownload,
+	SparseIndex,
+	GitInfoRefs,
+	GitUploadPack,
+	ApiPublish,
+	ApiYank,
+	ApiUnyank,
+	ApiOwners,
+	ApiSearch,
+	Other,
+}
+
+impl Route {
+	/// Determine the route for a request path.
+	pub fn from_path(path: &str) -> Self {
+		if path.starts_with("/crates/") {
+			Self::CrateDownload
+		} else if let Some(api_path) = path.strip_prefix("/api/v1/") {
+			Self::from_api_path(api_path)
+		} else if let Some(git_path) = path.strip_prefix("/index.git/").or_else(|| path.strip_prefix("/index/")) {
+			match git_path {
+				"info/refs" => Self::GitInfoRefs,
+				"git-upload-pack" => Self::GitUploadPack,
+				"git-receive-pack" => Self::Other,
+				_ if path.starts_with("/index/") => Self::SparseIndex,
+				_ => Self::Other,
+			}
+		} else {
+			Self::Other
+		}
+	}
+
+	fn from_api_path(api_path: &str) -> Self {
+		if api_path == "crates" {
+			return Self::ApiSearch;
+		}
+		match api_path.strip_prefix("crates/").map(|x| x.split('/').collect::<Vec<_>>()).as_deref() {
+			Some(["new"]) => Self::ApiPublish,
+			Some([_, "owners"]) => Self::ApiOwners,
+			Some([_, _, "yank"]) => Self::ApiYank,
+			Some([_, _, "unyank"]) => Self::ApiUnyank,
+			_ => Self::Other,
+		}
+	}
+
+	fn as_str(&self) -> &'static str {
+		match self {
+			Self::CrateDownload => "crate_download",
+			Self::SparseIndex => "sparse_index",
+			Self::GitInfoRefs => "git_info_refs",
+			Self::GitUploadPack => "git_upload_pack",
+			Self::ApiPublish => "api_v1_publish",
+			Self::ApiYank => "api_v1_yank",
+			Self::ApiUnyank => "api_v1_unyank",
+			Self::ApiOwners => "api_v1_owners",
+			Self::ApiSearch => "api_v1_search",
+			Self::Other => "other",
+		}
+	}
+}
+
+/// Registry operations that are counted.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd)]
+pub enum Operation {
+	Publish,
+	Yank,
+	Unyank,
+}
+
+impl Operation {
+	fn as_str(&self) -> &'static str {
+		match self {
+			Self::Publish => "publish",
+			Self::Yank => "yank",
+			Self::Unyank => "unyank",
+		}
+	}
+}
+
+/// Metrics collected by the server.
+///
+/// All counters start at zero when the server starts.
+pub struct Metrics {
+	routes: Mutex<BTreeMap<Route, RouteStats>>,
+	operations: Mutex<BTreeMap<(Operation, bool), u64>>,
+	git_upload_pack_processes: AtomicU64,
+	tls_handshake_failures: AtomicU64,
+}
+
+#[derive(Default)]
+struct RouteStats {
+	/// The number of responses per status code.
+	responses: BTreeMap<u16, u64>,
+
+	/// The number of requests per latency bucket.
+	///
+	/// The last element counts requests slower than the largest bucket.
+	latency_buckets: [u64; LATENCY_BUCKETS.len() + 1],
+
+	/// The total latency of all requests in seconds.
+	latency_sum: f64,
+
+	/// The number of response body bytes sent.
+	bytes_sent: u64,
+}
+
+impl Metrics {
+	const fn new() -> Self {
+		Self {
+			routes: Mutex::new(BTreeMap::new()),
+			operations: Mutex::new(BTreeMap::new()),
+			git_upload_pack_processes: AtomicU64::new(0),
+			tls_handshake_failures: AtomicU64::new(0),
+		}
+	}
+
+	/// Record a handled request.
+	pub fn record_request(&self, route: Route, status: StatusCode, latency: Duration) {
+		let latency = latency.as_secs_f64();
+		let mut routes = self.routes.lock().unwrap();
+		let stats = routes.entry(route).or_default();
+		*stats.responses.entry(status.as_u16()).or_default() += 1;
+		let bucket = LATENCY_BUCKETS.iter()
+			.position(|&bound| latency <= bound)
+			.unwrap_or(LATENCY_BUCKETS.len());
+		stats.latency_buckets[bucket] += 1;
+		stats.latency_sum += latency;
+	}
+
+	/// Record response body bytes sent for a route.
+	pub fn record_bytes_sent(&self, route: Route, bytes: u64) {
+		self.routes.lock().unwrap().entry(route).or_default().bytes_sent += bytes;
+	}
+
+	/// Start tracking a registry operation.
+	///
+	/// The operation is recorded as failed when the returned guard is dropped,
+	/// unless [`OperationGuard::succeeded()`] is called.
+	pub fn start_operation(&'static self, operation: Operation) -> OperationGuard {
+		OperationGuard {
+			metrics: self,
+			operation,
+			success: false,
+		}
+	}
+
+	/// Record the outcome of a registry operation.
+	fn record_operation(&self, operation: Operation, success: bool) {
+		*self.operations.lock().unwrap().entry((operation, success)).or_default() += 1;
+	}
+
+	/// Record a failed TLS handshake.
+	#[cfg(any(feature = "tls", feature = "rustls"))]
+	pub fn record_tls_handshake_failure(&self) {
+		self.tls_handshake_failures.fetch_add(1, Ordering::Relaxed);
+	}
+
+	/// Track a running `git-upload-pack` process.
+	///
+	/// The process is counted until the returned guard is dropped.
+	pub fn track_git_upload_pack(&'static self) -> ProcessGuard {
+		self.git_upload_pack_processes.fetch_add(1, Ordering::Relaxed);
+		ProcessGuard { counter: &self.git_upload_pack_processes }
+	}
+
+	/// Render the metrics in the Prometheus text format.
+	pub fn render(&self) -> String {
+		let mut output = String::new();
+		let routes = self.routes.lock().unwrap();
+
+		output += "# HELP palletizer_http_requests_total Number of HTTP requests handled, by route and status code.\n";
+		output += "# TYPE palletizer_http_requests_total counter\n";
+		for (route, stats) in routes.iter() {
+			for (status, count) in &stats.responses {
+				writeln!(output, "palletizer_http_requests_total{{route=\"{}\",status=\"{}\"}} {}", route.as_str(), status, count).unwrap();
+			}
+		}
+
+		output += "# HELP palletizer_http_request_duration_seconds Time taken to produce the response headers, by route.\n";
+		output += "# TYPE palletizer_http_request_duration_seconds histogram\n";
+		for (route, stats) in routes.iter() {
+			let mut cumulative = 0;
+			for (bound, count) in LATENCY_BUCKETS.iter().zip(&stats.latency_buckets) {
+				cumulative += count;
+				writeln!(output, "palletizer_http_request_duration_seconds_bucket{{route=\"{}\",le=\"{}\"}} {}", route.as_str(), bound, cumulative).unwrap();
+			}
+			cumulative += stats.latency_buckets[LATENCY_BUCKETS.len()];
+			writeln!(output, "palletizer_http_request_duration_seconds_bucket{{route=\"{}\",le=\"+Inf\"}} {}", route.as_str(), cumulative).unwrap();
+			writeln!(output, "palletizer_http_request_duration_seconds_sum{{route=\"{}\"}} {}", route.as_str(), stats.latency_sum).unwrap();
+			writeln!(output, "palletizer_http_request_duration_seconds_count{{route=\"{}\"}} {}", route.as_str(), cumulative).unwrap();
+		}
+
+		output += "# HELP palletizer_http_response_bytes_total Number of response body bytes sent, by route.\n";
+		output += "# TYPE palletizer_http_response_bytes_total counter\n";
+		for (route, stats) in routes.iter() {
+			writeln!(output, "palletizer_http_response_bytes_total{{route=\"{}\"}} {}", route.as_str(), stats.bytes_sent).unwrap();
+		}
+		drop(routes);
+
+		output += "# HELP palletizer_operations_total Number of registry operations, by operation and result.\n";
+		output += "# TYPE palletizer_operations_total counter\n";
+		for ((operation, success), count) in self.operations.lock().unwrap().iter() {
+			let result = if *success { "success" } else { "failure" };
+			writeln!(output, "palletizer_operations_total{{operation=\"{}\",result=\"{}\"}} {}", operation.as_str(), result, count).unwrap();
+		}
+
+		output += "# HELP palletizer_git_upload_pack_processes Number of running git-upload-pack processes.\n";
+		output += "# TYPE palletizer_git_upload_pack_processes gauge\n";
+		writeln!(output, "palletizer_git_upload_pack_processes {}", self.git_upload_pack_processes.load(Ordering::Relaxed)).unwrap();
+
+		output += "# HELP palletizer_tls_handshake_failures_total Number of failed TLS handshakes.\n";
+		output += "# TYPE palletizer_tls_handshake_failures_total counter\n";
+		writeln!(output, "palletizer_tls_handshake_failures_total {}", self.tls_handshake_failures.load(Ordering::Relaxed)).unwrap();
+
+		output
+	}
+}
+
+/// Guard that records the outcome of a registry operation when dropped.
+pub struct OperationGuard {
+	metrics: &'static Metrics,
+	operation: Operation,
+	success: bool,
+}
+
+impl OperationGuard {
+	/// Mark the operation as successful.
+	pub fn succeeded(mut self) {
+		self.success = true;
+	}
+}
+
+impl Drop for OperationGuard {
+	fn drop(&mut self) {
+		self.metrics.record_operation(self.operation, self.success);
+	}
+}
+
+/// Guard that keeps a process counted while it is alive.
+pub struct ProcessGuard {
+	counter: &'static AtomicU64,
+}
+
+impl Drop for ProcessGuard {
+	fn drop(&mut self) {
+		self.counter.fetch_sub(1, Ordering::Relaxed);
+	}
+}
+
+/// Handle a request on the metrics listener.
+pub async fn handle_request(request: Request) -> Result<Response, HttpError> {
+	if request.uri().path() != "/metrics" {
+		return server::not_found();
+	}
+	if let Some(response) = server::check_supported_method(request.method(), &[Method::GET, Method::HEAD]) {
+		return response;
+	}
+	server::response_no_cache()
+		.header(header::CONTENT_TYPE, "text/plain; version=0.0.4; charset=utf-8")
+		.body(METRICS.render().into())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Get the value of a sample from rendered metrics.
+	fn sample(output: &str, name: &str) -> f64 {
+		output.lines()
+			.find_map(|line| line.strip_prefix(name)?.strip_prefix(' '))
+			.unwrap_or_else(|| panic!("missing sample {}", name))
+			.parse()
+			.unwrap()
+	}
+
+	#[test]
+	fn route_from_path() {
+		assert_eq!(Route::from_path("/crates/serde/serde-1.0.0.crate"), Route::CrateDownload);
+
+		assert_eq!(Route::from_path("/index/config.json"), Route::SparseIndex);
+		assert_eq!(Route::from_path("/index/se/rd/serde"), Route::SparseIndex);
+		assert_eq!(Route::from_path("/index/3/f/foo"), Route::SparseIndex);
+		assert_eq!(Route::from_path("/index/info/refs"), Route::GitInfoRefs);
+		assert_eq!(Route::from_path("/index/git-upload-pack"), Route::GitUploadPack);
+		assert_eq!(Route::from_path("/index/git-receive-pack"), Route::Other);
+		assert_eq!(Route::from_path("/index.git/info/refs"), Route::GitInfoRefs);
+		assert_eq!(Route::from_path("/index.git/git-upload-pack"), Route::GitUploadPack);
+		assert_eq!(Route::from_path("/index.git/se/rd/serde"), Route::Other);
+		assert_eq!(Route::from_path("/index"), Route::Other);
+
+		assert_eq!(Route::from_path("/api/v1/crates"), Route::ApiSearch);
+		assert_eq!(Route::from_path("/api/v1/crates/new"), Route::ApiPublish);
+		assert_eq!(Route::from_path("/api/v1/crates/serde/owners"), Route::ApiOwners);
+		assert_eq!(Route::from_path("/api/v1/crates/serde/1.0.0/yank"), Route::ApiYank);
+		assert_eq!(Route::from_path("/api/v1/crates/serde/1.0.0/unyank"), Route::ApiUnyank);
+		assert_eq!(Route::from_path("/api/v1/crates/serde"), Route::Other);
+		assert_eq!(Route::from_path("/api/v1/crates/serde/1.0.0/download"), Route::Other);
+		assert_eq!(Route::from_path("/api/v2/crates"), Route::Other);
+
+		assert_eq!(Route::from_path("/"), Route::Other);
+		assert_eq!(Route::from_path("/metrics"), Route::Other);
+	}
+
+	#[test]
+	fn latency_histogram() {
+		let metrics = Metrics::new();
+		let latencies = [0.001, 0.005, 0.007, 0.3, 0.3, 4.0, 60.0];
+		for latency in latencies {
+			metrics.record_request(Route::ApiSearch, StatusCode::OK, Duration::from_secs_f64(latency));
+		}
+		metrics.record_request(Route::CrateDownload, StatusCode::NOT_FOUND, Duration::from_millis(20));
+		let output = metrics.render();
+
+		let bucket = |le: &str| sample(&output, &format!("palletizer_http_request_duration_seconds_bucket{{route=\"api_v1_search\",le=\"{}\"}}", le));
+		assert_eq!(bucket("0.005"), 2.0);
+		assert_eq!(bucket("0.01"), 3.0);
+		assert_eq!(bucket("0.25"), 3.0);
+		assert_eq!(bucket("0.5"), 5.0);
+		assert_eq!(bucket("5"), 6.0);
+		assert_eq!(bucket("10"), 6.0);
+		assert_eq!(bucket("+Inf"), 7.0);
+
+		// Buckets are cumulative.
+		let mut previous = 0.0;
+		for bound in LATENCY_BUCKETS {
+			let count = bucket(&bound.to_string());
+			assert!(count >= previous);
+			previous = count;
+		}
+		assert!(bucket("+Inf") >= previous);
+
+		assert_eq!(sample(&output, "palletizer_http_request_duration_seconds_count{route=\"api_v1_search\"}"), bucket("+Inf"));
+		let sum = sample(&output, "palletizer_http_request_duration_seconds_sum{route=\"api_v1_search\"}");
+		assert!((sum - latencies.iter().sum::<f64>()).abs() < 1e-6);
+		assert_eq!(sample(&output, "palletizer_http_requests_total{route=\"api_v1_search\",status=\"200\"}"), 7.0);
+
+		// Other routes have their own histogram.
+		assert_eq!(sample(&output, "palletizer_http_request_duration_seconds_bucket{route=\"crate_download\",le=\"0.01\"}"), 0.0);
+		assert_eq!(sample(&output, "palletizer_http_request_duration_seconds_bucket{route=\"crate_download\",le=\"0.025\"}"), 1.0);
+		assert_eq!(sample(&output, "palletizer_http_request_duration_seconds_count{route=\"crate_download\"}"), 1.0);
+		assert_eq!(sample(&output, "palletizer_http_requests_total{route=\"crate_download\",status=\"404\"}"), 1.0);
+	}
+
+	#[test]
+	fn operations() {
+		let metrics: &'static Metrics = Box::leak(Box::new(Metrics::new()));
+		metrics.start_operation(Operation::Publish).succeeded();
+		drop(metrics.start_operation(Operation::Publish));
+		metrics.start_operation(Operation::Yank).succeeded();
+		let output = metrics.render();
+
+		assert_eq!(sample(&output, "palletizer_operations_total{operation=\"publish\",result=\"success\"}"), 1.0);
+		assert_eq!(sample(&output, "palletizer_operations_total{operation=\"publish\",result=\"failure\"}"), 1.0);
+		assert_eq!(sample(&output, "palletizer_operations_total{operation=\"yank\",result=\"success\"}"), 1.0);
+		assert!(!output.contains("operation=\"unyank\""));
+	}
+}
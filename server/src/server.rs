@@ -2,58 +2,164 @@ use futures::{Stream, TryStreamExt as _};
 use http_body_util::BodyExt as _;
 use hyper::body::{Bytes, Incoming};
 use palletizer::Registry;
+use palletizer::storage::Storage;
 use std::pin::Pin;
 use std::sync::{Arc, RwLock};
 use std::path::PathBuf;
 use std::task::ready;
+use std::time::Instant;
+use std::net::SocketAddr;
+use hyper::header::HeaderValue;
 use hyper::{header, StatusCode, Method};
-use crate::{api_v1, git};
+use crate::{access_log, api_v1, git, sparse};
+use crate::access_log::Identity;
+use crate::config::AccessLog;
+use crate::metrics::{Route, METRICS};
+use crate::shutdown::Shutdown;
+use crate::upstream::Upstream;
 
 pub use hyper::http::Error as HttpError;
 pub type Request = hyper::Request<Incoming>;
 pub type Response = hyper::Response<Body>;
 
-pub async fn handle_request(registry: Arc<RwLock<Registry>>, index_repo_path: PathBuf, request: Request) -> Result<Response, HttpError> {
-	log::debug!("Got {} request for {}", request.method(), request.uri());
+/// Shared state used to handle requests.
+pub struct State {
+	/// The registry to serve.
+	pub registry: Arc<RwLock<Registry>>,
+
+	/// The upstream registry to forward requests for unknown crates to.
+	pub upstream: Option<Arc<Upstream>>,
+
+	/// The path of the index repository.
+	pub index_repo_path: PathBuf,
+
+	/// The maximum size in bytes of a published crate.
+	pub max_crate_size: u64,
+
+	/// The access log mode.
+	pub access_log: AccessLog,
+
+	/// Handle to watch for a graceful shutdown.
+	///
+	/// A graceful shutdown waits until the state is dropped.
+	pub shutdown: Shutdown,
+}
+
+/// Information about the connection a request was received on.
+///
+/// This is also stored in the request extensions.
+#[derive(Debug, Clone)]
+pub struct Connection {
+	/// The address of the remote peer.
+	pub address: SocketAddr,
+
+	/// Whether the connection uses TLS.
+	pub tls: bool,
+
+	/// The registry user that the TLS client certificate maps to.
+	pub client_identity: Option<Arc<str>>,
+}
+
+pub async fn handle_request(state: Arc<State>, connection: Connection, mut request: Request) -> Result<Response, HttpError> {
+	let start = Instant::now();
+	let request_id = access_log::next_request_id();
+	let identity = Identity::default();
+	request.extensions_mut().insert(identity.clone());
+	request.extensions_mut().insert(connection.clone());
+
 	let path = request.uri().path().replace("//", "/");
+	let route = Route::from_path(&path);
+	let entry = match state.access_log {
+		AccessLog::Off => None,
+		AccessLog::Json => Some(access_log::Entry::new(request_id.clone(), connection, &request, identity, start)),
+	};
+
+	log::debug!("[{}] Got {} request for {}", request_id, request.method(), request.uri());
+	let response = route_request(&state, request, &path).await;
+	let status = match &response {
+		Ok(x) => x.status(),
+		Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
+	};
+	METRICS.record_request(route, status, start.elapsed());
+
+	// The access log entry is written when the response body is dropped.
+	let entry = entry.map(|x| x.with_status(status));
+	let mut response = response?;
+	if let Ok(value) = HeaderValue::from_str(&request_id) {
+		response.headers_mut().insert("x-request-id", value);
+	}
+	Ok(response.map(|body| body.inspect_data(move |bytes| {
+		METRICS.record_bytes_sent(route, bytes);
+		if let Some(entry) = &entry {
+			entry.add_bytes_sent(bytes);
+		}
+	})))
+}
+
+async fn route_request(state: &State, request: Request, path: &str) -> Result<Response, HttpError> {
+	let registry = state.registry.clone();
+	let upstream = state.upstream.clone();
+	let index_repo_path = &state.index_repo_path;
+
+	// All read-only routes require authentication if the registry requires it.
+	// The API endpoints do their own authentication.
+	let is_read_route = path.starts_with("/crates/") || path.starts_with("/index/") || path.starts_with("/index.git/");
+	if is_read_route && let Some(response) = check_read_access(&registry, &request) {
+		return response;
+	}
+
 	if let Some(path) = path.strip_prefix("/crates/") {
-		get_crate(registry, path, request.method())
+		get_crate(registry, upstream, path, request.method()).await
 	} else if let Some(api_path) = path.strip_prefix("/api/v1/") {
-		api_v1::handle_request(registry, request, api_path).await
+		api_v1::handle_request(registry, request, api_path, state.max_crate_size).await
 	} else if let Some(path) = path.strip_prefix("/index.git/") {
-		git::handle_request(&index_repo_path, request, path).await
+		git::handle_request(index_repo_path, &state.shutdown, request, path).await
 	} else if let Some(path) = path.strip_prefix("/index/") {
-		git::handle_request(&index_repo_path, request, path).await
+		if git::is_git_path(path) {
+			git::handle_request(index_repo_path, &state.shutdown, request, path).await
+		} else {
+			sparse::handle_request(registry, upstream, &request, path).await
+		}
 	} else {
 		not_found()
 	}
 }
 
-fn get_crate(registry: Arc<RwLock<Registry>>, path: &str, method: &Method) -> Result<Response, HttpError> {
+async fn get_crate(registry: Arc<RwLock<Registry>>, upstream: Option<Arc<Upstream>>, path: &str, method: &Method) -> Result<Response, HttpError> {
 	if let Some(response) = check_supported_method(method, &[Method::GET, Method::HEAD]) {
 		log::warn!("Unsupported request method for crate download: {}", method);
 		return response;
 	}
 
-	let registry = registry.read().unwrap();
-	let crate_path = registry.crate_dir().join(path);
-	let data = match std::fs::read(&crate_path) {
-		Ok(data) => data,
-		Err(e) => {
-			return match e.kind() {
-				std::io::ErrorKind::NotFound => {
+	// Hidden objects are only served through the upstream cache.
+	if !palletizer::storage::is_valid_key(path) || palletizer::storage::is_hidden_key(path) {
+		log::warn!("Received request for invalid crate path: {}", path);
+		return not_found();
+	}
+
+	let storage = registry.read().unwrap().storage().clone();
+	let data = match (read_object(storage, path).await, &upstream) {
+		(Ok(Some(data)), _) => data,
+		(Ok(None), Some(upstream)) => {
+			match get_upstream_crate(&registry, upstream, path).await {
+				Ok(Some(data)) => data,
+				Ok(None) => {
 					log::warn!("Received request for unknown crate: {}", path);
-					not_found()
-				},
-				std::io::ErrorKind::PermissionDenied => {
-					log::error!("Failed to read crate data: {}: {}", crate_path.display(), e);
-					unauthorized()
+					return not_found();
 				},
-				_ => {
-					log::error!("Failed to read crate data: {}: {}", crate_path.display(), e);
-					internal_server_error("Failed to read crate data")
+				Err(e) => {
+					log::error!("Failed to get crate from upstream registry: {}: {}", path, e);
+					return bad_gateway("Failed to get crate from upstream registry");
 				},
-			};
+			}
+		},
+		(Ok(None), None) => {
+			log::warn!("Received request for unknown crate: {}", path);
+			return not_found();
+		},
+		(Err(e), _) => {
+			log::error!("Failed to read crate data: {}: {}", path, e);
+			return internal_server_error("Failed to read crate data");
 		},
 	};
 
@@ -68,6 +174,166 @@ fn get_crate(registry: Arc<RwLock<Registry>>, path: &str, method: &Method) -> Re
 	}
 }
 
+/// Error that can occur when authenticating a request.
+pub enum AuthError {
+	/// The request did not contain a token.
+	MissingToken,
+
+	/// The token in the request is not valid.
+	InvalidToken,
+
+	/// Some other error occured while checking the token.
+	Other(palletizer::error::Error),
+}
+
+impl std::fmt::Display for AuthError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Self::MissingToken => write!(f, "missing API token"),
+			Self::InvalidToken => write!(f, "invalid API token"),
+			Self::Other(e) => write!(f, "failed to check API token: {}", e),
+		}
+	}
+}
+
+/// Authenticate a request using the token from the `Authorization` header.
+///
+/// The token may be given directly, as a bearer token,
+/// or as password of HTTP basic authentication (for the git protocol).
+/// Without a token, the user of the TLS client certificate is used, if any.
+///
+/// Returns the user that the token or certificate belongs to.
+/// The user is also recorded as identity of the request for the access log.
+pub fn authenticate<B>(registry: &Registry, request: &hyper::Request<B>) -> Result<String, AuthError> {
+	use base64::Engine as _;
+
+	let Some(header) = request.headers().get(header::AUTHORIZATION) else {
+		let user = request.extensions().get::<Connection>()
+			.and_then(|x| x.client_identity.as_deref())
+			.ok_or(AuthError::MissingToken)?;
+		if let Some(identity) = request.extensions().get::<Identity>() {
+			identity.set(user);
+		}
+		return Ok(user.to_string());
+	};
+	let header = header
+		.to_str()
+		.map_err(|_| AuthError::InvalidToken)?;
+
+	let token = if let Some(basic) = header.strip_prefix("Basic ") {
+		let decoded = base64::engine::general_purpose::STANDARD.decode(basic.trim())
+			.map_err(|_| AuthError::InvalidToken)?;
+		let decoded = String::from_utf8(decoded)
+			.map_err(|_| AuthError::InvalidToken)?;
+		let (_user, password) = decoded.split_once(':')
+			.ok_or(AuthError::InvalidToken)?;
+		password.to_string()
+	} else {
+		header.strip_prefix("Bearer ").unwrap_or(header).trim().to_string()
+	};
+
+	match registry.authenticate(&token) {
+		Ok(Some(user)) => {
+			if let Some(identity) = request.extensions().get::<Identity>() {
+				identity.set(&user);
+			}
+			Ok(user)
+		},
+		Ok(None) => Err(AuthError::InvalidToken),
+		Err(e) => Err(AuthError::Other(e)),
+	}
+}
+
+/// Check if a request is allowed to access read-only routes.
+///
+/// If the registry does not require authentication, all requests are allowed.
+///
+/// Returns None if access is allowed, or an error response otherwise.
+fn check_read_access<B>(registry: &Arc<RwLock<Registry>>, request: &hyper::Request<B>) -> Option<Result<Response, HttpError>> {
+	let registry = registry.read().unwrap();
+	if !registry.auth_required() {
+		return None;
+	}
+
+	match authenticate(&registry, request) {
+		Ok(_user) => None,
+		Err(AuthError::MissingToken) => {
+			log::debug!("Refused {} request for {}: missing API token", request.method(), request.uri());
+			Some(unauthorized())
+		},
+		Err(e @ AuthError::InvalidToken) => {
+			log::warn!("Refused {} request for {}: {}", request.method(), request.uri(), e);
+			Some(forbidden())
+		},
+		Err(e @ AuthError::Other(_)) => {
+			log::error!("{}", e);
+			Some(internal_server_error("Failed to check API token"))
+		},
+	}
+}
+
+/// Get a crate from the upstream registry.
+///
+/// Crates downloaded from the upstream registry are cached in the storage backend as hidden objects under `.upstream/`.
+///
+/// Returns None if the crate is not available from the upstream registry.
+async fn get_upstream_crate(registry: &Arc<RwLock<Registry>>, upstream: &Arc<Upstream>, path: &str) -> Result<Option<Vec<u8>>, String> {
+	let (name, file_name) = match path.split_once('/') {
+		Some(x) => x,
+		None => return Ok(None),
+	};
+	let version = file_name.strip_prefix(name)
+		.and_then(|x| x.strip_prefix('-'))
+		.and_then(|x| x.strip_suffix(".crate"));
+	let version = match version {
+		Some(x) => x,
+		None => return Ok(None),
+	};
+	if !palletizer::is_plausible_crate_name(name) || !version.bytes().all(|c| c.is_ascii_alphanumeric() || b".+-".contains(&c)) {
+		return Ok(None);
+	}
+
+	let (storage, index_path) = {
+		let registry = registry.read().unwrap();
+		// Crates in the local registry shadow crates from the upstream registry.
+		if registry.contains_crate(name) {
+			return Ok(None);
+		}
+		let index_path = registry.index_path(name).to_string_lossy().into_owned();
+		(registry.storage().clone(), index_path)
+	};
+
+	let cache_key = format!(".upstream/{}", path);
+	if let Some(data) = read_object(storage.clone(), &cache_key).await? {
+		return Ok(Some(data));
+	}
+
+	let data = match upstream.fetch_crate(&index_path, name, version).await? {
+		Some(x) => x,
+		None => return Ok(None),
+	};
+
+	log::info!("Downloaded {}-{} from upstream registry", name, version);
+	let cached = data.clone();
+	let result = tokio::task::spawn_blocking(move || storage.put(&cache_key, &cached)).await;
+	match result {
+		Ok(Ok(())) => (),
+		Ok(Err(e)) => log::warn!("Failed to cache crate from upstream registry: {}", e),
+		Err(e) => log::warn!("Failed to cache crate from upstream registry: failed to join background task: {}", e),
+	}
+
+	Ok(Some(data))
+}
+
+/// Read an object from a storage backend without blocking the async runtime.
+async fn read_object(storage: Arc<dyn Storage>, key: &str) -> Result<Option<Vec<u8>>, String> {
+	let key = key.to_string();
+	tokio::task::spawn_blocking(move || storage.get(&key))
+		.await
+		.map_err(|e| format!("failed to join background task: {}", e))?
+		.map_err(|e| e.to_string())
+}
+
 pub fn response_no_cache() -> hyper::http::response::Builder {
 	hyper::Response::builder()
 		.header(header::CACHE_CONTROL, "no-store")
@@ -82,15 +348,29 @@ pub fn not_found() -> Result<Response, HttpError> {
 pub fn unauthorized() -> Result<Response, HttpError> {
 	response_no_cache()
 		.status(StatusCode::UNAUTHORIZED)
+		.header(header::WWW_AUTHENTICATE, "Cargo")
+		.header(header::WWW_AUTHENTICATE, "Basic realm=\"palletizer\"")
 		.body("Unauthorized".into())
 }
 
+pub fn forbidden() -> Result<Response, HttpError> {
+	response_no_cache()
+		.status(StatusCode::FORBIDDEN)
+		.body("Forbidden".into())
+}
+
 pub fn internal_server_error(message: impl std::fmt::Display) -> Result<Response, HttpError> {
 	response_no_cache()
 		.status(StatusCode::INTERNAL_SERVER_ERROR)
 		.body(message.to_string().into())
 }
 
+pub fn bad_gateway(message: impl std::fmt::Display) -> Result<Response, HttpError> {
+	response_no_cache()
+		.status(StatusCode::BAD_GATEWAY)
+		.body(message.to_string().into())
+}
+
 pub fn check_supported_method(actual_method: &Method, allowed_methods: &[Method]) -> Option<Result<Response, HttpError>> {
 	if allowed_methods.contains(actual_method) {
 		None
@@ -150,6 +430,28 @@ impl From<Vec<u8>> for Body {
 	}
 }
 
+impl Body {
+	/// Call a function with the size of every data frame that is sent.
+	///
+	/// For a body with fixed contents, the function is called once with the total size.
+	pub fn inspect_data(self, inspect: impl Fn(u64) + Send + 'static) -> Self {
+		match self {
+			Self::Bytes(x) => {
+				inspect(hyper::body::Body::size_hint(&x).exact().unwrap_or(0));
+				Self::Bytes(x)
+			},
+			Self::Stream(x) => {
+				let frames = x.frames.inspect_ok(move |frame| {
+					if let Some(data) = frame.data_ref() {
+						inspect(data.len() as u64);
+					}
+				});
+				Self::Stream(StreamBody::new(frames))
+			},
+		}
+	}
+}
+
 impl hyper::body::Body for Body {
 	type Data = Bytes;
 	type Error = String;
@@ -238,3 +540,105 @@ impl<Buf: hyper::body::Buf, Error> hyper::body::Body for StreamBody<Buf, Error>
 		Stream::poll_next(self.get_mut().frames.as_mut(), cx)
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_util;
+	use base64::Engine as _;
+
+	fn request(authorization: Option<&str>) -> hyper::Request<()> {
+		let mut request = hyper::Request::builder().uri("/index/config.json");
+		if let Some(authorization) = authorization {
+			request = request.header(header::AUTHORIZATION, authorization);
+		}
+		request.body(()).unwrap()
+	}
+
+	fn request_with_client_identity(client_identity: &str) -> hyper::Request<()> {
+		let mut request = request(None);
+		request.extensions_mut().insert(Connection {
+			address: "127.0.0.1:1234".parse().unwrap(),
+			tls: true,
+			client_identity: Some(client_identity.into()),
+		});
+		request
+	}
+
+	fn basic(user: &str, password: &str) -> String {
+		format!("Basic {}", base64::engine::general_purpose::STANDARD.encode(format!("{}:{}", user, password)))
+	}
+
+	fn status(response: Option<Result<Response, HttpError>>) -> Option<StatusCode> {
+		response.map(|x| x.ok().unwrap().status())
+	}
+
+	#[test]
+	fn authenticate_header_forms() {
+		let dir = tempfile::tempdir().unwrap();
+		let registry = test_util::init_registry(dir.path());
+		let token = registry.create_token("laptop", "alice").unwrap();
+
+		let check = |authorization: &str| authenticate(&registry, &request(Some(authorization))).ok();
+		assert_eq!(check(&token).as_deref(), Some("alice"));
+		assert_eq!(check(&format!("Bearer {}", token)).as_deref(), Some("alice"));
+		assert_eq!(check(&basic("anything", &token)).as_deref(), Some("alice"));
+		assert_eq!(check(&basic("", &token)).as_deref(), Some("alice"));
+
+		assert!(matches!(authenticate(&registry, &request(Some("wrong"))), Err(AuthError::InvalidToken)));
+		assert!(matches!(authenticate(&registry, &request(Some("Bearer wrong"))), Err(AuthError::InvalidToken)));
+		assert!(matches!(authenticate(&registry, &request(Some(&basic(&token, "wrong")))), Err(AuthError::InvalidToken)));
+		assert!(matches!(authenticate(&registry, &request(Some("Basic not-base64"))), Err(AuthError::InvalidToken)));
+		assert!(matches!(authenticate(&registry, &request(None)), Err(AuthError::MissingToken)));
+	}
+
+	#[test]
+	fn authenticate_records_identity() {
+		let dir = tempfile::tempdir().unwrap();
+		let registry = test_util::init_registry(dir.path());
+		let token = registry.create_token("laptop", "alice").unwrap();
+
+		let identity = Identity::default();
+		let mut request = request(Some(&token));
+		request.extensions_mut().insert(identity.clone());
+		assert_eq!(authenticate(&registry, &request).ok().as_deref(), Some("alice"));
+		assert_eq!(identity.get(), Some("alice"));
+	}
+
+	#[test]
+	fn authenticate_with_client_certificate() {
+		let dir = tempfile::tempdir().unwrap();
+		let registry = test_util::init_registry(dir.path());
+		assert_eq!(authenticate(&registry, &request_with_client_identity("ci")).ok().as_deref(), Some("ci"));
+
+		// A token takes precedence over the client certificate.
+		let token = registry.create_token("laptop", "alice").unwrap();
+		let mut request = request_with_client_identity("ci");
+		request.headers_mut().insert(header::AUTHORIZATION, token.parse().unwrap());
+		assert_eq!(authenticate(&registry, &request).ok().as_deref(), Some("alice"));
+	}
+
+	#[test]
+	fn read_access() {
+		let dir = tempfile::tempdir().unwrap();
+		let registry = test_util::init_registry(dir.path());
+		let token = registry.create_token("laptop", "alice").unwrap();
+		let registry = Arc::new(RwLock::new(registry));
+
+		// Without `auth_required`, everything is allowed.
+		assert_eq!(status(check_read_access(&registry, &request(None))), None);
+		assert_eq!(status(check_read_access(&registry, &request(Some("wrong")))), None);
+
+		let mut config: palletizer::Config = toml::from_str(&std::fs::read_to_string(dir.path().join("palletizer.toml")).unwrap()).unwrap();
+		config.auth_required = true;
+		std::fs::write(dir.path().join("palletizer.toml"), toml::to_string(&config).unwrap()).unwrap();
+		*registry.write().unwrap() = Registry::open(dir.path()).unwrap();
+
+		assert_eq!(status(check_read_access(&registry, &request(None))), Some(StatusCode::UNAUTHORIZED));
+		assert_eq!(status(check_read_access(&registry, &request(Some("wrong")))), Some(StatusCode::FORBIDDEN));
+		assert_eq!(status(check_read_access(&registry, &request(Some(&token)))), None);
+		assert_eq!(status(check_read_access(&registry, &request(Some(&format!("Bearer {}", token))))), None);
+		assert_eq!(status(check_read_access(&registry, &request(Some(&basic("alice", &token))))), None);
+		assert_eq!(status(check_read_access(&registry, &request_with_client_identity("ci"))), None);
+	}
+}
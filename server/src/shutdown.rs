@@ -0,0 +1,51 @@
+use tokio::sync::{mpsc, watch};
+
+/// Create a new shutdown trigger and a handle to watch it.
+pub fn channel() -> (Trigger, Shutdown) {
+	let (requested_tx, requested_rx) = watch::channel(false);
+	let (task_tx, task_rx) = mpsc::channel(1);
+	let trigger = Trigger {
+		requested: requested_tx,
+		tasks: task_rx,
+	};
+	let shutdown = Shutdown {
+		requested: requested_rx,
+		_task: task_tx,
+	};
+	(trigger, shutdown)
+}
+
+/// Handle to request a graceful shutdown.
+pub struct Trigger {
+	requested: watch::Sender<bool>,
+	tasks: mpsc::Receiver<()>,
+}
+
+impl Trigger {
+	/// Request a shutdown and wait until all [`Shutdown`] handles are dropped.
+	pub async fn shutdown(mut self) {
+		self.requested.send_replace(true);
+		// Nothing is ever sent, so this only returns when all senders are dropped.
+		self.tasks.recv().await;
+	}
+}
+
+/// Handle to watch for a shutdown request.
+///
+/// A graceful shutdown waits until all clones of the handle are dropped,
+/// so tasks that should finish before the server exits must hold on to a clone.
+#[derive(Clone)]
+pub struct Shutdown {
+	requested: watch::Receiver<bool>,
+	_task: mpsc::Sender<()>,
+}
+
+impl Shutdown {
+	/// Wait until a shutdown is requested.
+	pub async fn requested(&mut self) {
+		// If the trigger is dropped without requesting a shutdown, wait forever.
+		if self.requested.wait_for(|x| *x).await.is_err() {
+			std::future::pending::<()>().await;
+		}
+	}
+}
@@ -0,0 +1,77 @@
+/// A signal that the server reacts to.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Signal {
+	/// Shut down gracefully, on SIGINT or SIGTERM.
+	Shutdown,
+
+	/// Reload the configuration, on SIGHUP.
+	Reload,
+}
+
+/// Listener for process signals.
+pub struct Signals {
+	#[cfg(unix)]
+	interrupt: tokio::signal::unix::Signal,
+
+	#[cfg(unix)]
+	terminate: tokio::signal::unix::Signal,
+
+	#[cfg(unix)]
+	hangup: tokio::signal::unix::Signal,
+}
+
+impl Signals {
+	/// Install the signal handlers.
+	///
+	/// This must be called from within the tokio runtime.
+	#[cfg(unix)]
+	pub fn new() -> Result<Self, ()> {
+		use tokio::signal::unix::{signal, SignalKind};
+		Ok(Self {
+			interrupt: signal(SignalKind::interrupt())
+				.map_err(|e| log::error!("Failed to install SIGINT handler: {}", e))?,
+			terminate: signal(SignalKind::terminate())
+				.map_err(|e| log::error!("Failed to install SIGTERM handler: {}", e))?,
+			hangup: signal(SignalKind::hangup())
+				.map_err(|e| log::error!("Failed to install SIGHUP handler: {}", e))?,
+		})
+	}
+
+	/// Install the signal handlers.
+	#[cfg(not(unix))]
+	pub fn new() -> Result<Self, ()> {
+		Ok(Self {})
+	}
+
+	/// Wait for the next signal.
+	#[cfg(unix)]
+	pub async fn recv(&mut self) -> Signal {
+		tokio::select! {
+			_ = self.interrupt.recv() => {
+				log::info!("Received SIGINT");
+				Signal::Shutdown
+			},
+			_ = self.terminate.recv() => {
+				log::info!("Received SIGTERM");
+				Signal::Shutdown
+			},
+			_ = self.hangup.recv() => {
+				log::info!("Received SIGHUP");
+				Signal::Reload
+			},
+		}
+	}
+
+	/// Wait for the next signal.
+	///
+	/// Only Ctrl+C is supported on this platform.
+	#[cfg(not(unix))]
+	pub async fn recv(&mut self) -> Signal {
+		if let Err(e) = tokio::signal::ctrl_c().await {
+			log::error!("Failed to wait for Ctrl+C: {}", e);
+			std::future::pending::<()>().await;
+		}
+		log::info!("Received Ctrl+C");
+		Signal::Shutdown
+	}
+}
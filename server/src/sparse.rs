@@ -0,0 +1,229 @@
+use hyper::{header, Method, StatusCode};
+use palletizer::Registry;
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
+
+use crate::api_v1;
+use crate::server::{self, HttpError, Response};
+use crate::upstream::Upstream;
+
+/// Handle requests for the sparse index protocol.
+///
+/// The files are served directly from the work tree of the index repository.
+/// Every response carries an `ETag` and `Last-Modified` header,
+/// so that Cargo can cheaply revalidate its cached copy of the index.
+///
+/// If an upstream registry is configured, requests for crates that are not in the local index are forwarded to the upstream registry.
+pub async fn handle_request<B>(registry: Arc<RwLock<Registry>>, upstream: Option<Arc<Upstream>>, request: &hyper::Request<B>, rel_path: &str) -> Result<Response, HttpError> {
+	if let Some(response) = server::check_supported_method(request.method(), &[Method::GET, Method::HEAD]) {
+		log::warn!("Unsupported request method for sparse index: {}", request.method());
+		return response;
+	}
+
+	let file_path = {
+		let registry = registry.read().unwrap();
+		if rel_path == "config.json" {
+			registry.index_dir().join("config.json")
+		} else {
+			let crate_name = match rel_path.rsplit_once('/') {
+				Some((_, name)) => name,
+				None => return server::not_found(),
+			};
+			if !palletizer::is_plausible_crate_name(crate_name) || registry.index_path(crate_name).to_str() != Some(rel_path) {
+				log::debug!("Received sparse index request for invalid path: {}", rel_path);
+				return server::not_found();
+			}
+			registry.index_dir().join(rel_path)
+		}
+	};
+
+	let mut modified = None;
+	let data = match (std::fs::read(&file_path), &upstream) {
+		(Ok(data), _) => {
+			modified = std::fs::metadata(&file_path)
+				.and_then(|x| x.modified())
+				.map_err(|e| log::warn!("Failed to get modification time of {}: {}", file_path.display(), e))
+				.ok();
+			data
+		},
+		(Err(e), Some(upstream)) if e.kind() == std::io::ErrorKind::NotFound && rel_path != "config.json" => {
+			match upstream.fetch_index_file(rel_path).await {
+				Ok(Some(data)) => data,
+				Ok(None) => {
+					log::debug!("Received sparse index request for unknown crate: {}", rel_path);
+					return server::not_found();
+				},
+				Err(e) => {
+					log::error!("Failed to get index file from upstream registry: {}: {}", rel_path, e);
+					return server::bad_gateway("Failed to get index file from upstream registry");
+				},
+			}
+		},
+		(Err(e), _) => {
+			return match e.kind() {
+				std::io::ErrorKind::NotFound => {
+					log::debug!("Received sparse index request for unknown crate: {}", rel_path);
+					server::not_found()
+				},
+				_ => {
+					log::error!("Failed to read index file: {}: {}", file_path.display(), e);
+					server::internal_server_error("Failed to read index file")
+				},
+			};
+		},
+	};
+
+	let etag = format!("\"{}\"", api_v1::compute_sha256_hex(&data));
+	let response = hyper::Response::builder()
+		.header(header::CACHE_CONTROL, "no-cache")
+		.header(header::ETAG, &etag);
+	let response = match modified {
+		Some(modified) => response.header(header::LAST_MODIFIED, format_http_date(modified)),
+		None => response,
+	};
+
+	if !is_modified(request, &etag, modified) {
+		return response
+			.status(StatusCode::NOT_MODIFIED)
+			.body("".into());
+	}
+
+	let response = response.header(header::CONTENT_TYPE, "text/plain; charset=utf-8");
+	if request.method() == Method::GET {
+		response.body(data.into())
+	} else {
+		response.body("".into())
+	}
+}
+
+/// Check if the client copy of a file is out of date, based on the conditional request headers.
+///
+/// If the request has an `If-None-Match` header, the `If-Modified-Since` header is ignored.
+fn is_modified<B>(request: &hyper::Request<B>, etag: &str, modified: Option<SystemTime>) -> bool {
+	if let Some(if_none_match) = request.headers().get(header::IF_NONE_MATCH) {
+		let if_none_match = match if_none_match.to_str() {
+			Ok(x) => x,
+			Err(_) => return true,
+		};
+		return !if_none_match.split(',')
+			.map(|x| x.trim())
+			.map(|x| x.strip_prefix("W/").unwrap_or(x))
+			.any(|x| x == "*" || x == etag);
+	}
+
+	if let (Some(if_modified_since), Some(modified)) = (request.headers().get(header::IF_MODIFIED_SINCE), modified) {
+		let if_modified_since = match if_modified_since.to_str().ok().and_then(parse_http_date) {
+			Some(x) => x,
+			None => return true,
+		};
+		// HTTP dates have a resolution of one second.
+		let modified = chrono::DateTime::<chrono::Utc>::from(modified).timestamp();
+		return modified > if_modified_since.timestamp();
+	}
+
+	true
+}
+
+/// Format a timestamp as HTTP date.
+fn format_http_date(time: SystemTime) -> String {
+	chrono::DateTime::<chrono::Utc>::from(time)
+		.format("%a, %d %b %Y %H:%M:%S GMT")
+		.to_string()
+}
+
+/// Parse an HTTP date.
+fn parse_http_date(date: &str) -> Option<chrono::DateTime<chrono::Utc>> {
+	chrono::DateTime::parse_from_rfc2822(date)
+		.ok()
+		.map(|x| x.with_timezone(&chrono::Utc))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_util;
+	use http_body_util::BodyExt as _;
+
+	fn request(method: Method, headers: &[(header::HeaderName, &str)]) -> hyper::Request<()> {
+		let mut request = hyper::Request::builder().method(method);
+		for (name, value) in headers {
+			request = request.header(name, *value);
+		}
+		request.body(()).unwrap()
+	}
+
+	async fn get(registry: &Arc<RwLock<Registry>>, rel_path: &str, headers: &[(header::HeaderName, &str)]) -> (StatusCode, hyper::HeaderMap, Vec<u8>) {
+		let request = request(Method::GET, headers);
+		let response = handle_request(registry.clone(), None, &request, rel_path).await.unwrap();
+		let (parts, body) = response.into_parts();
+		let body = body.collect().await.unwrap().to_bytes().to_vec();
+		(parts.status, parts.headers, body)
+	}
+
+	#[tokio::test]
+	async fn etag() {
+		let dir = tempfile::tempdir().unwrap();
+		let mut registry = test_util::init_registry(dir.path());
+		test_util::add_crate(&mut registry, "foo", "0.1.0", b"foo 0.1.0");
+		let registry = Arc::new(RwLock::new(registry));
+
+		let (status, headers, body) = get(&registry, "3/f/foo", &[]).await;
+		assert_eq!(status, StatusCode::OK);
+		assert_eq!(body, std::fs::read(dir.path().join("index/3/f/foo")).unwrap());
+		let etag = headers[header::ETAG].to_str().unwrap().to_string();
+		assert_eq!(etag, format!("\"{}\"", api_v1::compute_sha256_hex(&body)));
+
+		let (status, headers, body) = get(&registry, "3/f/foo", &[(header::IF_NONE_MATCH, &etag)]).await;
+		assert_eq!(status, StatusCode::NOT_MODIFIED);
+		assert_eq!(headers[header::ETAG], etag);
+		assert!(body.is_empty());
+
+		let weak = format!("\"other\", W/{}", etag);
+		assert_eq!(get(&registry, "3/f/foo", &[(header::IF_NONE_MATCH, &weak)]).await.0, StatusCode::NOT_MODIFIED);
+		assert_eq!(get(&registry, "3/f/foo", &[(header::IF_NONE_MATCH, "*")]).await.0, StatusCode::NOT_MODIFIED);
+		assert_eq!(get(&registry, "3/f/foo", &[(header::IF_NONE_MATCH, "\"other\"")]).await.0, StatusCode::OK);
+
+		// After the index file changes, the old ETag no longer matches.
+		test_util::add_crate(&mut registry.write().unwrap(), "foo", "0.2.0", b"foo 0.2.0");
+		let (status, headers, body) = get(&registry, "3/f/foo", &[(header::IF_NONE_MATCH, &etag)]).await;
+		assert_eq!(status, StatusCode::OK);
+		assert_ne!(headers[header::ETAG], etag);
+		assert_eq!(body, std::fs::read(dir.path().join("index/3/f/foo")).unwrap());
+	}
+
+	#[tokio::test]
+	async fn if_modified_since() {
+		let dir = tempfile::tempdir().unwrap();
+		let mut registry = test_util::init_registry(dir.path());
+		test_util::add_crate(&mut registry, "foo", "0.1.0", b"foo 0.1.0");
+		let registry = Arc::new(RwLock::new(registry));
+
+		let (status, headers, _body) = get(&registry, "3/f/foo", &[]).await;
+		assert_eq!(status, StatusCode::OK);
+		let last_modified = headers[header::LAST_MODIFIED].to_str().unwrap().to_string();
+
+		assert_eq!(get(&registry, "3/f/foo", &[(header::IF_MODIFIED_SINCE, &last_modified)]).await.0, StatusCode::NOT_MODIFIED);
+		let old = "Thu, 01 Jan 1970 00:00:00 GMT";
+		assert_eq!(get(&registry, "3/f/foo", &[(header::IF_MODIFIED_SINCE, old)]).await.0, StatusCode::OK);
+		assert_eq!(get(&registry, "3/f/foo", &[(header::IF_MODIFIED_SINCE, "not a date")]).await.0, StatusCode::OK);
+
+		// If-None-Match takes precedence over If-Modified-Since.
+		let headers = [(header::IF_NONE_MATCH, "\"other\""), (header::IF_MODIFIED_SINCE, &last_modified)];
+		assert_eq!(get(&registry, "3/f/foo", &headers).await.0, StatusCode::OK);
+	}
+
+	#[test]
+	fn http_dates() {
+		let time = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_700_000_000);
+		let formatted = format_http_date(time);
+		assert_eq!(formatted, "Tue, 14 Nov 2023 22:13:20 GMT");
+		assert_eq!(parse_http_date(&formatted).unwrap().timestamp(), 1_700_000_000);
+
+		// Sub-second modification times still match the date that was sent to the client.
+		let etag = "\"abc\"";
+		let modified = time + std::time::Duration::from_millis(500);
+		assert!(!is_modified(&request(Method::GET, &[(header::IF_MODIFIED_SINCE, &formatted)]), etag, Some(modified)));
+		assert!(is_modified(&request(Method::GET, &[(header::IF_MODIFIED_SINCE, &formatted)]), etag, Some(time + std::time::Duration::from_secs(1))));
+		assert!(is_modified(&request(Method::GET, &[(header::IF_MODIFIED_SINCE, &formatted)]), etag, None));
+	}
+}
@@ -0,0 +1,46 @@
+//! Helpers for unit tests.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use palletizer::{index, Config, Registry};
+use sha2::{Digest, Sha256};
+
+/// Initialize a new registry in an empty directory.
+///
+/// The index repository gets its own commit author, so the tests do not depend on the git configuration of the user.
+pub fn init_registry(path: &Path) -> Registry {
+	let config = Config::example();
+	let index_dir = path.join(&config.index_dir);
+	git(&["init", "--quiet"], &index_dir);
+	git(&["config", "user.name", "Palletizer"], &index_dir);
+	git(&["config", "user.email", "palletizer@example.com"], &index_dir);
+	Registry::init(path, config).unwrap()
+}
+
+/// Add a crate version to a registry, with arbitrary data as crate file.
+pub fn add_crate(registry: &mut Registry, name: &str, version: &str, data: &[u8]) {
+	let entry = index::Entry {
+		name: name.into(),
+		version: version.into(),
+		dependencies: Vec::new(),
+		checksum_sha256: palletizer::to_hex(Sha256::digest(data)),
+		features: BTreeMap::new(),
+		features2: BTreeMap::new(),
+		yanked: false,
+		links: None,
+		rust_version: None,
+		v: None,
+	};
+	registry.add_crate_with_metadata(&entry, data).unwrap();
+}
+
+fn git(args: &[&str], dir: &Path) {
+	std::fs::create_dir_all(dir).unwrap();
+	let status = std::process::Command::new("git")
+		.args(args)
+		.current_dir(dir)
+		.status()
+		.unwrap();
+	assert!(status.success(), "git {} failed", args.join(" "));
+}
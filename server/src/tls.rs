@@ -1,94 +1,489 @@
+use std::net::IpAddr;
 use std::path::{Path, PathBuf};
-use std::pin::Pin;
 use std::time::{Duration, Instant};
 
-use openssl::ssl::{Ssl, SslContext};
-use tokio::net::TcpStream;
-use tokio_openssl::SslStream;
-
 use crate::config;
 
+// The backend is chosen at compile time: rustls if the `rustls` feature is enabled, OpenSSL (the `tls` feature) otherwise.
+
+#[cfg(not(feature = "rustls"))]
+mod openssl;
+#[cfg(not(feature = "rustls"))]
+pub use self::openssl::TlsAcceptor;
+
+#[cfg(feature = "rustls")]
+mod rustls;
+#[cfg(feature = "rustls")]
+pub use self::rustls::TlsAcceptor;
+
 const RELOAD_AFTER_SUCCESS: Duration = Duration::from_secs(3600 * 24);
 const RELOAD_AFTER_ERROR: Duration = Duration::from_secs(60);
 const RELOAD_AFTER_ERROR_MAX: Duration = Duration::from_secs(3600);
 
-pub struct TlsAcceptor {
+/// The TLS settings of a listener, with all paths resolved.
+struct Settings {
+	default_certificate: CertificateFiles,
+	sni_certificates: Vec<CertificateFiles>,
+	client_auth: Option<ClientAuth>,
+}
+
+/// The files of a certificate and the server names to use it for.
+struct CertificateFiles {
+	server_names: Vec<String>,
 	certificate_chain: PathBuf,
 	private_key: PathBuf,
-	context: SslContext,
+}
+
+/// The settings for client certificate authentication.
+struct ClientAuth {
+	ca_certificates: PathBuf,
+	revocation_lists: Vec<PathBuf>,
+	optional: bool,
+	identities: Vec<(CertificateMatcher, String)>,
+}
+
+/// A pattern to match client certificates against.
+enum CertificateMatcher {
+	/// Match the subject, as sorted list of attributes with normalized names.
+	Subject(Vec<(String, String)>),
+
+	/// Match one of the subject alternative names.
+	AltName(AltName),
+}
+
+/// A subject alternative name of a certificate.
+#[derive(Debug, Clone, Eq, PartialEq)]
+enum AltName {
+	/// A DNS name, in lowercase.
+	Dns(String),
+	Email(String),
+	Uri(String),
+	Ip(IpAddr),
+}
+
+/// The names of a client certificate, extracted by the TLS backend.
+struct ClientCertificate {
+	/// The subject as sorted list of attributes with normalized names.
+	///
+	/// This is `None` if any attribute can not be represented as text.
+	subject: Option<Vec<(String, String)>>,
+
+	/// The subject alternative names.
+	alt_names: Vec<AltName>,
+}
+
+/// Timer to periodically reload the TLS files.
+///
+/// After a failed reload, the reload is retried with exponential backoff.
+struct ReloadTimer {
 	next_reload: Instant,
 	fail_timeout: Duration,
 }
 
-impl TlsAcceptor {
-	/// Create an acceptor from a configuration.
-	pub fn from_config(config: &config::Tls, config_dir: &Path) -> Result<Self, ()> {
-		let certificate_chain = config_dir.join(&config.certificate_chain);
-		let private_key = config_dir.join(&config.private_key);
-		let context = load_tls_files(&certificate_chain, &private_key)?;
+impl Settings {
+	fn from_config(config: &config::Tls, config_dir: &Path) -> Result<Self, ()> {
+		let default_certificate = CertificateFiles {
+			server_names: Vec::new(),
+			certificate_chain: config_dir.join(&config.certificate_chain),
+			private_key: config_dir.join(&config.private_key),
+		};
+		let mut sni_certificates = Vec::with_capacity(config.certificates.len());
+		for certificate in &config.certificates {
+			if certificate.server_names.is_empty() {
+				log::error!("No server names configured for TLS certificate {}", certificate.certificate_chain.display());
+				return Err(());
+			}
+			sni_certificates.push(CertificateFiles {
+				server_names: certificate.server_names.iter().map(|x| x.to_ascii_lowercase()).collect(),
+				certificate_chain: config_dir.join(&certificate.certificate_chain),
+				private_key: config_dir.join(&certificate.private_key),
+			});
+		}
+		let client_auth = match &config.client_auth {
+			None => None,
+			Some(client_auth) => Some(ClientAuth::from_config(client_auth, config_dir)?),
+		};
 		Ok(Self {
-			certificate_chain,
-			private_key,
-			context,
-			next_reload: Instant::now() + RELOAD_AFTER_SUCCESS,
-			fail_timeout: RELOAD_AFTER_ERROR,
+			default_certificate,
+			sni_certificates,
+			client_auth,
 		})
 	}
 
-	/// Reload the certificate chain and private key from disk.
-	pub fn reload(&mut self) -> Result<(), ()> {
-		match load_tls_files(&self.certificate_chain, &self.private_key) {
-			Ok(context) => {
-				self.context = context;
-				self.next_reload = Instant::now() + RELOAD_AFTER_SUCCESS;
-				self.fail_timeout = RELOAD_AFTER_ERROR;
-				Ok(())
-			},
-			Err(e) => {
-				self.next_reload = Instant::now() + self.fail_timeout;
-				self.fail_timeout = (self.fail_timeout * 2).min(RELOAD_AFTER_ERROR_MAX);
-				Err(e)
+	/// Get all certificates, starting with the default certificate.
+	fn certificates(&self) -> impl Iterator<Item = &CertificateFiles> {
+		std::iter::once(&self.default_certificate).chain(&self.sni_certificates)
+	}
+
+	/// Log which files are about to be reloaded.
+	fn log_reload(&self) {
+		for certificate in self.certificates() {
+			log::info!("Reloading TLS private key from {}", certificate.private_key.display());
+			log::info!("Reloading TLS certificate from {}", certificate.certificate_chain.display());
+		}
+		if let Some(client_auth) = &self.client_auth {
+			log::info!("Reloading TLS client CA certificates from {}", client_auth.ca_certificates.display());
+			for revocation_list in &client_auth.revocation_lists {
+				log::info!("Reloading TLS certificate revocation list from {}", revocation_list.display());
 			}
 		}
 	}
 
-	/// Initialize a TLS session for an accepted connection.
+	/// Get the registry user that a client certificate maps to.
+	fn client_identity(&self, certificate: &ClientCertificate) -> Option<String> {
+		let client_auth = self.client_auth.as_ref()?;
+		client_auth.identities.iter()
+			.find(|(matcher, _)| matcher.matches(certificate))
+			.map(|(_, user)| user.clone())
+	}
+}
+
+impl ClientAuth {
+	fn from_config(config: &config::ClientAuth, config_dir: &Path) -> Result<Self, ()> {
+		let mut identities = Vec::with_capacity(config.identities.len());
+		for identity in &config.identities {
+			let matcher = match (&identity.subject, &identity.subject_alt_name) {
+				(Some(subject), None) => CertificateMatcher::parse_subject(subject)?,
+				(None, Some(name)) => CertificateMatcher::AltName(AltName::parse(name)?),
+				_ => {
+					log::error!("Client identity for user {} must have exactly one of `subject` or `subject_alt_name`", identity.user);
+					return Err(());
+				},
+			};
+			identities.push((matcher, identity.user.clone()));
+		}
+		Ok(Self {
+			ca_certificates: config_dir.join(&config.ca_certificates),
+			revocation_lists: config.revocation_lists.iter().map(|x| config_dir.join(x)).collect(),
+			optional: config.optional,
+			identities,
+		})
+	}
+}
+
+impl CertificateMatcher {
+	/// Parse a subject like `CN=ci,O=Example`.
 	///
-	/// This will automatically reload the TLS key and certificate every 24 hours.
-	pub async fn accept(&mut self, connection: TcpStream) -> Result<SslStream<TcpStream>, ()> {
-		if Instant::now() >= self.next_reload {
-			log::info!("Reloading TLS private key from {}", self.private_key.display());
-			log::info!("Reloading TLS certificate from {}", self.certificate_chain.display());
-			self.reload().ok();
+	/// Attributes are separated by `,` or `+`.
+	/// Special characters in values must be escaped as described in RFC 4514, like `O=Foo\, Inc`.
+	fn parse_subject(subject: &str) -> Result<Self, ()> {
+		let mut attributes = Vec::new();
+		for attribute in split_unescaped(subject, &[',', '+']) {
+			let (name, value) = attribute.split_once('=')
+				.ok_or_else(|| log::error!("Invalid certificate subject {:?}: expected attributes like `CN=name`", subject))?;
+			let value = unescape_attribute_value(value.trim_start())
+				.map_err(|e| log::error!("Invalid certificate subject {:?}: {}", subject, e))?;
+			attributes.push((attribute_name(name.trim()), value));
+		}
+		attributes.sort();
+		Ok(Self::Subject(attributes))
+	}
+
+	fn matches(&self, certificate: &ClientCertificate) -> bool {
+		match self {
+			Self::Subject(expected) => certificate.subject.as_ref() == Some(expected),
+			Self::AltName(expected) => certificate.alt_names.contains(expected),
+		}
+	}
+}
+
+impl AltName {
+	/// Parse a subject alternative name like `DNS:ci.example.com`.
+	fn parse(name: &str) -> Result<Self, ()> {
+		let invalid = || log::error!("Invalid subject alternative name {:?}: expected a `DNS:`, `email:`, `URI:` or `IP:` prefix", name);
+		let (kind, value) = name.split_once(':').ok_or_else(invalid)?;
+		if kind.eq_ignore_ascii_case("DNS") {
+			Ok(Self::Dns(value.to_ascii_lowercase()))
+		} else if kind.eq_ignore_ascii_case("email") {
+			Ok(Self::Email(value.to_string()))
+		} else if kind.eq_ignore_ascii_case("URI") {
+			Ok(Self::Uri(value.to_string()))
+		} else if kind.eq_ignore_ascii_case("IP") {
+			let address = value.parse()
+				.map_err(|e| log::error!("Invalid IP address in subject alternative name {:?}: {}", name, e))?;
+			Ok(Self::Ip(address))
+		} else {
+			invalid();
+			Err(())
+		}
+	}
+
+	/// Convert the raw bytes of an IP address name.
+	fn from_ip_bytes(bytes: &[u8]) -> Option<Self> {
+		match bytes.len() {
+			4 => Some(Self::Ip(IpAddr::from(<[u8; 4]>::try_from(bytes).ok()?))),
+			16 => Some(Self::Ip(IpAddr::from(<[u8; 16]>::try_from(bytes).ok()?))),
+			_ => None,
+		}
+	}
+}
+
+/// Normalize the name of a subject attribute.
+///
+/// Names are compared case insensitive, and `Email` is an alias for `emailAddress`.
+fn attribute_name(name: &str) -> String {
+	let name = name.to_ascii_uppercase();
+	if name == "EMAIL" {
+		String::from("EMAILADDRESS")
+	} else {
+		name
+	}
+}
+
+/// Split a string on separators that are not escaped with a backslash.
+fn split_unescaped<'a>(input: &'a str, separators: &[char]) -> Vec<&'a str> {
+	let mut parts = Vec::new();
+	let mut start = 0;
+	let mut escaped = false;
+	for (i, c) in input.char_indices() {
+		if escaped {
+			escaped = false;
+		} else if c == '\\' {
+			escaped = true;
+		} else if separators.contains(&c) {
+			parts.push(&input[start..i]);
+			start = i + c.len_utf8();
+		}
+	}
+	parts.push(&input[start..]);
+	parts
+}
+
+/// Remove the RFC 4514 escaping from an attribute value.
+///
+/// A backslash escapes a special character or starts a pair of hex digits.
+/// Whitespace at the end of the value is removed, unless it is escaped.
+fn unescape_attribute_value(value: &str) -> Result<String, String> {
+	let mut output = Vec::with_capacity(value.len());
+	// The length of the output up to and including the last escaped character.
+	let mut escaped_len = 0;
+	let mut bytes = value.bytes();
+	while let Some(byte) = bytes.next() {
+		if byte != b'\\' {
+			output.push(byte);
+			continue;
 		}
+		match bytes.next() {
+			Some(high) if high.is_ascii_hexdigit() => {
+				let low = bytes.next()
+					.filter(|x| x.is_ascii_hexdigit())
+					.ok_or("expected two hex digits after `\\`")?;
+				// Unwrap should be fine: we just checked that both are hex digits.
+				output.push(u8::from_str_radix(std::str::from_utf8(&[high, low]).unwrap(), 16).unwrap());
+			},
+			Some(special) if b" \"#+,;<=>\\".contains(&special) => output.push(special),
+			Some(other) => return Err(format!("invalid escape sequence `\\{}`", other as char)),
+			None => return Err(String::from("value ends with an unescaped `\\`")),
+		}
+		escaped_len = output.len();
+	}
+	while output.len() > escaped_len && output.last().is_some_and(|x| x.is_ascii_whitespace()) {
+		output.pop();
+	}
+	String::from_utf8(output).map_err(|_| String::from("escaped value is not valid UTF-8"))
+}
+
+/// Check if a server name matches a configured server name.
+///
+/// Both names must already be lowercase.
+/// A leading `*.` in the pattern matches exactly one label.
+fn server_name_matches(pattern: &str, server_name: &str) -> bool {
+	match pattern.strip_prefix("*.") {
+		Some(suffix) => server_name.split_once('.').is_some_and(|(label, rest)| !label.is_empty() && rest == suffix),
+		None => pattern == server_name,
+	}
+}
+
+impl ReloadTimer {
+	fn new() -> Self {
+		Self {
+			next_reload: Instant::now() + RELOAD_AFTER_SUCCESS,
+			fail_timeout: RELOAD_AFTER_ERROR,
+		}
+	}
+
+	/// Check if it is time to reload the TLS files.
+	fn is_due(&self) -> bool {
+		Instant::now() >= self.next_reload
+	}
+
+	/// Schedule the next reload after a successful reload.
+	fn succeeded(&mut self) {
+		self.next_reload = Instant::now() + RELOAD_AFTER_SUCCESS;
+		self.fail_timeout = RELOAD_AFTER_ERROR;
+	}
+
+	/// Schedule the next reload after a failed reload.
+	fn failed(&mut self) {
+		self.next_reload = Instant::now() + self.fail_timeout;
+		self.fail_timeout = (self.fail_timeout * 2).min(RELOAD_AFTER_ERROR_MAX);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Create a client certificate the way the TLS backends do.
+	fn certificate(subject: &[(&str, &str)], alt_names: &[AltName]) -> ClientCertificate {
+		let mut subject: Vec<_> = subject.iter()
+			.map(|(name, value)| (attribute_name(name), value.to_string()))
+			.collect();
+		subject.sort();
+		ClientCertificate {
+			subject: Some(subject),
+			alt_names: alt_names.to_vec(),
+		}
+	}
+
+	fn identity(user: &str, subject: Option<&str>, subject_alt_name: Option<&str>) -> config::ClientIdentity {
+		config::ClientIdentity {
+			user: user.into(),
+			subject: subject.map(String::from),
+			subject_alt_name: subject_alt_name.map(String::from),
+		}
+	}
+
+	fn client_auth(identities: Vec<config::ClientIdentity>) -> Result<ClientAuth, ()> {
+		let config = config::ClientAuth {
+			ca_certificates: "ca.pem".into(),
+			revocation_lists: Vec::new(),
+			optional: false,
+			identities,
+		};
+		ClientAuth::from_config(&config, Path::new("/etc/palletizer"))
+	}
 
-		let ssl = Ssl::new(&self.context)
-			.map_err(|e| log::error!("Failed to initialize TLS session: {}", e))?;
-		let mut stream = tokio_openssl::SslStream::new(ssl, connection)
-			.map_err(|e| log::error!("Failed to create TLS stream: {}", e))?;
-		Pin::new(&mut stream).accept()
-			.await
-			.map_err(|e| log::error!("TLS handshake failed: {}", e))?;
-		Ok(stream)
-	}
-}
-
-fn load_tls_files(certificate_chain: &Path, private_key: &Path) -> Result<openssl::ssl::SslContext, ()> {
-	let mut builder = mozilla_modern_v5()
-		.map_err(|e| log::error!("Failed to create OpenSSL context: {}", e))?;
-	builder.set_private_key_file(private_key, openssl::ssl::SslFiletype::PEM)
-		.map_err(|e| log::error!("Failed to load private key from {}: {}", private_key.display(), e))?;
-	builder.set_certificate_chain_file(certificate_chain)
-		.map_err(|e| log::error!("Failed to load certificate chain from {}: {}", certificate_chain.display(), e))?;
-	Ok(builder.build())
-}
-
-fn mozilla_modern_v5() -> Result<openssl::ssl::SslContextBuilder, openssl::error::ErrorStack> {
-	use openssl::ssl::{SslMethod, SslOptions};
-	let mut context = SslContext::builder(SslMethod::tls_server())?;
-	context.set_options(SslOptions::NO_SSL_MASK & !SslOptions::NO_TLSV1_3);
-	context.set_ciphersuites(
-		"TLS_AES_128_GCM_SHA256:TLS_AES_256_GCM_SHA384:TLS_CHACHA20_POLY1305_SHA256",
-	)?;
-	Ok(context)
+	fn subject_matches(pattern: &str, certificate: &ClientCertificate) -> bool {
+		CertificateMatcher::parse_subject(pattern).unwrap().matches(certificate)
+	}
+
+	#[test]
+	fn match_subject() {
+		let ci = certificate(&[("CN", "ci"), ("O", "Example")], &[]);
+		assert!(subject_matches("CN=ci,O=Example", &ci));
+		assert!(subject_matches("O=Example, CN=ci", &ci));
+		assert!(subject_matches("cn=ci,o=Example", &ci));
+		// Values are compared exactly, and all attributes must match.
+		assert!(!subject_matches("CN=CI,O=Example", &ci));
+		assert!(!subject_matches("CN=ci", &ci));
+		assert!(!subject_matches("CN=ci,O=Example,OU=Build", &ci));
+
+		// A certificate with a subject that can not be represented as text matches no subject.
+		let binary = ClientCertificate { subject: None, alt_names: Vec::new() };
+		assert!(!subject_matches("CN=ci,O=Example", &binary));
+	}
+
+	#[test]
+	fn match_subject_email_alias() {
+		let ci = certificate(&[("CN", "ci"), ("emailAddress", "ci@example.com")], &[]);
+		assert!(subject_matches("CN=ci,Email=ci@example.com", &ci));
+		assert!(subject_matches("CN=ci,emailAddress=ci@example.com", &ci));
+		assert!(subject_matches("CN=ci,EMAILADDRESS=ci@example.com", &ci));
+	}
+
+	#[test]
+	fn match_subject_with_escaped_characters() {
+		let ci = certificate(&[("CN", "ci+build"), ("O", "Foo, Inc. "), ("OU", "a=b\\c")], &[]);
+		assert!(subject_matches("CN=ci\\+build,O=Foo\\, Inc.\\ ,OU=a\\=b\\\\c", &ci));
+		assert!(subject_matches("CN=ci\\2Bbuild,O=Foo\\2C Inc.\\20,OU=a\\3Db\\5Cc", &ci));
+		assert!(!subject_matches("CN=ci\\+build,O=Foo\\, Inc.,OU=a\\=b\\\\c", &ci));
+
+		// Unescaped `+` separates attributes of a multi-valued RDN.
+		let multi = certificate(&[("CN", "ci"), ("UID", "42")], &[]);
+		assert!(subject_matches("CN=ci+UID=42", &multi));
+
+		let utf8 = certificate(&[("CN", "é")], &[]);
+		assert!(subject_matches("CN=\\C3\\A9", &utf8));
+	}
+
+	#[test]
+	fn invalid_subjects() {
+		assert!(CertificateMatcher::parse_subject("ci").is_err());
+		assert!(CertificateMatcher::parse_subject("CN=ci,").is_err());
+		assert!(CertificateMatcher::parse_subject("CN=ci,O=Foo\\").is_err());
+		assert!(CertificateMatcher::parse_subject("CN=ci,O=Foo\\x").is_err());
+		assert!(CertificateMatcher::parse_subject("CN=ci,O=Foo\\4").is_err());
+		assert!(CertificateMatcher::parse_subject("CN=\\FF").is_err());
+	}
+
+	#[test]
+	fn parse_alt_names() {
+		assert_eq!(AltName::parse("DNS:CI.Example.com"), Ok(AltName::Dns("ci.example.com".into())));
+		assert_eq!(AltName::parse("dns:ci.example.com"), Ok(AltName::Dns("ci.example.com".into())));
+		assert_eq!(AltName::parse("email:CI@example.com"), Ok(AltName::Email("CI@example.com".into())));
+		assert_eq!(AltName::parse("URI:spiffe://example.com/ci"), Ok(AltName::Uri("spiffe://example.com/ci".into())));
+		assert_eq!(AltName::parse("IP:10.0.0.1"), Ok(AltName::Ip("10.0.0.1".parse().unwrap())));
+		assert_eq!(AltName::parse("IP:fd00::1"), Ok(AltName::Ip("fd00::1".parse().unwrap())));
+		assert!(AltName::parse("ci.example.com").is_err());
+		assert!(AltName::parse("otherName:ci").is_err());
+		assert!(AltName::parse("IP:ci.example.com").is_err());
+	}
+
+	#[test]
+	fn alt_names_from_ip_bytes() {
+		assert_eq!(AltName::from_ip_bytes(&[10, 0, 0, 1]), Some(AltName::Ip("10.0.0.1".parse().unwrap())));
+		assert_eq!(AltName::from_ip_bytes(&[0xfd, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]), Some(AltName::Ip("fd00::1".parse().unwrap())));
+		assert_eq!(AltName::from_ip_bytes(&[10, 0, 0]), None);
+	}
+
+	#[test]
+	fn identity_needs_subject_or_alt_name() {
+		assert!(client_auth(vec![identity("ci", Some("CN=ci"), None)]).is_ok());
+		assert!(client_auth(vec![identity("ci", None, Some("DNS:ci.internal"))]).is_ok());
+		assert!(client_auth(vec![identity("ci", Some("CN=ci"), Some("DNS:ci.internal"))]).is_err());
+		assert!(client_auth(vec![identity("ci", None, None)]).is_err());
+	}
+
+	#[test]
+	fn client_identities() {
+		let client_auth = client_auth(vec![
+			identity("ci", None, Some("DNS:ci.internal")),
+			identity("mail", None, Some("email:ci@example.com")),
+			identity("uri", None, Some("URI:spiffe://example.com/ci")),
+			identity("ip", None, Some("IP:10.0.0.1")),
+			identity("deploy", Some("CN=deploy,O=Example"), None),
+		]).unwrap();
+		let settings = Settings {
+			default_certificate: CertificateFiles {
+				server_names: Vec::new(),
+				certificate_chain: "cert.pem".into(),
+				private_key: "key.pem".into(),
+			},
+			sni_certificates: Vec::new(),
+			client_auth: Some(client_auth),
+		};
+
+		let identity = |certificate| settings.client_identity(&certificate);
+		assert_eq!(identity(certificate(&[("CN", "x")], &[AltName::Dns("ci.internal".into())])).as_deref(), Some("ci"));
+		assert_eq!(identity(certificate(&[("CN", "x")], &[AltName::Email("ci@example.com".into())])).as_deref(), Some("mail"));
+		assert_eq!(identity(certificate(&[("CN", "x")], &[AltName::Uri("spiffe://example.com/ci".into())])).as_deref(), Some("uri"));
+		assert_eq!(identity(certificate(&[("CN", "x")], &[AltName::Ip("10.0.0.1".parse().unwrap())])).as_deref(), Some("ip"));
+		assert_eq!(identity(certificate(&[("O", "Example"), ("CN", "deploy")], &[])).as_deref(), Some("deploy"));
+
+		// The first matching identity wins.
+		assert_eq!(identity(certificate(&[("CN", "deploy"), ("O", "Example")], &[AltName::Dns("ci.internal".into())])).as_deref(), Some("ci"));
+
+		// Names of a different kind do not match.
+		assert_eq!(identity(certificate(&[("CN", "x")], &[AltName::Uri("ci.internal".into())])), None);
+		assert_eq!(identity(certificate(&[("CN", "ci.internal")], &[])), None);
+	}
+
+	#[test]
+	fn exact_server_names() {
+		assert!(server_name_matches("registry.example.com", "registry.example.com"));
+		assert!(!server_name_matches("registry.example.com", "example.com"));
+		assert!(!server_name_matches("registry.example.com", "www.registry.example.com"));
+	}
+
+	#[test]
+	fn wildcard_server_names() {
+		assert!(server_name_matches("*.example.com", "registry.example.com"));
+		assert!(server_name_matches("*.example.com", "a.example.com"));
+		// A wildcard matches exactly one label.
+		assert!(!server_name_matches("*.example.com", "example.com"));
+		assert!(!server_name_matches("*.example.com", "a.registry.example.com"));
+		assert!(!server_name_matches("*.example.com", ".example.com"));
+		assert!(!server_name_matches("*.example.com", "registry.example.org"));
+	}
 }
@@ -0,0 +1,212 @@
+use std::path::Path;
+use std::pin::Pin;
+
+use openssl::ssl::{NameType, SniError, Ssl, SslContext, SslFiletype, SslVerifyMode};
+use openssl::x509::{X509Name, X509Ref};
+use openssl::x509::verify::X509VerifyFlags;
+use openssl::x509::store::X509Lookup;
+use tokio::net::TcpStream;
+use tokio_openssl::SslStream;
+
+use super::{AltName, ClientAuth, ClientCertificate, ReloadTimer, Settings};
+use crate::config;
+use crate::metrics::METRICS;
+
+pub struct TlsAcceptor {
+	settings: Settings,
+	context: SslContext,
+	reload_timer: ReloadTimer,
+}
+
+impl TlsAcceptor {
+	/// Create an acceptor from a configuration.
+	pub fn from_config(config: &config::Tls, config_dir: &Path) -> Result<Self, ()> {
+		let settings = Settings::from_config(config, config_dir)?;
+		let context = load_context(&settings)?;
+		Ok(Self {
+			settings,
+			context,
+			reload_timer: ReloadTimer::new(),
+		})
+	}
+
+	/// Reload all certificate chains, private keys and revocation lists from disk.
+	pub fn reload(&mut self) -> Result<(), ()> {
+		match load_context(&self.settings) {
+			Ok(context) => {
+				self.context = context;
+				self.reload_timer.succeeded();
+				Ok(())
+			},
+			Err(e) => {
+				self.reload_timer.failed();
+				Err(e)
+			}
+		}
+	}
+
+	/// Initialize a TLS session for an accepted connection.
+	///
+	/// This will automatically reload the TLS keys and certificates every 24 hours.
+	pub async fn accept(&mut self, connection: TcpStream) -> Result<SslStream<TcpStream>, ()> {
+		if self.reload_timer.is_due() {
+			self.settings.log_reload();
+			self.reload().ok();
+		}
+
+		let ssl = Ssl::new(&self.context)
+			.map_err(|e| log::error!("Failed to initialize TLS session: {}", e))?;
+		let mut stream = tokio_openssl::SslStream::new(ssl, connection)
+			.map_err(|e| log::error!("Failed to create TLS stream: {}", e))?;
+		Pin::new(&mut stream).accept()
+			.await
+			.map_err(|e| {
+				METRICS.record_tls_handshake_failure();
+				log::error!("TLS handshake failed: {}", e)
+			})?;
+		Ok(stream)
+	}
+
+	/// Get the registry user that the client certificate of a connection maps to.
+	///
+	/// Returns `None` if the client did not send a certificate, or if the certificate does not match any identity.
+	pub fn client_identity(&self, stream: &SslStream<TcpStream>) -> Option<String> {
+		self.settings.client_auth.as_ref()?;
+		let certificate = stream.ssl().peer_certificate()?;
+		let user = self.settings.client_identity(&client_certificate(&certificate));
+		if user.is_none() {
+			log::debug!("No identity configured for client certificate {:?}", certificate.subject_name());
+		}
+		user
+	}
+}
+
+/// Extract the names of a client certificate.
+fn client_certificate(certificate: &X509Ref) -> ClientCertificate {
+	let subject = certificate.subject_name().entries()
+		.map(|entry| {
+			let name = entry.object().nid().short_name().ok()?;
+			let value = entry.data().to_string().ok()?;
+			Some((super::attribute_name(name), value))
+		})
+		.collect::<Option<Vec<_>>>()
+		.map(|mut attributes| {
+			attributes.sort();
+			attributes
+		});
+
+	let alt_names = certificate.subject_alt_names()
+		.map(|names| {
+			names.iter()
+				.filter_map(|name| {
+					if let Some(dns) = name.dnsname() {
+						Some(AltName::Dns(dns.to_ascii_lowercase()))
+					} else if let Some(email) = name.email() {
+						Some(AltName::Email(email.to_string()))
+					} else if let Some(uri) = name.uri() {
+						Some(AltName::Uri(uri.to_string()))
+					} else {
+						AltName::from_ip_bytes(name.ipaddress()?)
+					}
+				})
+				.collect()
+		})
+		.unwrap_or_default();
+
+	ClientCertificate { subject, alt_names }
+}
+
+/// Load the default certificate and the SNI certificates into a single context.
+///
+/// The returned context selects the certificate during the handshake,
+/// based on the server name requested by the client.
+fn load_context(settings: &Settings) -> Result<SslContext, ()> {
+	let client_auth = settings.client_auth.as_ref();
+	let mut contexts = Vec::with_capacity(settings.sni_certificates.len());
+	for certificate in &settings.sni_certificates {
+		let mut builder = load_tls_files(&certificate.certificate_chain, &certificate.private_key)?;
+		if let Some(client_auth) = client_auth {
+			load_client_auth(&mut builder, client_auth)?;
+		}
+		contexts.push((certificate.server_names.clone(), builder.build()));
+	}
+
+	let default_certificate = &settings.default_certificate;
+	let mut builder = load_tls_files(&default_certificate.certificate_chain, &default_certificate.private_key)?;
+	if let Some(client_auth) = client_auth {
+		load_client_auth(&mut builder, client_auth)?;
+	}
+	if !contexts.is_empty() {
+		builder.set_servername_callback(move |ssl, _alert| {
+			let Some(server_name) = ssl.servername(NameType::HOST_NAME) else {
+				return Ok(());
+			};
+			let server_name = server_name.to_ascii_lowercase();
+			let context = contexts.iter()
+				.find(|(names, _)| names.iter().any(|pattern| super::server_name_matches(pattern, &server_name)));
+			if let Some((_, context)) = context {
+				ssl.set_ssl_context(context)
+					.map_err(|e| {
+						log::error!("Failed to select TLS certificate for {}: {}", server_name, e);
+						SniError::ALERT_FATAL
+					})?;
+			}
+			Ok(())
+		});
+	}
+	Ok(builder.build())
+}
+
+fn load_tls_files(certificate_chain: &Path, private_key: &Path) -> Result<openssl::ssl::SslContextBuilder, ()> {
+	let mut builder = mozilla_modern_v5()
+		.map_err(|e| log::error!("Failed to create OpenSSL context: {}", e))?;
+	builder.set_private_key_file(private_key, SslFiletype::PEM)
+		.map_err(|e| log::error!("Failed to load private key from {}: {}", private_key.display(), e))?;
+	builder.set_certificate_chain_file(certificate_chain)
+		.map_err(|e| log::error!("Failed to load certificate chain from {}: {}", certificate_chain.display(), e))?;
+	Ok(builder)
+}
+
+/// Configure a context to verify client certificates.
+///
+/// This is applied to every certificate of a listener, since the context is swapped when selecting a certificate by SNI.
+fn load_client_auth(builder: &mut openssl::ssl::SslContextBuilder, client_auth: &ClientAuth) -> Result<(), ()> {
+	let ca_certificates = &client_auth.ca_certificates;
+	let ca_names = X509Name::load_client_ca_file(ca_certificates)
+		.map_err(|e| log::error!("Failed to load client CA certificates from {}: {}", ca_certificates.display(), e))?;
+	builder.set_client_ca_list(ca_names);
+	builder.set_ca_file(ca_certificates)
+		.map_err(|e| log::error!("Failed to load client CA certificates from {}: {}", ca_certificates.display(), e))?;
+
+	if !client_auth.revocation_lists.is_empty() {
+		let store = builder.cert_store_mut();
+		let lookup = store.add_lookup(X509Lookup::file())
+			.map_err(|e| log::error!("Failed to create certificate lookup: {}", e))?;
+		for revocation_list in &client_auth.revocation_lists {
+			lookup.load_crl_file(revocation_list, SslFiletype::PEM)
+				.map_err(|e| log::error!("Failed to load certificate revocation list from {}: {}", revocation_list.display(), e))?;
+		}
+		store.set_flags(X509VerifyFlags::CRL_CHECK | X509VerifyFlags::CRL_CHECK_ALL)
+			.map_err(|e| log::error!("Failed to enable certificate revocation checks: {}", e))?;
+	}
+
+	// Session resumption requires a session ID context when verifying clients.
+	builder.set_session_id_context(b"palletizer")
+		.map_err(|e| log::error!("Failed to set TLS session ID context: {}", e))?;
+	if client_auth.optional {
+		builder.set_verify(SslVerifyMode::PEER);
+	} else {
+		builder.set_verify(SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT);
+	}
+	Ok(())
+}
+
+fn mozilla_modern_v5() -> Result<openssl::ssl::SslContextBuilder, openssl::error::ErrorStack> {
+	use openssl::ssl::{SslMethod, SslOptions};
+	let mut context = SslContext::builder(SslMethod::tls_server())?;
+	context.set_options(SslOptions::NO_SSL_MASK & !SslOptions::NO_TLSV1_3);
+	context.set_ciphersuites(
+		"TLS_AES_128_GCM_SHA256:TLS_AES_256_GCM_SHA384:TLS_CHACHA20_POLY1305_SHA256",
+	)?;
+	Ok(context)
+}
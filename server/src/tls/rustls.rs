@@ -0,0 +1,226 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::{CertificateDer, CertificateRevocationListDer, PrivateKeyDer};
+use rustls::pki_types::pem::PemObject;
+use rustls::server::{ClientHello, ResolvesServerCert, WebPkiClientVerifier};
+use rustls::sign::CertifiedKey;
+use rustls::{RootCertStore, ServerConfig};
+use tokio::net::TcpStream;
+use tokio_rustls::server::TlsStream;
+use x509_parser::certificate::X509Certificate;
+use x509_parser::extensions::GeneralName;
+
+use super::{AltName, CertificateFiles, ClientAuth, ClientCertificate, ReloadTimer, Settings};
+use crate::config;
+use crate::metrics::METRICS;
+
+pub struct TlsAcceptor {
+	settings: Settings,
+	acceptor: tokio_rustls::TlsAcceptor,
+	reload_timer: ReloadTimer,
+}
+
+impl TlsAcceptor {
+	/// Create an acceptor from a configuration.
+	pub fn from_config(config: &config::Tls, config_dir: &Path) -> Result<Self, ()> {
+		let settings = Settings::from_config(config, config_dir)?;
+		let acceptor = load_acceptor(&settings)?;
+		Ok(Self {
+			settings,
+			acceptor,
+			reload_timer: ReloadTimer::new(),
+		})
+	}
+
+	/// Reload all certificate chains, private keys and revocation lists from disk.
+	pub fn reload(&mut self) -> Result<(), ()> {
+		match load_acceptor(&self.settings) {
+			Ok(acceptor) => {
+				self.acceptor = acceptor;
+				self.reload_timer.succeeded();
+				Ok(())
+			},
+			Err(e) => {
+				self.reload_timer.failed();
+				Err(e)
+			}
+		}
+	}
+
+	/// Initialize a TLS session for an accepted connection.
+	///
+	/// This will automatically reload the TLS keys and certificates every 24 hours.
+	pub async fn accept(&mut self, connection: TcpStream) -> Result<TlsStream<TcpStream>, ()> {
+		if self.reload_timer.is_due() {
+			self.settings.log_reload();
+			self.reload().ok();
+		}
+
+		self.acceptor.accept(connection)
+			.await
+			.map_err(|e| {
+				METRICS.record_tls_handshake_failure();
+				log::error!("TLS handshake failed: {}", e)
+			})
+	}
+
+	/// Get the registry user that the client certificate of a connection maps to.
+	///
+	/// Returns `None` if the client did not send a certificate, or if the certificate does not match any identity.
+	pub fn client_identity(&self, stream: &TlsStream<TcpStream>) -> Option<String> {
+		self.settings.client_auth.as_ref()?;
+		let certificate = stream.get_ref().1.peer_certificates()?.first()?;
+		let (_, certificate) = x509_parser::parse_x509_certificate(certificate)
+			.map_err(|e| log::error!("Failed to parse client certificate: {}", e))
+			.ok()?;
+		let user = self.settings.client_identity(&client_certificate(&certificate));
+		if user.is_none() {
+			log::debug!("No identity configured for client certificate {}", certificate.subject());
+		}
+		user
+	}
+}
+
+/// Selects the certificate based on the server name requested by the client.
+#[derive(Debug)]
+struct CertificateResolver {
+	default: Arc<CertifiedKey>,
+	sni: Vec<(Vec<String>, Arc<CertifiedKey>)>,
+}
+
+impl ResolvesServerCert for CertificateResolver {
+	fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+		let Some(server_name) = client_hello.server_name() else {
+			return Some(self.default.clone());
+		};
+		let server_name = server_name.to_ascii_lowercase();
+		let certificate = self.sni.iter()
+			.find(|(names, _)| names.iter().any(|pattern| super::server_name_matches(pattern, &server_name)))
+			.map(|(_, certificate)| certificate)
+			.unwrap_or(&self.default);
+		Some(certificate.clone())
+	}
+}
+
+/// Extract the names of a client certificate.
+fn client_certificate(certificate: &X509Certificate) -> ClientCertificate {
+	let subject = certificate.subject().iter_attributes()
+		.map(|attribute| {
+			let name = x509_parser::objects::oid2abbrev(attribute.attr_type(), x509_parser::objects::oid_registry()).ok()?;
+			let value = attribute.as_str().ok()?;
+			Some((super::attribute_name(name), value.to_string()))
+		})
+		.collect::<Option<Vec<_>>>()
+		.map(|mut attributes| {
+			attributes.sort();
+			attributes
+		});
+
+	let alt_names = match certificate.subject_alternative_name() {
+		Ok(Some(extension)) => extension.value.general_names.iter()
+			.filter_map(|name| match name {
+				GeneralName::DNSName(dns) => Some(AltName::Dns(dns.to_ascii_lowercase())),
+				GeneralName::RFC822Name(email) => Some(AltName::Email(email.to_string())),
+				GeneralName::URI(uri) => Some(AltName::Uri(uri.to_string())),
+				GeneralName::IPAddress(address) => AltName::from_ip_bytes(address),
+				_ => None,
+			})
+			.collect(),
+		Ok(None) => Vec::new(),
+		Err(e) => {
+			log::error!("Failed to parse subject alternative names of client certificate: {}", e);
+			Vec::new()
+		},
+	};
+
+	ClientCertificate { subject, alt_names }
+}
+
+/// Load all certificates and client authentication settings into a single acceptor.
+fn load_acceptor(settings: &Settings) -> Result<tokio_rustls::TlsAcceptor, ()> {
+	let provider = Arc::new(mozilla_modern_v5());
+
+	let mut sni = Vec::with_capacity(settings.sni_certificates.len());
+	for certificate in &settings.sni_certificates {
+		sni.push((certificate.server_names.clone(), load_tls_files(certificate, &provider)?));
+	}
+	let resolver = CertificateResolver {
+		default: load_tls_files(&settings.default_certificate, &provider)?,
+		sni,
+	};
+
+	let builder = ServerConfig::builder_with_provider(provider.clone())
+		.with_protocol_versions(&[&rustls::version::TLS13])
+		.map_err(|e| log::error!("Failed to create TLS configuration: {}", e))?;
+	let builder = match &settings.client_auth {
+		None => builder.with_no_client_auth(),
+		Some(client_auth) => builder.with_client_cert_verifier(load_client_auth(client_auth, provider)?),
+	};
+	let config = builder.with_cert_resolver(Arc::new(resolver));
+	Ok(tokio_rustls::TlsAcceptor::from(Arc::new(config)))
+}
+
+fn load_tls_files(certificate: &CertificateFiles, provider: &CryptoProvider) -> Result<Arc<CertifiedKey>, ()> {
+	let private_key = &certificate.private_key;
+	let certificate_chain = &certificate.certificate_chain;
+	let key = PrivateKeyDer::from_pem_file(private_key)
+		.map_err(|e| log::error!("Failed to load private key from {}: {}", private_key.display(), e))?;
+	let chain = load_pem_file::<CertificateDer>(certificate_chain, "certificate chain")?;
+	let certified_key = CertifiedKey::from_der(chain, key, provider)
+		.map_err(|e| log::error!("Failed to load private key from {}: {}", private_key.display(), e))?;
+	Ok(Arc::new(certified_key))
+}
+
+/// Create a verifier for client certificates.
+fn load_client_auth(client_auth: &ClientAuth, provider: Arc<CryptoProvider>) -> Result<Arc<dyn rustls::server::danger::ClientCertVerifier>, ()> {
+	let ca_certificates = &client_auth.ca_certificates;
+	let mut roots = RootCertStore::empty();
+	for certificate in load_pem_file::<CertificateDer>(ca_certificates, "client CA certificates")? {
+		roots.add(certificate)
+			.map_err(|e| log::error!("Failed to load client CA certificates from {}: {}", ca_certificates.display(), e))?;
+	}
+
+	let mut revocation_lists = Vec::new();
+	for revocation_list in &client_auth.revocation_lists {
+		revocation_lists.extend(load_pem_file::<CertificateRevocationListDer>(revocation_list, "certificate revocation list")?);
+	}
+
+	let mut builder = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+		.with_crls(revocation_lists);
+	if client_auth.optional {
+		builder = builder.allow_unauthenticated();
+	}
+	builder.build()
+		.map_err(|e| log::error!("Failed to create client certificate verifier: {}", e))
+}
+
+/// Load all PEM items of one type from a file.
+///
+/// It is an error if the file does not contain any matching items.
+fn load_pem_file<T: PemObject>(path: &Path, description: &str) -> Result<Vec<T>, ()> {
+	let items = T::pem_file_iter(path)
+		.and_then(|items| items.collect::<Result<Vec<_>, _>>())
+		.map_err(|e| log::error!("Failed to load {} from {}: {}", description, path.display(), e))?;
+	if items.is_empty() {
+		log::error!("Failed to load {} from {}: no PEM items found", description, path.display());
+		return Err(());
+	}
+	Ok(items)
+}
+
+/// Create a crypto provider with the cipher suites of the Mozilla modern configuration, version 5.
+///
+/// The protocol version is restricted to TLS 1.3 when building the server configuration.
+fn mozilla_modern_v5() -> CryptoProvider {
+	use rustls::crypto::ring::cipher_suite;
+	CryptoProvider {
+		cipher_suites: vec![
+			cipher_suite::TLS13_AES_128_GCM_SHA256,
+			cipher_suite::TLS13_AES_256_GCM_SHA384,
+			cipher_suite::TLS13_CHACHA20_POLY1305_SHA256,
+		],
+		..rustls::crypto::ring::default_provider()
+	}
+}
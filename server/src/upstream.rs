@@ -0,0 +1,300 @@
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::{api_v1, config};
+
+/// The maximum time for a single request to the upstream registry.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long fetched index files are cached.
+const INDEX_CACHE_TTL: Duration = Duration::from_secs(60);
+
+/// The maximum number of cached index files.
+const INDEX_CACHE_SIZE: usize = 4096;
+
+/// An upstream registry to forward requests for unknown crates to.
+///
+/// The upstream registry can be accessed over HTTP(S) using the sparse protocol,
+/// or it can be a local directory with the same layout.
+pub struct Upstream {
+	/// The root of the sparse index of the upstream registry.
+	index: Location,
+
+	/// The download URL template of the upstream registry.
+	download: String,
+
+	/// The directory to resolve relative download paths against.
+	config_dir: PathBuf,
+
+	/// The HTTP client for requests to the upstream registry.
+	agent: ureq::Agent,
+
+	/// Recently fetched index files, by path relative to the index root.
+	index_cache: Mutex<HashMap<String, CachedIndexFile>>,
+}
+
+enum Location {
+	Url(String),
+	Path(PathBuf),
+}
+
+/// An index file in the cache.
+struct CachedIndexFile {
+	/// When the index file was fetched.
+	fetched: Instant,
+
+	/// The contents of the index file, or None if the upstream registry does not have it.
+	data: Option<Vec<u8>>,
+}
+
+impl Upstream {
+	/// Create an upstream from a configuration.
+	pub fn from_config(config: &config::Upstream, config_dir: &Path) -> Self {
+		let index = if is_url(&config.index) {
+			Location::Url(config.index.trim_end_matches('/').to_string())
+		} else {
+			Location::Path(config_dir.join(&config.index))
+		};
+		let agent = ureq::Agent::config_builder()
+			.timeout_global(Some(REQUEST_TIMEOUT))
+			.build()
+			.into();
+		Self {
+			index,
+			download: config.download.clone(),
+			config_dir: config_dir.to_path_buf(),
+			agent,
+			index_cache: Mutex::new(HashMap::new()),
+		}
+	}
+
+	/// Fetch an index file from the upstream registry.
+	///
+	/// Index files are cached for a short time, including the fact that the upstream registry does not have them.
+	///
+	/// Returns None if the upstream registry does not have the file.
+	pub async fn fetch_index_file(self: &Arc<Self>, rel_path: &str) -> Result<Option<Vec<u8>>, String> {
+		let upstream = self.clone();
+		let rel_path = rel_path.to_string();
+		tokio::task::spawn_blocking(move || upstream.fetch_index_file_blocking(&rel_path))
+			.await
+			.map_err(|e| format!("failed to join background task: {}", e))?
+	}
+
+	/// Fetch a crate from the upstream registry and verify the checksum.
+	///
+	/// The checksum is taken from the upstream index file at `index_path`.
+	///
+	/// Returns None if the upstream registry does not have the crate.
+	pub async fn fetch_crate(self: &Arc<Self>, index_path: &str, name: &str, version: &str) -> Result<Option<Vec<u8>>, String> {
+		let upstream = self.clone();
+		let index_path = index_path.to_string();
+		let name = name.to_string();
+		let version = version.to_string();
+		tokio::task::spawn_blocking(move || upstream.fetch_crate_blocking(&index_path, &name, &version))
+			.await
+			.map_err(|e| format!("failed to join background task: {}", e))?
+	}
+
+	fn fetch_index_file_blocking(&self, rel_path: &str) -> Result<Option<Vec<u8>>, String> {
+		if let Some(cached) = self.index_cache.lock().unwrap().get(rel_path) && cached.fetched.elapsed() < INDEX_CACHE_TTL {
+			return Ok(cached.data.clone());
+		}
+
+		let data = match &self.index {
+			Location::Url(url) => self.fetch_url(&format!("{}/{}", url, rel_path))?,
+			Location::Path(path) => read_file(&path.join(rel_path))?,
+		};
+
+		let mut cache = self.index_cache.lock().unwrap();
+		if cache.len() >= INDEX_CACHE_SIZE {
+			cache.retain(|_, cached| cached.fetched.elapsed() < INDEX_CACHE_TTL);
+		}
+		if cache.len() >= INDEX_CACHE_SIZE {
+			let oldest = cache.iter()
+				.min_by_key(|(_, cached)| cached.fetched)
+				.map(|(path, _)| path.clone());
+			if let Some(oldest) = oldest {
+				cache.remove(&oldest);
+			}
+		}
+		cache.insert(rel_path.to_string(), CachedIndexFile {
+			fetched: Instant::now(),
+			data: data.clone(),
+		});
+
+		Ok(data)
+	}
+
+	fn fetch_crate_blocking(&self, index_path: &str, name: &str, version: &str) -> Result<Option<Vec<u8>>, String> {
+		let index = match self.fetch_index_file_blocking(index_path)? {
+			Some(x) => x,
+			None => return Ok(None),
+		};
+
+		let checksum = match find_checksum(&index, name, version)? {
+			Some(x) => x,
+			None => return Ok(None),
+		};
+
+		let url = palletizer::index::expand_download_url(&self.download, name, version, &checksum);
+		let data = if is_url(&url) {
+			self.fetch_url(&url)?
+		} else {
+			read_file(&self.config_dir.join(&url))?
+		};
+		let data = match data {
+			Some(x) => x,
+			None => return Err(format!("upstream index contains {}-{}, but the crate could not be downloaded from {}", name, version, url)),
+		};
+
+		let actual_checksum = api_v1::compute_sha256_hex(&data);
+		if !actual_checksum.eq_ignore_ascii_case(&checksum) {
+			return Err(format!("checksum mismatch for {}-{} from {}: expected {}, got {}", name, version, url, checksum, actual_checksum));
+		}
+
+		Ok(Some(data))
+	}
+
+	/// Fetch data from a URL.
+	///
+	/// Returns None if the server responds with 404 Not Found.
+	fn fetch_url(&self, url: &str) -> Result<Option<Vec<u8>>, String> {
+		let response = match self.agent.get(url).call() {
+			Ok(x) => x,
+			Err(ureq::Error::StatusCode(404)) => return Ok(None),
+			Err(e) => return Err(format!("failed to fetch {}: {}", url, e)),
+		};
+		let mut data = Vec::new();
+		response.into_body()
+			.into_reader()
+			.read_to_end(&mut data)
+			.map_err(|e| format!("failed to read response from {}: {}", url, e))?;
+		Ok(Some(data))
+	}
+}
+
+/// Find the checksum of a crate version in the contents of an index file.
+///
+/// The entries are parsed loosely, so that fields that we do not know about do not cause problems.
+fn find_checksum(index: &[u8], name: &str, version: &str) -> Result<Option<String>, String> {
+	#[derive(serde::Deserialize)]
+	struct Entry {
+		name: String,
+		vers: String,
+		cksum: String,
+	}
+
+	for line in index.split(|&c| c == b'\n') {
+		if line.is_empty() {
+			continue;
+		}
+		let entry: Entry = serde_json::from_slice(line)
+			.map_err(|e| format!("failed to parse upstream index entry: {}", e))?;
+		if entry.name.eq_ignore_ascii_case(name) && entry.vers == version {
+			return Ok(Some(entry.cksum));
+		}
+	}
+
+	Ok(None)
+}
+
+fn is_url(location: &str) -> bool {
+	location.starts_with("http://") || location.starts_with("https://")
+}
+
+/// Read data from a file.
+///
+/// Returns None if the file does not exist.
+fn read_file(path: &Path) -> Result<Option<Vec<u8>>, String> {
+	match std::fs::read(path) {
+		Ok(x) => Ok(Some(x)),
+		Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+		Err(e) => Err(format!("failed to read {}: {}", path.display(), e)),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Create a stand-in upstream registry in a directory, with index files in `index/` and crate files in `crates/`.
+	fn local_upstream(dir: &Path) -> Upstream {
+		let config = config::Upstream {
+			index: "index".into(),
+			download: "crates/{crate}/{crate}-{version}.crate".into(),
+		};
+		std::fs::create_dir_all(dir.join("index/3/f")).unwrap();
+		std::fs::create_dir_all(dir.join("crates/foo")).unwrap();
+		Upstream::from_config(&config, dir)
+	}
+
+	/// Format an index entry for a crate with the given data.
+	fn index_entry(name: &str, version: &str, data: &[u8]) -> String {
+		format!(
+			"{{\"name\":\"{}\",\"vers\":\"{}\",\"deps\":[],\"cksum\":\"{}\",\"features\":{{}},\"yanked\":false,\"v\":2,\"rust_version\":\"1.70\"}}\n",
+			name,
+			version,
+			api_v1::compute_sha256_hex(data),
+		)
+	}
+
+	#[test]
+	fn fetch_index_file() {
+		let dir = tempfile::tempdir().unwrap();
+		let upstream = local_upstream(dir.path());
+		let entry = index_entry("foo", "0.1.0", b"foo 0.1.0");
+		std::fs::write(dir.path().join("index/3/f/foo"), &entry).unwrap();
+
+		assert_eq!(upstream.fetch_index_file_blocking("3/f/foo").unwrap(), Some(entry.into_bytes()));
+		assert_eq!(upstream.fetch_index_file_blocking("3/b/bar").unwrap(), None);
+	}
+
+	#[test]
+	fn index_files_are_cached() {
+		let dir = tempfile::tempdir().unwrap();
+		let upstream = local_upstream(dir.path());
+		let entry = index_entry("foo", "0.1.0", b"foo 0.1.0");
+		std::fs::write(dir.path().join("index/3/f/foo"), &entry).unwrap();
+		assert_eq!(upstream.fetch_index_file_blocking("3/b/bar").unwrap(), None);
+		assert_eq!(upstream.fetch_index_file_blocking("3/f/foo").unwrap(), Some(entry.clone().into_bytes()));
+
+		// Changes in the upstream registry are not seen until the cache entry expires.
+		std::fs::create_dir_all(dir.path().join("index/3/b")).unwrap();
+		std::fs::write(dir.path().join("index/3/b/bar"), index_entry("bar", "1.0.0", b"bar 1.0.0")).unwrap();
+		std::fs::write(dir.path().join("index/3/f/foo"), index_entry("foo", "0.2.0", b"foo 0.2.0")).unwrap();
+		assert_eq!(upstream.fetch_index_file_blocking("3/b/bar").unwrap(), None);
+		assert_eq!(upstream.fetch_index_file_blocking("3/f/foo").unwrap(), Some(entry.into_bytes()));
+
+		upstream.index_cache.lock().unwrap().get_mut("3/f/foo").unwrap().fetched -= INDEX_CACHE_TTL;
+		assert_eq!(upstream.fetch_index_file_blocking("3/f/foo").unwrap(), Some(index_entry("foo", "0.2.0", b"foo 0.2.0").into_bytes()));
+	}
+
+	#[test]
+	fn fetch_crate() {
+		let dir = tempfile::tempdir().unwrap();
+		let upstream = local_upstream(dir.path());
+		let index = index_entry("foo", "0.1.0", b"foo 0.1.0") + &index_entry("foo", "0.2.0", b"foo 0.2.0") + &index_entry("foo", "0.3.0", b"foo 0.3.0");
+		std::fs::write(dir.path().join("index/3/f/foo"), index).unwrap();
+		std::fs::write(dir.path().join("crates/foo/foo-0.1.0.crate"), "foo 0.1.0").unwrap();
+		std::fs::write(dir.path().join("crates/foo/foo-0.2.0.crate"), "corrupted").unwrap();
+
+		assert_eq!(upstream.fetch_crate_blocking("3/f/foo", "foo", "0.1.0").unwrap(), Some(b"foo 0.1.0".to_vec()));
+		assert!(upstream.fetch_crate_blocking("3/f/foo", "foo", "0.2.0").unwrap_err().contains("checksum mismatch"));
+		assert!(upstream.fetch_crate_blocking("3/f/foo", "foo", "0.3.0").unwrap_err().contains("could not be downloaded"));
+		assert_eq!(upstream.fetch_crate_blocking("3/f/foo", "foo", "0.4.0").unwrap(), None);
+		assert_eq!(upstream.fetch_crate_blocking("3/b/bar", "bar", "1.0.0").unwrap(), None);
+	}
+
+	#[test]
+	fn find_checksums() {
+		let index = index_entry("Foo", "0.1.0", b"foo 0.1.0") + &index_entry("foo", "0.2.0", b"foo 0.2.0");
+		assert_eq!(find_checksum(index.as_bytes(), "foo", "0.1.0").unwrap(), Some(api_v1::compute_sha256_hex(b"foo 0.1.0")));
+		assert_eq!(find_checksum(index.as_bytes(), "foo", "0.2.0").unwrap(), Some(api_v1::compute_sha256_hex(b"foo 0.2.0")));
+		assert_eq!(find_checksum(index.as_bytes(), "foo", "0.3.0").unwrap(), None);
+		assert!(find_checksum(b"not json\n", "foo", "0.1.0").is_err());
+	}
+}
@@ -0,0 +1,520 @@
+use libflate::gzip;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::path::{Component, Path};
+
+use crate::error::Error;
+use crate::{storage, util, Config, Registry};
+
+/// The version of the bundle format.
+const FORMAT_VERSION: u32 = 1;
+
+/// The path of the checksum manifest in the archive.
+const MANIFEST_PATH: &str = "manifest.json";
+
+/// The path of the registry configuration in the archive.
+const CONFIG_PATH: &str = "palletizer.toml";
+
+/// The path of the git bundle of the index repository in the archive.
+const INDEX_BUNDLE_PATH: &str = "index.bundle";
+
+/// Loose files from the registry root that are included in the archive if they exist.
+const EXTRA_FILES: &[&str] = &["owners.toml", "tokens.toml"];
+
+/// The checksum manifest of a bundle.
+///
+/// It lists all other files in the archive with their SHA-256 checksum.
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct Manifest {
+	version: u32,
+	files: BTreeMap<String, String>,
+}
+
+/// Summary of an exported or imported bundle.
+#[derive(Debug, Clone, Default)]
+pub struct Summary {
+	/// The number of crate files in the bundle.
+	pub crates: usize,
+
+	/// The total number of files in the bundle, including the manifest.
+	pub files: usize,
+}
+
+/// Export a registry to a gzipped tar archive.
+///
+/// The archive holds the registry configuration, a git bundle of the index repository,
+/// all crate files from the storage backend, the stored metadata, the owners and the tokens,
+/// and a manifest with the SHA-256 checksum of each file.
+///
+/// The registry is locked for the duration of the export,
+/// so no crate can be published, yanked or deleted while the archive is written.
+pub fn export(registry: &Registry, output: impl Write) -> Result<Summary, Error> {
+	let mut summary = Summary::default();
+	let mut manifest = Manifest { version: FORMAT_VERSION, files: BTreeMap::new() };
+
+	let mut lock = registry.lock_exclusive()?;
+
+	let output = gzip::Encoder::new(output)
+		.map_err(|e| Error::new(format!("failed to initialize gzip encoder: {}", e)))?;
+	let mut archive = tar::Builder::new(output);
+
+	// Read the config from the locked file, opening it again would block on our own lock.
+	let mut config = Vec::new();
+	lock.read_to_end(&mut config)
+		.map_err(|e| Error::new(format!("failed to read from {}: {}", registry.config_file().display(), e)))?;
+	append_file(&mut archive, &mut manifest, CONFIG_PATH, &config)?;
+
+	let index_bundle = write_git_bundle(registry.index_repo())?;
+	append_file(&mut archive, &mut manifest, INDEX_BUNDLE_PATH, &index_bundle)?;
+
+	let storage = registry.storage();
+	for object in storage.list("")? {
+		let data = storage.get(&object.key)?
+			.ok_or_else(|| Error::new(format!("crate file disappeared during export: {}", object.key)))?;
+		append_file(&mut archive, &mut manifest, &format!("crates/{}", object.key), &data)?;
+		summary.crates += 1;
+	}
+
+	let metadata_dir = registry.metadata_dir();
+	if metadata_dir.exists() {
+		for path in util::iter_files(&metadata_dir) {
+			let path = path?;
+			let rel_path = path.strip_prefix(&metadata_dir).unwrap_or(&path);
+			let archive_path = format!("metadata/{}", archive_path(rel_path)?);
+			append_file(&mut archive, &mut manifest, &archive_path, &util::read_file(&path)?)?;
+		}
+	}
+
+	for name in EXTRA_FILES {
+		let path = registry.path().join(name);
+		if path.exists() {
+			append_file(&mut archive, &mut manifest, name, &util::read_file(&path)?)?;
+		}
+	}
+
+	// Write the manifest last, so it can contain the checksums of all other files.
+	summary.files = manifest.files.len() + 1;
+	let manifest = serde_json::to_vec_pretty(&manifest)
+		.map_err(|e| Error::new(format!("failed to serialize bundle manifest: {}", e)))?;
+	append_entry(&mut archive, MANIFEST_PATH, &manifest)?;
+
+	let output = archive.into_inner()
+		.map_err(|e| Error::new(format!("failed to finish archive: {}", e)))?;
+	output.finish().into_result()
+		.map_err(|e| Error::new(format!("failed to finish gzip stream: {}", e)))?
+		.flush()
+		.map_err(|e| Error::new(format!("failed to flush archive: {}", e)))?;
+
+	drop(lock);
+	Ok(summary)
+}
+
+/// Import a registry from an archive created by [`export()`].
+///
+/// The checksums of all files are verified before anything is written.
+/// The target directory must not exist yet or it must be empty.
+///
+/// The archive is read twice: once to verify the checksums, and once to extract the files.
+pub fn import(archive_path: &Path, target: &Path) -> Result<(Registry, Summary), Error> {
+	if let Ok(mut entries) = std::fs::read_dir(target) && entries.next().is_some() {
+		return Err(Error::new(format!("refusing to import into {}: directory is not empty", target.display())));
+	}
+
+	// First pass: verify all checksums and parse the configuration.
+	let mut manifest = None;
+	let mut checksums = BTreeMap::new();
+	let mut config = None;
+	for_each_file(archive_path, |path, data| {
+		if path == MANIFEST_PATH {
+			let parsed: Manifest = serde_json::from_slice(&data)
+				.map_err(|e| Error::new(format!("failed to parse bundle manifest: {}", e)))?;
+			manifest = Some(parsed);
+			return Ok(());
+		}
+		if !is_known_path(path) {
+			return Err(Error::new(format!("unexpected file in archive: {}", path)));
+		}
+		if path == CONFIG_PATH {
+			config = Some(util::parse_toml::<Config>(&data, &CONFIG_PATH)?);
+		}
+		if checksums.insert(path.to_string(), util::compute_sha256_hex(&data)).is_some() {
+			return Err(Error::new(format!("archive contains {} more than once", path)));
+		}
+		Ok(())
+	})?;
+
+	let manifest = manifest.ok_or_else(|| Error::new(format!("archive does not contain {}", MANIFEST_PATH)))?;
+	if manifest.version != FORMAT_VERSION {
+		return Err(Error::new(format!("unsupported bundle format version: {}", manifest.version)));
+	}
+	let config = config.ok_or_else(|| Error::new(format!("archive does not contain {}", CONFIG_PATH)))?;
+	if !checksums.contains_key(INDEX_BUNDLE_PATH) {
+		return Err(Error::new(format!("archive does not contain {}", INDEX_BUNDLE_PATH)));
+	}
+	for (path, expected) in &manifest.files {
+		match checksums.get(path) {
+			None => return Err(Error::new(format!("archive does not contain {}, but it is listed in the manifest", path))),
+			Some(actual) if actual != expected => {
+				return Err(Error::new(format!("checksum mismatch for {}: expected {}, got {}", path, expected, actual)));
+			},
+			Some(_) => (),
+		}
+	}
+	if let Some(path) = checksums.keys().find(|x| !manifest.files.contains_key(x.as_str())) {
+		return Err(Error::new(format!("archive contains {}, but it is not listed in the manifest", path)));
+	}
+
+	// Second pass: extract the files.
+	// The checksums are verified again, in case the archive changed between the two passes.
+	let mut summary = Summary { crates: 0, files: manifest.files.len() + 1 };
+	util::create_dirs(target)?;
+	let storage = storage::open(&config.storage, &target.join(&config.crate_dir))?;
+	for_each_file(archive_path, |path, data| {
+		if path == MANIFEST_PATH {
+			return Ok(());
+		}
+		if manifest.files.get(path) != Some(&util::compute_sha256_hex(&data)) {
+			return Err(Error::new(format!("{} changed while importing it", archive_path.display())));
+		}
+
+		if path == INDEX_BUNDLE_PATH {
+			read_git_bundle(&data, &target.join(&config.index_dir))
+		} else if let Some(key) = path.strip_prefix("crates/") {
+			summary.crates += 1;
+			storage.put(key, &data)
+		} else if let Some(rel_path) = path.strip_prefix("metadata/") {
+			util::write_new_file(target.join(&config.metadata_dir).join(rel_path), data)
+		} else {
+			util::write_new_file(target.join(path), data)
+		}
+	})?;
+
+	let registry = Registry::open(target)?;
+	Ok((registry, summary))
+}
+
+/// Check if a path in the archive is one that we know how to import.
+fn is_known_path(path: &str) -> bool {
+	path == CONFIG_PATH
+		|| path == INDEX_BUNDLE_PATH
+		|| path.starts_with("crates/")
+		|| path.starts_with("metadata/")
+		|| EXTRA_FILES.contains(&path)
+}
+
+/// Call a function for each regular file in an archive.
+///
+/// Paths are checked to be relative and free of `..` components before the function is called.
+fn for_each_file(archive_file: &Path, mut fun: impl FnMut(&str, Vec<u8>) -> Result<(), Error>) -> Result<(), Error> {
+	let file = util::open_file_read(archive_file)?;
+	let file = gzip::Decoder::new(file)
+		.map_err(|e| Error::new(format!("failed to initialize gzip decoder for {}: {}", archive_file.display(), e)))?;
+	let mut archive = tar::Archive::new(file);
+
+	let entries = archive.entries()
+		.map_err(|e| Error::new(format!("failed to read archive header: {}", e)))?;
+	for entry in entries {
+		let mut entry = entry.map_err(|e| Error::new(format!("failed to read archive entry header: {}", e)))?;
+		if !entry.header().entry_type().is_file() {
+			continue;
+		}
+
+		let entry_path = entry.path()
+			.map_err(|e| Error::new(format!("archive entry contains non-UTF8 path: {}", e)))?
+			.to_path_buf();
+		if !entry_path.components().all(|x| matches!(x, Component::Normal(_))) {
+			return Err(Error::new(format!("archive entry has an invalid path: {}", entry_path.display())));
+		}
+		let path = archive_path(&entry_path)?;
+
+		let mut data = Vec::new();
+		entry.read_to_end(&mut data)
+			.map_err(|e| Error::new(format!("failed to read archive data for {}: {}", path, e)))?;
+		fun(&path, data)?;
+	}
+
+	Ok(())
+}
+
+/// Add a file to the archive and record the checksum in the manifest.
+fn append_file<W: Write>(archive: &mut tar::Builder<W>, manifest: &mut Manifest, path: &str, data: &[u8]) -> Result<(), Error> {
+	append_entry(archive, path, data)?;
+	manifest.files.insert(path.to_string(), util::compute_sha256_hex(data));
+	Ok(())
+}
+
+/// Add a file to the archive.
+fn append_entry<W: Write>(archive: &mut tar::Builder<W>, path: &str, data: &[u8]) -> Result<(), Error> {
+	let mut header = tar::Header::new_gnu();
+	header.set_size(data.len() as u64);
+	header.set_mode(0o644);
+	header.set_cksum();
+	archive.append_data(&mut header, path, data)
+		.map_err(|e| Error::new(format!("failed to add {} to archive: {}", path, e)))
+}
+
+/// Convert a relative path to a path in the archive, using `/` as separator.
+fn archive_path(path: &Path) -> Result<String, Error> {
+	let mut output = String::new();
+	for component in path.components() {
+		let component = component.as_os_str().to_str()
+			.ok_or_else(|| Error::new(format!("invalid UTF-8 in path: {}", path.display())))?;
+		if !output.is_empty() {
+			output.push('/');
+		}
+		output.push_str(component);
+	}
+	Ok(output)
+}
+
+/// Create a git bundle with all branches of a repository.
+///
+/// The bundle can be used with `git clone` and `git fetch`, or imported with [`read_git_bundle()`].
+fn write_git_bundle(repo: &git2::Repository) -> Result<Vec<u8>, Error> {
+	let mut output = b"# v2 git bundle\n".to_vec();
+	let mut walk = repo.revwalk()
+		.map_err(|e| Error::new(format!("failed to walk index repository history: {}", e)))?;
+
+	let head = repo.head()
+		.map_err(|e| Error::new(format!("failed to determine HEAD of index repository: {}", e)))?;
+	let head = head.target()
+		.ok_or_else(|| Error::new("HEAD of index repository is not a direct reference".into()))?;
+	writeln!(output, "{} HEAD", head).unwrap();
+	walk.push(head)
+		.map_err(|e| Error::new(format!("failed to walk index repository history: {}", e)))?;
+
+	let branches = repo.references_glob("refs/heads/*")
+		.map_err(|e| Error::new(format!("failed to list branches of index repository: {}", e)))?;
+	for branch in branches {
+		let branch = branch.map_err(|e| Error::new(format!("failed to read branch of index repository: {}", e)))?;
+		let (Some(name), Some(target)) = (branch.name(), branch.target()) else {
+			continue;
+		};
+		writeln!(output, "{} {}", target, name).unwrap();
+		walk.push(target)
+			.map_err(|e| Error::new(format!("failed to walk index repository history: {}", e)))?;
+	}
+	output.push(b'\n');
+
+	let mut pack_builder = repo.packbuilder()
+		.map_err(|e| Error::new(format!("failed to create pack builder: {}", e)))?;
+	pack_builder.insert_walk(&mut walk)
+		.map_err(|e| Error::new(format!("failed to add commits to pack: {}", e)))?;
+	let mut pack = git2::Buf::new();
+	pack_builder.write_buf(&mut pack)
+		.map_err(|e| Error::new(format!("failed to write pack: {}", e)))?;
+	output.extend_from_slice(&pack);
+
+	Ok(output)
+}
+
+/// Create a new repository from a git bundle created by [`write_git_bundle()`].
+///
+/// The branches and HEAD are restored, and HEAD is checked out.
+fn read_git_bundle(data: &[u8], path: &Path) -> Result<(), Error> {
+	let data = data.strip_prefix(b"# v2 git bundle\n")
+		.ok_or_else(|| Error::new("index bundle is not a v2 git bundle".into()))?;
+
+	// Parse the references, up to the empty line that separates them from the pack.
+	let mut refs = Vec::new();
+	let mut rest = data;
+	loop {
+		let end = rest.iter().position(|&c| c == b'\n')
+			.ok_or_else(|| Error::new("unexpected end of index bundle header".into()))?;
+		let line = std::str::from_utf8(&rest[..end])
+			.map_err(|_| Error::new("index bundle header contains invalid UTF-8".into()))?;
+		rest = &rest[end + 1..];
+		if line.is_empty() {
+			break;
+		}
+		if line.starts_with('-') {
+			return Err(Error::new("index bundle requires prerequisite commits".into()));
+		}
+		let (oid, name) = line.split_once(' ')
+			.ok_or_else(|| Error::new(format!("invalid reference in index bundle: {}", line)))?;
+		let oid = git2::Oid::from_str(oid)
+			.map_err(|e| Error::new(format!("invalid object ID in index bundle: {}: {}", oid, e)))?;
+		refs.push((oid, name.to_string()));
+	}
+
+	util::create_dirs(path)?;
+	let repo = git2::Repository::init(path)
+		.map_err(|e| Error::new(format!("failed to initialize git repository at {}: {}", path.display(), e)))?;
+
+	let odb = repo.odb()
+		.map_err(|e| Error::new(format!("failed to open object database of {}: {}", path.display(), e)))?;
+	let mut pack_writer = odb.packwriter()
+		.map_err(|e| Error::new(format!("failed to create pack writer for {}: {}", path.display(), e)))?;
+	pack_writer.write_all(rest)
+		.map_err(|e| Error::new(format!("failed to write pack to {}: {}", path.display(), e)))?;
+	pack_writer.commit()
+		.map_err(|e| Error::new(format!("failed to commit pack to {}: {}", path.display(), e)))?;
+
+	let mut head = None;
+	for (oid, name) in &refs {
+		if name == "HEAD" {
+			head = Some(*oid);
+			continue;
+		}
+		repo.reference(name, *oid, true, "palletizer import")
+			.map_err(|e| Error::new(format!("failed to create reference {} in {}: {}", name, path.display(), e)))?;
+	}
+
+	// Point HEAD at the branch it pointed at before, if we can figure out which one that was.
+	let head = head.ok_or_else(|| Error::new("index bundle does not contain HEAD".into()))?;
+	let result = match refs.iter().find(|(oid, name)| *oid == head && name.starts_with("refs/heads/")) {
+		Some((_, name)) => repo.set_head(name),
+		None => repo.set_head_detached(head),
+	};
+	result.map_err(|e| Error::new(format!("failed to set HEAD of {}: {}", path.display(), e)))?;
+
+	repo.checkout_head(Some(git2::build::CheckoutBuilder::new().force()))
+		.map_err(|e| Error::new(format!("failed to check out HEAD in {}: {}", path.display(), e)))?;
+
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::metadata::Metadata;
+	use crate::test_util;
+
+	/// Create a registry with several crates, a yanked version, metadata, owners and a token.
+	///
+	/// Returns the registry and the token.
+	fn example_registry(path: &Path) -> (Registry, String) {
+		let mut registry = test_util::init_registry(path);
+		test_util::add_crate(&mut registry, "foo", "0.1.0", b"foo 0.1.0");
+		test_util::add_crate(&mut registry, "foo", "0.2.0", b"foo 0.2.0");
+		test_util::add_crate(&mut registry, "bar", "1.0.0", b"bar 1.0.0");
+		test_util::add_crate(&mut registry, "a", "0.0.1", b"a 0.0.1");
+		registry.yank_crate("foo", "0.1.0").unwrap();
+		registry.store_metadata(&Metadata {
+			name: "foo".into(),
+			version: "0.2.0".into(),
+			description: Some("The foo crate".into()),
+			..Default::default()
+		}).unwrap();
+		registry.add_crate_owners("foo", &["alice", "bob"]).unwrap();
+		let token = registry.create_token("alice-laptop", "alice").unwrap();
+		(registry, token)
+	}
+
+	/// Export a registry to a file.
+	fn export_to_file(registry: &Registry, path: &Path) -> Summary {
+		let file = std::fs::File::create(path).unwrap();
+		export(registry, file).unwrap()
+	}
+
+	/// Rewrite an archive, passing the contents of each file through a function.
+	fn rewrite_archive(path: &Path, mut modify: impl FnMut(&str, Vec<u8>) -> Vec<u8>) {
+		let mut files = Vec::new();
+		for_each_file(path, |path, data| {
+			files.push((path.to_string(), modify(path, data)));
+			Ok(())
+		}).unwrap();
+
+		let output = gzip::Encoder::new(std::fs::File::create(path).unwrap()).unwrap();
+		let mut archive = tar::Builder::new(output);
+		for (path, data) in &files {
+			append_entry(&mut archive, path, data).unwrap();
+		}
+		archive.into_inner().unwrap().finish().into_result().unwrap();
+	}
+
+	/// Get the commit IDs of the history of HEAD.
+	fn history(repo: &git2::Repository) -> Vec<git2::Oid> {
+		let mut walk = repo.revwalk().unwrap();
+		walk.push_head().unwrap();
+		walk.collect::<Result<_, _>>().unwrap()
+	}
+
+	#[test]
+	fn round_trip() {
+		let dir = tempfile::tempdir().unwrap();
+		let (registry, token) = example_registry(&dir.path().join("registry"));
+		let archive = dir.path().join("backup.tar.gz");
+		let exported = export_to_file(&registry, &archive);
+		assert_eq!(exported.crates, 4);
+
+		let (restored, imported) = import(&archive, &dir.path().join("restored")).unwrap();
+		assert_eq!(imported.crates, 4);
+		assert_eq!(imported.files, exported.files);
+
+		// The complete index history is restored, and HEAD is checked out.
+		assert_eq!(history(restored.index_repo()), history(registry.index_repo()));
+		assert_eq!(restored.index_repo().head().unwrap().name(), registry.index_repo().head().unwrap().name());
+		let foo = restored.read_index("foo").unwrap();
+		assert_eq!(foo.iter().map(|x| (x.version.as_str(), x.yanked)).collect::<Vec<_>>(), [("0.1.0", true), ("0.2.0", false)]);
+		assert!(restored.contains_crate("a"));
+
+		for (name, version, data) in [("foo", "0.1.0", "foo 0.1.0"), ("foo", "0.2.0", "foo 0.2.0"), ("bar", "1.0.0", "bar 1.0.0"), ("a", "0.0.1", "a 0.0.1")] {
+			assert_eq!(restored.read_crate(name, version).unwrap().as_deref(), Some(data.as_bytes()));
+		}
+		assert_eq!(restored.read_metadata("foo").unwrap()[0].description.as_deref(), Some("The foo crate"));
+		assert_eq!(restored.crate_owners("foo").unwrap(), ["alice", "bob"]);
+		assert_eq!(restored.authenticate(&token).unwrap().as_deref(), Some("alice"));
+
+		let report = restored.fsck().unwrap();
+		assert!(report.is_ok(), "{}", report.to_json());
+	}
+
+	#[test]
+	fn import_refuses_non_empty_target() {
+		let dir = tempfile::tempdir().unwrap();
+		let (registry, _token) = example_registry(&dir.path().join("registry"));
+		let archive = dir.path().join("backup.tar.gz");
+		export_to_file(&registry, &archive);
+
+		let target = dir.path().join("restored");
+		util::write_new_file(target.join("existing"), "data").unwrap();
+		assert!(import(&archive, &target).is_err());
+	}
+
+	#[test]
+	fn import_verifies_checksums_before_writing() {
+		let dir = tempfile::tempdir().unwrap();
+		let (registry, _token) = example_registry(&dir.path().join("registry"));
+		let archive = dir.path().join("backup.tar.gz");
+		export_to_file(&registry, &archive);
+
+		// Tamper with the checksum of a crate file that comes after other files in the archive.
+		rewrite_archive(&archive, |path, data| {
+			if path != MANIFEST_PATH {
+				return data;
+			}
+			let mut manifest: Manifest = serde_json::from_slice(&data).unwrap();
+			let checksum = manifest.files.get_mut("crates/foo/foo-0.2.0.crate").unwrap();
+			*checksum = util::compute_sha256_hex("something else");
+			serde_json::to_vec(&manifest).unwrap()
+		});
+
+		let target = dir.path().join("restored");
+		let error = import(&archive, &target).err().unwrap();
+		assert!(error.to_string().contains("checksum mismatch for crates/foo/foo-0.2.0.crate"), "{}", error);
+		assert!(!target.exists());
+	}
+
+	#[test]
+	fn import_refuses_unlisted_files() {
+		let dir = tempfile::tempdir().unwrap();
+		let (registry, _token) = example_registry(&dir.path().join("registry"));
+		let archive = dir.path().join("backup.tar.gz");
+		export_to_file(&registry, &archive);
+
+		rewrite_archive(&archive, |path, data| {
+			if path != MANIFEST_PATH {
+				return data;
+			}
+			let mut manifest: Manifest = serde_json::from_slice(&data).unwrap();
+			manifest.files.remove("owners.toml");
+			serde_json::to_vec(&manifest).unwrap()
+		});
+
+		let target = dir.path().join("restored");
+		assert!(import(&archive, &target).is_err());
+		assert!(!target.exists());
+	}
+}
@@ -25,6 +25,14 @@ pub struct Config {
 	/// Relative paths are resolved relative to directory that contains the config file.
 	pub crate_dir: PathBuf,
 
+	/// The path where additional crate metadata is stored.
+	///
+	/// This holds metadata that is not part of the index, like the description and readme of each crate version.
+	///
+	/// Relative paths are resolved relative to directory that contains the config file.
+	#[serde(default = "default_metadata_dir")]
+	pub metadata_dir: PathBuf,
+
 	/// Allowed external registries for crates in this registry.
 	///
 	/// Packages with dependencies from other registries will be refused.
@@ -32,6 +40,89 @@ pub struct Config {
 	/// Each entry should be the full URL of the index repository of an external registry.
 	#[serde(default = "Default::default", skip_serializing_if = "Vec::is_empty")]
 	pub allowed_registries: Vec<String>,
+
+	/// Require authentication for all operations, including index and crate downloads.
+	///
+	/// See also https://doc.rust-lang.org/cargo/reference/registries.html#index-format
+	#[serde(default, skip_serializing_if = "is_false")]
+	pub auth_required: bool,
+
+	/// The storage backend for crate files.
+	///
+	/// By default, crate files are stored on the local filesystem in `crate_dir`.
+	#[serde(default, skip_serializing_if = "StorageConfig::is_filesystem")]
+	pub storage: StorageConfig,
+}
+
+/// The storage backend for crate files.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum StorageConfig {
+	/// Store crate files on the local filesystem in the crate directory.
+	#[default]
+	Filesystem,
+
+	/// Store crate files in an S3-compatible object store.
+	S3(S3Config),
+}
+
+/// The configuration of an S3-compatible object store.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct S3Config {
+	/// The URL of the S3 endpoint, like `https://s3.eu-west-1.amazonaws.com` or `http://localhost:9000`.
+	pub endpoint: String,
+
+	/// The name of the bucket.
+	pub bucket: String,
+
+	/// The region of the bucket.
+	#[serde(default = "default_s3_region")]
+	pub region: String,
+
+	/// A prefix for all object keys.
+	#[serde(default, skip_serializing_if = "String::is_empty")]
+	pub prefix: String,
+
+	/// The access key ID.
+	///
+	/// If not set, the `AWS_ACCESS_KEY_ID` environment variable is used.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub access_key_id: Option<String>,
+
+	/// The secret access key.
+	///
+	/// If not set, the `AWS_SECRET_ACCESS_KEY` environment variable is used.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub secret_access_key: Option<String>,
+
+	/// Use path-style URLs (`endpoint/bucket/key`) instead of virtual-hosted-style URLs (`bucket.endpoint/key`).
+	///
+	/// Most self-hosted object stores, like MinIO, need path-style URLs.
+	#[serde(default = "default_true")]
+	pub path_style: bool,
+}
+
+impl StorageConfig {
+	fn is_filesystem(&self) -> bool {
+		matches!(self, Self::Filesystem)
+	}
+}
+
+fn default_s3_region() -> String {
+	String::from("us-east-1")
+}
+
+fn default_true() -> bool {
+	true
+}
+
+fn default_metadata_dir() -> PathBuf {
+	PathBuf::from("metadata")
+}
+
+fn is_false(value: &bool) -> bool {
+	!value
 }
 
 impl Config {
@@ -41,9 +132,12 @@ impl Config {
 			api_url: "https://example.com".into(),
 			index_dir: "index".into(),
 			crate_dir: "crates".into(),
+			metadata_dir: "metadata".into(),
 			allowed_registries: vec![
 				"https://github.com/rust-lang/crates.io-index".into(),
 			],
+			auth_required: false,
+			storage: StorageConfig::Filesystem,
 		}
 	}
 }
@@ -56,11 +150,14 @@ impl Config {
 		struct CargoConfig<'a> {
 			dl: &'a str,
 			api: &'a str,
+			#[serde(rename = "auth-required", skip_serializing_if = "is_false")]
+			auth_required: bool,
 		}
 
 		let cargo_config = CargoConfig {
 			dl: &self.download_url,
 			api: &self.api_url,
+			auth_required: self.auth_required,
 		};
 
 		// Unwrap should be fine: contents is always JSON encodable.
@@ -69,3 +166,26 @@ impl Config {
 		json
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn cargo_json() {
+		let mut config = Config::example();
+		let json: serde_json::Value = serde_json::from_str(&config.cargo_json()).unwrap();
+		assert_eq!(json, serde_json::json!({
+			"dl": "https://example.com/crates/{crate}/{crate}-{version}.crate",
+			"api": "https://example.com",
+		}));
+
+		config.auth_required = true;
+		let json: serde_json::Value = serde_json::from_str(&config.cargo_json()).unwrap();
+		assert_eq!(json, serde_json::json!({
+			"dl": "https://example.com/crates/{crate}/{crate}-{version}.crate",
+			"api": "https://example.com",
+			"auth-required": true,
+		}));
+	}
+}
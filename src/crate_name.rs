@@ -0,0 +1,116 @@
+use crate::error::Error;
+
+/// The maximum length of a crate name.
+pub const MAX_CRATE_NAME_LEN: usize = 64;
+
+/// Crate names that can not be used.
+///
+/// This includes the names of the crates shipped with the Rust toolchain,
+/// and names that are reserved file names on Windows.
+const RESERVED_NAMES: &[&str] = &[
+	"alloc", "core", "proc-macro", "std", "test",
+	"con", "prn", "aux", "nul",
+	"com1", "com2", "com3", "com4", "com5", "com6", "com7", "com8", "com9",
+	"lpt1", "lpt2", "lpt3", "lpt4", "lpt5", "lpt6", "lpt7", "lpt8", "lpt9",
+];
+
+/// Check if a crate name is valid for new crates.
+///
+/// Crate names must start with an ASCII letter, and may only contain ASCII letters, digits, `-` and `_`.
+/// They may be at most [`MAX_CRATE_NAME_LEN`] characters long, and they can not be a reserved name.
+pub fn validate_crate_name(name: &str) -> Result<(), Error> {
+	let first = match name.chars().next() {
+		Some(x) => x,
+		None => return Err(Error::new("crate name can not be empty".into())),
+	};
+	if !first.is_ascii_alphabetic() {
+		return Err(Error::new(format!("invalid crate name `{}`: crate names must start with an ASCII letter", name)));
+	}
+	if let Some(c) = name.chars().find(|&c| !is_crate_name_char(c)) {
+		return Err(Error::new(format!(
+			"invalid crate name `{}`: invalid character {:?}, only ASCII letters, digits, `-` and `_` are allowed",
+			name, c,
+		)));
+	}
+	if name.len() > MAX_CRATE_NAME_LEN {
+		return Err(Error::new(format!("invalid crate name `{}`: crate names may be at most {} characters long", name, MAX_CRATE_NAME_LEN)));
+	}
+	if RESERVED_NAMES.contains(&canonical_crate_name(name).as_str()) {
+		return Err(Error::new(format!("invalid crate name `{}`: the name is reserved", name)));
+	}
+	Ok(())
+}
+
+/// Get the canonical form of a crate name.
+///
+/// Crate names that have the same canonical form are considered the same crate.
+/// The canonical form is lowercase and uses `-` instead of `_`.
+pub fn canonical_crate_name(name: &str) -> String {
+	name.to_ascii_lowercase().replace('_', "-")
+}
+
+/// Check if a name could be the name of an existing crate.
+///
+/// This only checks that the name is not empty and only contains characters that are allowed in crate names.
+/// Unlike [`validate_crate_name()`], it accepts crates that were added before the current rules were enforced.
+pub fn is_plausible_crate_name(name: &str) -> bool {
+	!name.is_empty() && name.chars().all(is_crate_name_char)
+}
+
+/// Check if a character is allowed in crate names.
+fn is_crate_name_char(c: char) -> bool {
+	c.is_ascii_alphanumeric() || c == '-' || c == '_'
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn valid_names() {
+		for name in ["a", "foo", "foo-bar", "foo_bar", "Foo2", "a123456789"] {
+			assert!(validate_crate_name(name).is_ok(), "{name}");
+		}
+		assert!(validate_crate_name(&"a".repeat(MAX_CRATE_NAME_LEN)).is_ok());
+	}
+
+	#[test]
+	fn invalid_names() {
+		for name in ["", "1foo", "-foo", "_foo", "foo bar", "foo.bar", "foo/bar", "føø"] {
+			assert!(validate_crate_name(name).is_err(), "{name}");
+		}
+		assert!(validate_crate_name(&"a".repeat(MAX_CRATE_NAME_LEN + 1)).is_err());
+	}
+
+	#[test]
+	fn reserved_names() {
+		for &name in RESERVED_NAMES {
+			assert!(validate_crate_name(name).is_err(), "{name}");
+		}
+		// Reserved names are matched on the canonical name.
+		for name in ["STD", "Proc_Macro", "Nul", "LPT1"] {
+			assert!(validate_crate_name(name).is_err(), "{name}");
+		}
+		for name in ["stdx", "core2", "com10"] {
+			assert!(validate_crate_name(name).is_ok(), "{name}");
+		}
+	}
+
+	#[test]
+	fn canonical_names() {
+		assert_eq!(canonical_crate_name("foo"), "foo");
+		assert_eq!(canonical_crate_name("Foo_Bar"), "foo-bar");
+		assert_eq!(canonical_crate_name("foo-bar"), "foo-bar");
+		assert_eq!(canonical_crate_name("FOO__BAR"), "foo--bar");
+	}
+
+	#[test]
+	fn plausible_names() {
+		for name in ["foo", "1foo", "_foo", "Foo-Bar"] {
+			assert!(is_plausible_crate_name(name), "{name}");
+		}
+		for name in ["", "foo/bar", "..", "foo bar", "føø"] {
+			assert!(!is_plausible_crate_name(name), "{name}");
+		}
+	}
+}
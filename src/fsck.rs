@@ -0,0 +1,342 @@
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+use crate::error::Error;
+use crate::{util, Registry};
+
+/// The result of a registry consistency check.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Report {
+	/// The number of index files that were checked.
+	pub crates: usize,
+
+	/// The number of index entries that were checked.
+	pub versions: usize,
+
+	/// The problems that were found.
+	pub problems: Vec<Problem>,
+}
+
+/// A problem found by a registry consistency check.
+///
+/// All paths are relative to the registry root.
+/// Crate files are identified by their key in the storage backend.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Problem {
+	/// An index file could not be read or parsed.
+	InvalidIndexFile {
+		path: PathBuf,
+		error: String,
+	},
+
+	/// An index file is not in the directory where Cargo expects it.
+	MisplacedIndexFile {
+		path: PathBuf,
+		expected_path: PathBuf,
+	},
+
+	/// An index file contains an entry for a different crate.
+	IndexEntryNameMismatch {
+		path: PathBuf,
+		name: String,
+		version: String,
+	},
+
+	/// The crate file for an index entry does not exist.
+	MissingCrateFile {
+		name: String,
+		version: String,
+		key: String,
+	},
+
+	/// The checksum of a crate file does not match the checksum in the index.
+	ChecksumMismatch {
+		name: String,
+		version: String,
+		key: String,
+		expected: String,
+		actual: String,
+	},
+
+	/// A crate file is not referenced by any index entry.
+	OrphanedCrateFile {
+		key: String,
+	},
+
+	/// A file in the work tree of the index repository differs from HEAD.
+	DirtyWorktree {
+		path: PathBuf,
+		status: Vec<&'static str>,
+	},
+}
+
+impl Report {
+	/// Check if the registry is consistent.
+	pub fn is_ok(&self) -> bool {
+		self.problems.is_empty()
+	}
+
+	/// Encode the report as JSON.
+	pub fn to_json(&self) -> String {
+		// Unwrap should be fine: contents is always JSON encodable.
+		serde_json::to_string_pretty(self).unwrap()
+	}
+}
+
+impl Problem {
+	/// Check if the problem can be repaired automatically.
+	pub fn is_repairable(&self) -> bool {
+		matches!(self, Self::MisplacedIndexFile { .. })
+	}
+}
+
+/// Check the consistency of a registry.
+pub fn check(registry: &Registry) -> Result<Report, Error> {
+	let mut report = Report::default();
+	let mut expected_crate_files = BTreeSet::new();
+
+	for index_file in util::iter_files(&registry.index_dir()) {
+		let index_file = index_file?;
+		let path_rel = index_file.strip_prefix(registry.path()).unwrap_or(&index_file).to_path_buf();
+		let rel_to_index = index_file.strip_prefix(registry.index_dir()).unwrap_or(&index_file);
+
+		// The config file is the only file in the index that is not an index file.
+		if rel_to_index == Path::new("config.json") {
+			continue;
+		}
+
+		report.crates += 1;
+		let file_name = match index_file.file_name().and_then(|x| x.to_str()) {
+			Some(x) => x,
+			None => {
+				report.problems.push(Problem::InvalidIndexFile { path: path_rel, error: "invalid UTF-8 in file name".into() });
+				continue;
+			},
+		};
+
+		let expected_path = registry.index_path(file_name);
+		if rel_to_index != expected_path {
+			report.problems.push(Problem::MisplacedIndexFile {
+				path: path_rel.clone(),
+				expected_path: registry.index_dir().strip_prefix(registry.path()).unwrap_or(&registry.index_dir()).join(expected_path),
+			});
+		}
+
+		let entries = match util::open_file_read(&index_file).and_then(|file| crate::registry::read_index(file, &index_file)) {
+			Ok(x) => x,
+			Err(e) => {
+				report.problems.push(Problem::InvalidIndexFile { path: path_rel, error: e.to_string() });
+				continue;
+			},
+		};
+
+		for entry in entries {
+			report.versions += 1;
+			if !entry.name.eq_ignore_ascii_case(file_name) {
+				report.problems.push(Problem::IndexEntryNameMismatch {
+					path: path_rel.clone(),
+					name: entry.name.clone(),
+					version: entry.version.clone(),
+				});
+			}
+
+			let crate_key = registry.crate_key(&entry.name, &entry.version);
+			expected_crate_files.insert(crate_key.clone());
+
+			let data = match registry.storage().get(&crate_key)? {
+				Some(x) => x,
+				None => {
+					report.problems.push(Problem::MissingCrateFile {
+						name: entry.name,
+						version: entry.version,
+						key: crate_key,
+					});
+					continue;
+				},
+			};
+
+			let actual = util::compute_sha256_hex(&data);
+			if !actual.eq_ignore_ascii_case(&entry.checksum_sha256) {
+				report.problems.push(Problem::ChecksumMismatch {
+					name: entry.name,
+					version: entry.version,
+					key: crate_key,
+					expected: entry.checksum_sha256,
+					actual,
+				});
+			}
+		}
+	}
+
+	for object in registry.storage().list("")? {
+		if !expected_crate_files.contains(&object.key) {
+			report.problems.push(Problem::OrphanedCrateFile { key: object.key });
+		}
+	}
+
+	check_worktree(registry, &mut report)?;
+
+	Ok(report)
+}
+
+/// Repair the problems from a report that can be repaired safely.
+///
+/// Currently, only misplaced index files are repaired, by moving them to the expected location.
+/// Index files are never moved if the expected location already exists.
+///
+/// Returns the problems that were repaired.
+pub fn repair(registry: &mut Registry, report: &Report) -> Result<Vec<Problem>, Error> {
+	let mut repaired = Vec::new();
+	for problem in &report.problems {
+		if let Problem::MisplacedIndexFile { path, expected_path } = problem {
+			let source = registry.path().join(path);
+			let target = registry.path().join(expected_path);
+			if target.exists() {
+				continue;
+			}
+			if let Some(parent) = target.parent() {
+				util::create_dirs(parent)?;
+			}
+			std::fs::rename(&source, &target)
+				.map_err(|e| Error::new(format!("failed to move {} to {}: {}", source.display(), target.display(), e)))?;
+
+			// Delete all empty parent directories of the old location.
+			for parent in source.ancestors().skip(1) {
+				if parent == registry.index_dir() || std::fs::remove_dir(parent).is_err() {
+					break;
+				}
+			}
+
+			let index_dir = registry.index_dir();
+			let files = [
+				source.strip_prefix(&index_dir).unwrap_or(&source),
+				target.strip_prefix(&index_dir).unwrap_or(&target),
+			];
+			util::add_commit(registry.index_repo(), &format!("Move {} to {}", files[0].display(), files[1].display()), &files)?;
+			repaired.push(problem.clone());
+		}
+	}
+	Ok(repaired)
+}
+
+/// Check that the work tree of the index repository matches HEAD.
+fn check_worktree(registry: &Registry, report: &mut Report) -> Result<(), Error> {
+	let mut options = git2::StatusOptions::new();
+	options.include_untracked(true);
+	options.recurse_untracked_dirs(true);
+	let statuses = registry.index_repo().statuses(Some(&mut options))
+		.map_err(|e| Error::new(format!("failed to get status of index repository: {}", e)))?;
+
+	let index_dir_rel = registry.index_dir().strip_prefix(registry.path()).unwrap_or(&registry.index_dir()).to_path_buf();
+	for entry in statuses.iter() {
+		let status = entry.status();
+		if status.is_empty() || status.is_ignored() {
+			continue;
+		}
+		let path = match entry.path() {
+			Some(x) => index_dir_rel.join(x),
+			None => index_dir_rel.join(String::from_utf8_lossy(entry.path_bytes()).as_ref()),
+		};
+		report.problems.push(Problem::DirtyWorktree {
+			path,
+			status: describe_status(status),
+		});
+	}
+	Ok(())
+}
+
+/// Describe a git status with human readable words.
+fn describe_status(status: git2::Status) -> Vec<&'static str> {
+	let flags = [
+		(git2::Status::INDEX_NEW, "index_new"),
+		(git2::Status::INDEX_MODIFIED, "index_modified"),
+		(git2::Status::INDEX_DELETED, "index_deleted"),
+		(git2::Status::INDEX_RENAMED, "index_renamed"),
+		(git2::Status::INDEX_TYPECHANGE, "index_typechange"),
+		(git2::Status::WT_NEW, "worktree_new"),
+		(git2::Status::WT_MODIFIED, "worktree_modified"),
+		(git2::Status::WT_DELETED, "worktree_deleted"),
+		(git2::Status::WT_RENAMED, "worktree_renamed"),
+		(git2::Status::WT_TYPECHANGE, "worktree_typechange"),
+		(git2::Status::CONFLICTED, "conflicted"),
+	];
+	flags.iter()
+		.filter(|(flag, _)| status.contains(*flag))
+		.map(|(_, name)| *name)
+		.collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_util;
+
+	#[test]
+	fn consistent_registry() {
+		let dir = tempfile::tempdir().unwrap();
+		let mut registry = test_util::init_registry(dir.path());
+		test_util::add_crate(&mut registry, "foo", "0.1.0", b"foo 0.1.0");
+		test_util::add_crate(&mut registry, "foo", "0.2.0", b"foo 0.2.0");
+		test_util::add_crate(&mut registry, "bar", "1.0.0", b"bar 1.0.0");
+
+		let report = check(&registry).unwrap();
+		assert!(report.is_ok(), "{}", report.to_json());
+		assert_eq!(report.crates, 2);
+		assert_eq!(report.versions, 3);
+	}
+
+	#[test]
+	fn crate_file_problems() {
+		let dir = tempfile::tempdir().unwrap();
+		let mut registry = test_util::init_registry(dir.path());
+		test_util::add_crate(&mut registry, "foo", "0.1.0", b"foo 0.1.0");
+		test_util::add_crate(&mut registry, "foo", "0.2.0", b"foo 0.2.0");
+		registry.storage().delete("foo/foo-0.1.0.crate").unwrap();
+		std::fs::write(registry.crate_dir().join("foo/foo-0.2.0.crate"), "corrupted").unwrap();
+		registry.storage().put("foo/foo-0.3.0.crate", b"foo 0.3.0").unwrap();
+
+		let report = check(&registry).unwrap();
+		assert_eq!(report.problems.len(), 3, "{}", report.to_json());
+		assert!(report.problems.iter().any(|x| matches!(x, Problem::MissingCrateFile { key, .. } if key == "foo/foo-0.1.0.crate")));
+		assert!(report.problems.iter().any(|x| matches!(x, Problem::ChecksumMismatch { key, .. } if key == "foo/foo-0.2.0.crate")));
+		assert!(report.problems.iter().any(|x| matches!(x, Problem::OrphanedCrateFile { key } if key == "foo/foo-0.3.0.crate")));
+		assert!(!report.problems.iter().any(|x| x.is_repairable()));
+	}
+
+	#[test]
+	fn dirty_worktree() {
+		let dir = tempfile::tempdir().unwrap();
+		let mut registry = test_util::init_registry(dir.path());
+		test_util::add_crate(&mut registry, "foo", "0.1.0", b"foo 0.1.0");
+		std::fs::write(registry.index_dir().join("3/f/foo"), "").unwrap();
+
+		let report = check(&registry).unwrap();
+		assert!(report.problems.iter().any(|x| matches!(x, Problem::DirtyWorktree { path, .. } if path == Path::new("index/3/f/foo"))));
+	}
+
+	#[test]
+	fn repair_misplaced_index_file() {
+		let dir = tempfile::tempdir().unwrap();
+		let mut registry = test_util::init_registry(dir.path());
+		test_util::add_crate(&mut registry, "foo", "0.1.0", b"foo 0.1.0");
+		let index_dir = registry.index_dir();
+		std::fs::create_dir_all(index_dir.join("fo/o")).unwrap();
+		std::fs::rename(index_dir.join("3/f/foo"), index_dir.join("fo/o/foo")).unwrap();
+		util::add_commit(registry.index_repo(), "Misplace foo", &["3/f/foo", "fo/o/foo"]).unwrap();
+
+		let report = check(&registry).unwrap();
+		assert_eq!(report.problems.len(), 1, "{}", report.to_json());
+		assert!(matches!(
+			&report.problems[0],
+			Problem::MisplacedIndexFile { path, expected_path } if path == Path::new("index/fo/o/foo") && expected_path == Path::new("index/3/f/foo"),
+		));
+
+		let repaired = repair(&mut registry, &report).unwrap();
+		assert_eq!(repaired.len(), 1);
+		assert!(!index_dir.join("fo").exists());
+		let report = check(&registry).unwrap();
+		assert!(report.is_ok(), "{}", report.to_json());
+	}
+}
@@ -0,0 +1,243 @@
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+use crate::error::Error;
+use crate::storage::Object;
+use crate::{util, Registry};
+
+/// What to do with crate files that are not referenced by the index.
+#[derive(Debug, Clone)]
+pub enum Mode {
+	/// Only report the garbage, without touching anything.
+	DryRun,
+
+	/// Delete the garbage.
+	Delete,
+
+	/// Move unreferenced crate files to a quarantine directory.
+	///
+	/// The files are stored in the quarantine directory using their storage key as relative path.
+	Quarantine(PathBuf),
+}
+
+/// The result of a garbage collection run.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Report {
+	/// The storage keys of the crate files that were not referenced by any index entry.
+	pub files: Vec<String>,
+
+	/// The empty directories in the crate directory, relative to the registry root.
+	///
+	/// This is only used with the filesystem storage backend.
+	pub directories: Vec<PathBuf>,
+
+	/// The total size of the unreferenced crate files in bytes.
+	///
+	/// For a dry run, this is the amount of space that would be freed.
+	pub freed_bytes: u64,
+}
+
+impl Report {
+	/// Encode the report as JSON.
+	pub fn to_json(&self) -> String {
+		// Unwrap should be fine: contents is always JSON encodable.
+		serde_json::to_string_pretty(self).unwrap()
+	}
+}
+
+/// Find and remove crate files and empty directories that are not referenced by the index.
+///
+/// The index file of a crate is locked while its crate files are cleaned up,
+/// so this can safely run while crates are being published.
+/// Hidden files and directories are never touched.
+pub fn collect(registry: &Registry, mode: &Mode) -> Result<Report, Error> {
+	let mut report = Report::default();
+
+	// Group the objects by the crate they belong to.
+	let mut crates: BTreeMap<String, Vec<Object>> = BTreeMap::new();
+	for object in registry.storage().list("")? {
+		match object.key.split_once('/') {
+			Some((name, _)) => crates.entry(name.to_string()).or_default().push(object),
+			// Crate files always live in a directory named after the crate.
+			None => remove_object(registry, &object, mode, &mut report)?,
+		}
+	}
+
+	// With the filesystem backend, also look for empty directories.
+	let crate_dir = registry.crate_dir();
+	if crate_dir.is_dir() {
+		for entry in std::fs::read_dir(&crate_dir).map_err(|e| Error::new(format!("failed to read directory {}: {}", crate_dir.display(), e)))? {
+			let entry = entry.map_err(|e| Error::new(format!("failed to read directory entry: {}", e)))?;
+			if !entry.file_type().map(|x| x.is_dir()).unwrap_or(false) {
+				continue;
+			}
+			if let Some(name) = entry.file_name().to_str() && !name.starts_with('.') {
+				crates.entry(name.to_string()).or_default();
+			}
+		}
+	}
+
+	for (name, objects) in &crates {
+		collect_crate(registry, name, objects, mode, &mut report)?;
+	}
+
+	Ok(report)
+}
+
+/// Clean up the crate files of a single crate.
+fn collect_crate(registry: &Registry, name: &str, objects: &[Object], mode: &Mode, report: &mut Report) -> Result<(), Error> {
+	// Lock the index file so no new version can be published while we look at the crate files.
+	// If the index file does not exist, a publish would fail anyway because of the existing crate files.
+	let index_path = registry.index_dir().join(registry.index_path(name));
+	// Keep the file open until we are done: closing it releases the lock.
+	let mut index_file = None;
+	let mut referenced = BTreeSet::new();
+	if index_path.exists() {
+		let file = index_file.insert(util::open_file_read_write(&index_path)?);
+		for entry in crate::registry::read_index(file, &index_path)? {
+			referenced.insert(registry.crate_key(&entry.name, &entry.version));
+		}
+	}
+
+	for object in objects {
+		if !referenced.contains(&object.key) {
+			remove_object(registry, object, mode, report)?;
+		}
+	}
+
+	let crate_dir = registry.crate_dir().join(name);
+	if crate_dir.is_dir() {
+		remove_empty_dirs(registry, &crate_dir, mode, report)?;
+	}
+
+	drop(index_file);
+	Ok(())
+}
+
+/// Delete or quarantine a single unreferenced crate file.
+fn remove_object(registry: &Registry, object: &Object, mode: &Mode, report: &mut Report) -> Result<(), Error> {
+	let storage = registry.storage();
+	match mode {
+		Mode::DryRun => (),
+		Mode::Delete => {
+			storage.delete(&object.key)?;
+		},
+		Mode::Quarantine(quarantine_dir) => {
+			let target = quarantine_dir.join(&object.key);
+			let data = storage.get(&object.key)?
+				.ok_or_else(|| Error::new(format!("failed to quarantine {}: object disappeared", object.key)))?;
+			util::write_new_file(&target, data)
+				.map_err(|e| Error::new(format!("failed to quarantine {}: {}", object.key, e)))?;
+			storage.delete(&object.key)?;
+		},
+	}
+
+	report.files.push(object.key.clone());
+	report.freed_bytes += object.size;
+	Ok(())
+}
+
+/// Remove all empty directories in a directory, including the directory itself.
+///
+/// Directories that only contain garbage count as empty.
+/// For a dry run, these are reported but not removed.
+///
+/// Returns true if the directory is (or would be) removed.
+fn remove_empty_dirs(registry: &Registry, dir: &Path, mode: &Mode, report: &mut Report) -> Result<bool, Error> {
+	let entries = std::fs::read_dir(dir)
+		.map_err(|e| Error::new(format!("failed to read directory {}: {}", dir.display(), e)))?;
+
+	let crate_dir = registry.crate_dir();
+	let mut empty = true;
+	for entry in entries {
+		let entry = entry.map_err(|e| Error::new(format!("failed to read directory entry: {}", e)))?;
+		let path = entry.path();
+		let is_hidden = entry.file_name().to_str().map(|x| x.starts_with('.')).unwrap_or(false);
+		let is_dir = entry.file_type().map(|x| x.is_dir()).unwrap_or(false);
+		if is_dir && !is_hidden {
+			if !remove_empty_dirs(registry, &path, mode, report)? {
+				empty = false;
+			}
+		} else if !matches!(mode, Mode::DryRun) || !report.files.iter().any(|x| crate_dir.join(x) == path) {
+			empty = false;
+		}
+	}
+
+	if empty {
+		if !matches!(mode, Mode::DryRun) {
+			std::fs::remove_dir(dir)
+				.map_err(|e| Error::new(format!("failed to delete directory {}: {}", dir.display(), e)))?;
+		}
+		report.directories.push(dir.strip_prefix(registry.path()).unwrap_or(dir).to_path_buf());
+	}
+	Ok(empty)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_util;
+
+	/// Create a registry with one referenced crate file, two unreferenced crate files and an empty directory.
+	fn registry_with_garbage(path: &Path) -> Registry {
+		let mut registry = test_util::init_registry(path);
+		test_util::add_crate(&mut registry, "foo", "0.1.0", b"foo 0.1.0");
+		registry.storage().put("foo/foo-0.2.0.crate", b"foo 0.2.0").unwrap();
+		registry.storage().put("bar/bar-1.0.0.crate", b"bar 1.0.0!").unwrap();
+		std::fs::create_dir_all(registry.crate_dir().join("baz")).unwrap();
+		util::write_new_file(registry.crate_dir().join(".upstream/qux/qux-1.0.0.crate"), "qux 1.0.0").unwrap();
+		registry
+	}
+
+	#[test]
+	fn dry_run() {
+		let dir = tempfile::tempdir().unwrap();
+		let registry = registry_with_garbage(dir.path());
+
+		let report = collect(&registry, &Mode::DryRun).unwrap();
+		assert_eq!(report.files, ["bar/bar-1.0.0.crate", "foo/foo-0.2.0.crate"]);
+		assert_eq!(report.directories, [Path::new("crates/bar"), Path::new("crates/baz")]);
+		assert_eq!(report.freed_bytes, 19);
+
+		assert!(registry.storage().exists("foo/foo-0.2.0.crate").unwrap());
+		assert!(registry.storage().exists("bar/bar-1.0.0.crate").unwrap());
+		assert!(registry.crate_dir().join("baz").is_dir());
+	}
+
+	#[test]
+	fn delete() {
+		let dir = tempfile::tempdir().unwrap();
+		let registry = registry_with_garbage(dir.path());
+
+		let report = collect(&registry, &Mode::Delete).unwrap();
+		assert_eq!(report.files, ["bar/bar-1.0.0.crate", "foo/foo-0.2.0.crate"]);
+		// The storage backend already removes the directory of the last crate file.
+		assert_eq!(report.directories, [Path::new("crates/baz")]);
+
+		assert!(registry.storage().exists("foo/foo-0.1.0.crate").unwrap());
+		assert!(!registry.storage().exists("foo/foo-0.2.0.crate").unwrap());
+		assert!(!registry.crate_dir().join("bar").exists());
+		assert!(!registry.crate_dir().join("baz").exists());
+		assert!(registry.crate_dir().join(".upstream/qux/qux-1.0.0.crate").is_file());
+		assert!(crate::fsck::check(&registry).unwrap().is_ok());
+
+		// Running again finds nothing.
+		let report = collect(&registry, &Mode::Delete).unwrap();
+		assert!(report.files.is_empty());
+		assert!(report.directories.is_empty());
+	}
+
+	#[test]
+	fn quarantine() {
+		let dir = tempfile::tempdir().unwrap();
+		let quarantine = tempfile::tempdir().unwrap();
+		let registry = registry_with_garbage(dir.path());
+
+		let report = collect(&registry, &Mode::Quarantine(quarantine.path().to_path_buf())).unwrap();
+		assert_eq!(report.files, ["bar/bar-1.0.0.crate", "foo/foo-0.2.0.crate"]);
+		assert!(!registry.storage().exists("foo/foo-0.2.0.crate").unwrap());
+		assert_eq!(std::fs::read(quarantine.path().join("foo/foo-0.2.0.crate")).unwrap(), b"foo 0.2.0");
+		assert_eq!(std::fs::read(quarantine.path().join("bar/bar-1.0.0.crate")).unwrap(), b"bar 1.0.0!");
+	}
+}
@@ -15,8 +15,21 @@ pub struct Entry {
 	#[serde(rename = "cksum")]
 	pub checksum_sha256: String,
 	pub features: BTreeMap<String, Vec<String>>,
+	/// Features using namespaced (`dep:foo`) or weak (`foo?/bar`) dependency syntax.
+	///
+	/// These are kept separate from the other features because older Cargo versions can not parse them.
+	#[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+	pub features2: BTreeMap<String, Vec<String>>,
 	pub yanked: bool,
-	pub links: Option<String>
+	pub links: Option<String>,
+	/// The minimum supported Rust version of the crate.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub rust_version: Option<String>,
+	/// The version of the index entry format.
+	///
+	/// This must be set to 2 if `features2` is not empty.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub v: Option<u32>,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -83,18 +96,74 @@ impl Entry {
 			add_deps(&mut dependencies, data.build_dependencies, DependencyKind::Build, Some(&target))?;
 		}
 
+		let (features, features2) = split_features(manifest.features);
 		Ok(Self {
 			name: manifest.package.name,
 			version: manifest.package.version,
 			checksum_sha256,
-			features: manifest.features,
+			v: format_version(&features2),
+			features,
+			features2,
 			yanked: false,
 			links: manifest.links,
+			rust_version: manifest.package.rust_version,
 			dependencies,
 		})
 	}
 }
 
+/// Split features into the `features` and `features2` fields of an index entry.
+///
+/// Features that use namespaced (`dep:foo`) or weak (`foo?/bar`) dependency syntax go into `features2`,
+/// all other features go into `features`.
+pub fn split_features(features: BTreeMap<String, Vec<String>>) -> (BTreeMap<String, Vec<String>>, BTreeMap<String, Vec<String>>) {
+	features.into_iter()
+		.partition(|(_name, values)| !values.iter().any(|x| is_v2_feature_value(x)))
+}
+
+/// Get the required index entry format version for the given `features2` field.
+pub fn format_version(features2: &BTreeMap<String, Vec<String>>) -> Option<u32> {
+	if features2.is_empty() {
+		None
+	} else {
+		Some(2)
+	}
+}
+
+/// Expand the markers in a download URL template from a registry `config.json`.
+///
+/// If the template contains no markers, `/{crate}/{version}/download` is appended,
+/// just like Cargo does.
+///
+/// The prefix is computed from the characters of the name, so invalid names can not cause a panic.
+pub fn expand_download_url(template: &str, name: &str, version: &str, checksum: &str) -> String {
+	let markers = ["{crate}", "{version}", "{prefix}", "{lowerprefix}", "{sha256-checksum}"];
+	if !markers.iter().any(|x| template.contains(x)) {
+		return format!("{}/{}/{}/download", template.trim_end_matches('/'), name, version);
+	}
+
+	let chars: Vec<char> = name.chars().collect();
+	let prefix = match chars.as_slice() {
+		[] => String::new(),
+		[_] => "1".to_string(),
+		[_, _] => "2".to_string(),
+		[a, _, _] => format!("3/{}", a),
+		[a, b, c, d, ..] => format!("{}{}/{}{}", a, b, c, d),
+	};
+
+	template
+		.replace("{crate}", name)
+		.replace("{version}", version)
+		.replace("{lowerprefix}", &prefix.to_ascii_lowercase())
+		.replace("{prefix}", &prefix)
+		.replace("{sha256-checksum}", checksum)
+}
+
+/// Check if a feature value requires the `features2` field.
+fn is_v2_feature_value(value: &str) -> bool {
+	value.starts_with("dep:") || value.contains("?/")
+}
+
 fn add_deps(out: &mut Vec<Dependency>, deps: BTreeMap<String, ManifestDependency>, kind: DependencyKind, target: Option<&str>) -> Result<(), Error> {
 	out.reserve(deps.len());
 	for (name, data) in deps {
@@ -113,3 +182,92 @@ fn add_deps(out: &mut Vec<Dependency>, deps: BTreeMap<String, ManifestDependency
 
 	Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn features(features: &[(&str, &[&str])]) -> BTreeMap<String, Vec<String>> {
+		features.iter()
+			.map(|(name, values)| (name.to_string(), values.iter().map(|x| x.to_string()).collect()))
+			.collect()
+	}
+
+	#[test]
+	fn expand_download_url_markers() {
+		let template = "https://example.com/{prefix}/{lowerprefix}/{crate}/{version}/{sha256-checksum}";
+		assert_eq!(expand_download_url(template, "a", "1.0.0", "abc"), "https://example.com/1/1/a/1.0.0/abc");
+		assert_eq!(expand_download_url(template, "ab", "1.0.0", "abc"), "https://example.com/2/2/ab/1.0.0/abc");
+		assert_eq!(expand_download_url(template, "Abc", "1.0.0", "abc"), "https://example.com/3/A/3/a/Abc/1.0.0/abc");
+		assert_eq!(expand_download_url(template, "FooBar", "1.0.0", "abc"), "https://example.com/Fo/oB/fo/ob/FooBar/1.0.0/abc");
+	}
+
+	#[test]
+	fn expand_download_url_without_markers() {
+		assert_eq!(expand_download_url("https://example.com/api/v1/crates/", "foo", "1.0.0", "abc"), "https://example.com/api/v1/crates/foo/1.0.0/download");
+	}
+
+	#[test]
+	fn expand_download_url_invalid_names() {
+		// These names are invalid, but they must not cause a panic.
+		assert_eq!(expand_download_url("{prefix}/{crate}", "", "1.0.0", "abc"), "/");
+		assert_eq!(expand_download_url("{prefix}/{crate}", "é", "1.0.0", "abc"), "1/é");
+		assert_eq!(expand_download_url("{prefix}/{crate}", "fé", "1.0.0", "abc"), "2/fé");
+		assert_eq!(expand_download_url("{prefix}/{crate}", "aébc", "1.0.0", "abc"), "aé/bc/aébc");
+	}
+
+	#[test]
+	fn split_features_moves_new_syntax_to_features2() {
+		let (features1, features2) = split_features(features(&[
+			("default", &["std"]),
+			("std", &[]),
+			("serde", &["dep:serde"]),
+			("derive", &["std", "serde?/derive"]),
+			("full", &["default", "derive"]),
+		]));
+		assert_eq!(features1, features(&[
+			("default", &["std"]),
+			("std", &[]),
+			("full", &["default", "derive"]),
+		]));
+		assert_eq!(features2, features(&[
+			("serde", &["dep:serde"]),
+			("derive", &["std", "serde?/derive"]),
+		]));
+		assert_eq!(format_version(&features2), Some(2));
+	}
+
+	#[test]
+	fn split_features_keeps_plain_features() {
+		// `foo/bar` enables a feature of a dependency, which older Cargo versions understand.
+		let (features1, features2) = split_features(features(&[
+			("default", &["foo/bar"]),
+			("foo", &[]),
+		]));
+		assert_eq!(features1, features(&[("default", &["foo/bar"]), ("foo", &[])]));
+		assert!(features2.is_empty());
+		assert_eq!(format_version(&features2), None);
+	}
+
+	fn entry_from_manifest(manifest: &str) -> serde_json::Value {
+		let manifest: Manifest = toml::from_str(manifest).unwrap();
+		let entry = Entry::from_manifest(manifest, "abc".into()).unwrap();
+		serde_json::from_str(&serde_json::to_string(&entry).unwrap()).unwrap()
+	}
+
+	#[test]
+	fn rust_version_is_written_when_set() {
+		let json = entry_from_manifest("[package]\nname = \"foo\"\nversion = \"1.0.0\"\nrust-version = \"1.70\"\n");
+		assert_eq!(json["rust_version"], "1.70");
+	}
+
+	#[test]
+	fn rust_version_is_omitted_when_absent() {
+		let json = entry_from_manifest("[package]\nname = \"foo\"\nversion = \"1.0.0\"\n");
+		assert!(json.get("rust_version").is_none());
+
+		// Entries written without the field can still be read.
+		let entry: Entry = serde_json::from_value(json).unwrap();
+		assert_eq!(entry.rust_version, None);
+	}
+}
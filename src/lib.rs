@@ -1,9 +1,25 @@
+pub mod bundle;
 mod config;
+mod crate_name;
+pub mod fsck;
+pub mod gc;
 pub mod index;
 mod registry;
+pub mod storage;
 mod util;
 pub mod error;
 mod manifest;
+pub mod metadata;
+pub mod migrate;
+pub mod mirror;
+mod owners;
+mod token;
 
-pub use config::Config;
+#[cfg(test)]
+mod test_util;
+
+pub use config::{Config, S3Config, StorageConfig};
+pub use crate_name::{canonical_crate_name, is_plausible_crate_name, validate_crate_name, MAX_CRATE_NAME_LEN};
 pub use registry::Registry;
+pub use token::Token;
+pub use util::to_hex;
@@ -35,6 +35,43 @@ pub struct Dependencies {
 pub struct Package {
 	pub name: String,
 	pub version: String,
+	#[serde(default)]
+	pub authors: Vec<String>,
+	pub description: Option<String>,
+	#[serde(default)]
+	pub keywords: Vec<String>,
+	#[serde(default)]
+	pub categories: Vec<String>,
+	pub license: Option<String>,
+	#[serde(rename = "license-file")]
+	pub license_file: Option<String>,
+	pub repository: Option<String>,
+	pub homepage: Option<String>,
+	pub documentation: Option<String>,
+	pub readme: Option<Readme>,
+	#[serde(rename = "rust-version")]
+	pub rust_version: Option<String>,
+}
+
+/// The `readme` field of a package.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum Readme {
+	/// The path of the readme file.
+	Path(String),
+
+	/// Explicitly enable or disable the default readme file.
+	Enabled(bool),
+}
+
+impl Package {
+	/// Get the path of the readme file relative to the package root, if any.
+	pub fn readme_path(&self) -> Option<&str> {
+		match &self.readme {
+			Some(Readme::Path(path)) => Some(path),
+			_ => None,
+		}
+	}
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -53,11 +90,24 @@ pub struct Dependency {
 fn default_true() -> bool { true }
 
 pub fn extract<R: Read>(archive: R) -> Result<Manifest, Error> {
+	let data = extract_file(archive, "Cargo.toml")?
+		.ok_or_else(|| Error::new("failed to find manifest in archive".into()))?;
+	toml::from_slice(&data)
+		.map_err(|e| Error::new(format!("failed to parse manifest from archive: {}", e)))
+}
+
+/// Extract a file from a packaged crate.
+///
+/// The path of the file must be relative to the package root.
+///
+/// Returns None if the file does not exist in the archive.
+pub fn extract_file<R: Read>(archive: R, rel_path: &str) -> Result<Option<Vec<u8>>, Error> {
 	let mut archive = archive;
 	let archive = gzip::Decoder::new(&mut archive)
 		.map_err(|e| Error::new(format!("failed to initialize gzip decoder: {}", e)))?;
 	let mut archive = tar::Archive::new(archive);
 
+	let rel_path = std::path::Path::new(rel_path);
 	let entries = archive.entries()
 		.map_err(|e| Error::new(format!("failed to read archive header: {}", e)))?;
 	for file in entries {
@@ -66,15 +116,19 @@ pub fn extract<R: Read>(archive: R) -> Result<Manifest, Error> {
 			.map_err(|e| Error::new(format!("acrhive entry contains non-UTF8 path: {}", e)))?
 			.to_path_buf();
 
-		if entry_path.components().count() == 2 && entry_path.ends_with("Cargo.toml") {
+		// All files in the archive are in a `$name-$version` directory.
+		let mut components = entry_path.components();
+		if components.next().is_none() {
+			continue;
+		}
+
+		if components.as_path() == rel_path {
 			let mut data = Vec::new();
 			file.read_to_end(&mut data)
 				.map_err(|e| Error::new(format!("failed to read archive data for {}: {}", entry_path.display(), e)))?;
-			let manifest = toml::from_slice(&data)
-				.map_err(|e| Error::new(format!("failed to parse manifest from archive: {}: {}", entry_path.display(), e)))?;
-			return Ok(manifest)
+			return Ok(Some(data))
 		}
 	}
 
-	Err(Error::new("failed to find manifest in archive".into()))
+	Ok(None)
 }
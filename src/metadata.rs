@@ -0,0 +1,102 @@
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::error::Error;
+use crate::manifest::{self, Manifest};
+
+/// Metadata of a crate version that is not part of the index.
+///
+/// The metadata is stored next to the index in a separate directory,
+/// using the same directory layout as the index.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Metadata {
+	pub name: String,
+	#[serde(rename = "vers")]
+	pub version: String,
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub authors: Vec<String>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub description: Option<String>,
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub keywords: Vec<String>,
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub categories: Vec<String>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub license: Option<String>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub license_file: Option<String>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub repository: Option<String>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub homepage: Option<String>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub documentation: Option<String>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub readme: Option<String>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub readme_file: Option<String>,
+}
+
+pub fn read_metadata<R: std::io::Read>(mut read: R, path: impl AsRef<Path>) -> Result<Vec<Metadata>, Error> {
+	let path = path.as_ref();
+	let mut data = Vec::new();
+	read.read_to_end(&mut data)
+		.map_err(|e| Error::new(format!("failed to read from {}: {}", path.display(), e)))?;
+
+	data.split(|&c| c == b'\n')
+		.enumerate()
+		.filter(|(_i, line)| !line.is_empty())
+		.map(|(i, line)| {
+			serde_json::from_slice(line)
+				.map_err(|e| Error::new(format!("failed to parse metadata at {}:{}: {}", path.display(), i, e)))
+		})
+		.collect()
+}
+
+pub fn write_metadata<'a, W: std::io::Write>(mut write: W, path: impl AsRef<Path>, entries: impl IntoIterator<Item = &'a Metadata>) -> Result<(), Error> {
+	let path = path.as_ref();
+	for entry in entries.into_iter() {
+		let json = serde_json::to_string(entry)
+			.map_err(|e| Error::new(format!("failed to serialize metadata for {}-{}: {}", entry.name, entry.version, e)))?;
+		write.write_all(json.as_bytes())
+			.map_err(|e| Error::new(format!("failed to write to {}: {}", path.display(), e)))?;
+		write.write_all(b"\n")
+			.map_err(|e| Error::new(format!("failed to write to {}: {}", path.display(), e)))?;
+	}
+	write.flush().map_err(|e| Error::new(format!("failed to write to {}: {}", path.display(), e)))?;
+	Ok(())
+}
+
+impl Metadata {
+	/// Get the metadata from a packaged crate.
+	pub(crate) fn from_crate(data: &[u8]) -> Result<Self, Error> {
+		let manifest = manifest::extract(data)?;
+		let readme = match manifest.package.readme_path() {
+			None => None,
+			Some(path) => manifest::extract_file(data, path)?
+				.map(|x| String::from_utf8_lossy(&x).into_owned()),
+		};
+		Ok(Self::from_manifest(manifest, readme))
+	}
+
+	/// Get the metadata from a crate manifest and the contents of the readme file.
+	pub(crate) fn from_manifest(manifest: Manifest, readme: Option<String>) -> Self {
+		let package = manifest.package;
+		let readme_file = package.readme_path().map(String::from);
+		Self {
+			name: package.name,
+			version: package.version,
+			authors: package.authors,
+			description: package.description,
+			keywords: package.keywords,
+			categories: package.categories,
+			license: package.license,
+			license_file: package.license_file,
+			repository: package.repository,
+			homepage: package.homepage,
+			documentation: package.documentation,
+			readme,
+			readme_file,
+		}
+	}
+}
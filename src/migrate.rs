@@ -0,0 +1,263 @@
+use std::io::Read;
+use std::path::PathBuf;
+
+use crate::error::Error;
+use crate::index::{self, Entry};
+use crate::{manifest, util, Registry};
+
+/// A migration step for index entries.
+///
+/// Each step must be idempotent: applying it to an already migrated entry must not change it.
+struct Step {
+	/// A short description of the step.
+	name: &'static str,
+
+	/// Apply the migration step to an index entry.
+	apply: fn(&Registry, &mut Entry) -> Result<(), Error>,
+}
+
+/// All migration steps, in the order they are applied.
+///
+/// Add new steps at the end when the index format changes.
+const STEPS: &[Step] = &[
+	Step { name: "lowercase checksums", apply: lowercase_checksum },
+	Step { name: "split features into features and features2", apply: split_features },
+	Step { name: "add rust_version from the crate manifest", apply: add_rust_version },
+];
+
+/// A planned change to an index file.
+#[derive(Debug, Clone)]
+pub struct FileChange {
+	/// The path of the index file, relative to the index repository.
+	pub path: PathBuf,
+
+	/// The current contents of the index file.
+	pub old: String,
+
+	/// The migrated contents of the index file.
+	pub new: String,
+}
+
+impl FileChange {
+	/// Format the change as a unified diff with three lines of context.
+	///
+	/// Migrations rewrite each line in place, so lines are compared by position.
+	pub fn diff(&self) -> String {
+		const CONTEXT: usize = 3;
+
+		let old: Vec<_> = self.old.split_inclusive('\n').collect();
+		let new: Vec<_> = self.new.split_inclusive('\n').collect();
+		let len = old.len().max(new.len());
+		let changed: Vec<_> = (0..len).filter(|&i| old.get(i) != new.get(i)).collect();
+
+		let mut output = format!("--- a/{path}\n+++ b/{path}\n", path = self.path.display());
+		let mut i = 0;
+		while i < changed.len() {
+			// Merge changes into one hunk if their context would overlap.
+			let first = changed[i];
+			let mut last = first;
+			i += 1;
+			while i < changed.len() && changed[i] - last <= 2 * CONTEXT + 1 {
+				last = changed[i];
+				i += 1;
+			}
+
+			let start = first.saturating_sub(CONTEXT);
+			let end = (last + CONTEXT + 1).min(len);
+			let old_count = old.len().min(end).saturating_sub(start);
+			let new_count = new.len().min(end).saturating_sub(start);
+			output.push_str(&format!(
+				"@@ -{},{} +{},{} @@\n",
+				hunk_start(start, old_count),
+				old_count,
+				hunk_start(start, new_count),
+				new_count,
+			));
+			for j in start..end {
+				match (old.get(j), new.get(j)) {
+					(Some(old), Some(new)) if old == new => push_diff_line(&mut output, ' ', old),
+					(old, new) => {
+						if let Some(old) = old {
+							push_diff_line(&mut output, '-', old);
+						}
+						if let Some(new) = new {
+							push_diff_line(&mut output, '+', new);
+						}
+					},
+				}
+			}
+		}
+		output
+	}
+}
+
+/// Get the line number to start a hunk at.
+///
+/// Hunks without lines start at the line before the hunk.
+fn hunk_start(start: usize, count: usize) -> usize {
+	if count == 0 {
+		start
+	} else {
+		start + 1
+	}
+}
+
+/// Add a line to a diff, including the marker for a missing newline at the end of the file.
+fn push_diff_line(output: &mut String, prefix: char, line: &str) {
+	output.push(prefix);
+	output.push_str(line);
+	if !line.ends_with('\n') {
+		output.push_str("\n\\ No newline at end of file\n");
+	}
+}
+
+/// Get the names of all migration steps, in the order they are applied.
+pub fn step_names() -> impl Iterator<Item = &'static str> {
+	STEPS.iter().map(|x| x.name)
+}
+
+/// Determine the changes needed to migrate all index files to the current format.
+///
+/// Nothing is written to disk.
+pub fn plan(registry: &Registry) -> Result<Vec<FileChange>, Error> {
+	let mut changes = Vec::new();
+	for name in registry.iter_crate_names() {
+		let name = name?;
+		let path = registry.index_path(&name);
+		let path_abs = registry.index_dir().join(&path);
+
+		let mut old = String::new();
+		util::open_file_read(&path_abs)?
+			.read_to_string(&mut old)
+			.map_err(|e| Error::new(format!("failed to read from {}: {}", path_abs.display(), e)))?;
+
+		let mut entries = crate::registry::read_index(old.as_bytes(), &path_abs)?;
+		for entry in &mut entries {
+			for step in STEPS {
+				(step.apply)(registry, entry)
+					.map_err(|e| Error::new(format!("failed to {} for {}-{}: {}", step.name, entry.name, entry.version, e)))?;
+			}
+		}
+
+		let mut new = Vec::new();
+		index::write_index(&mut new, &path_abs, &entries)?;
+		let new = String::from_utf8(new)
+			.map_err(|e| Error::new(format!("serialized index for {} contains invalid UTF-8: {}", name, e)))?;
+
+		if new != old {
+			changes.push(FileChange { path, old, new });
+		}
+	}
+	Ok(changes)
+}
+
+/// Write the planned changes to the index files and commit them in a single commit.
+///
+/// All index files are locked while they are written.
+/// If an index file changed since the changes were planned, nothing is written.
+pub fn apply(registry: &mut Registry, changes: &[FileChange]) -> Result<(), Error> {
+	if changes.is_empty() {
+		return Ok(());
+	}
+
+	// Lock all files and make sure nothing changed since the migration was planned.
+	let mut files = Vec::with_capacity(changes.len());
+	for change in changes {
+		let path_abs = registry.index_dir().join(&change.path);
+		let mut file = util::open_file_read_write(&path_abs)?;
+		let mut current = String::new();
+		file.read_to_string(&mut current)
+			.map_err(|e| Error::new(format!("failed to read from {}: {}", path_abs.display(), e)))?;
+		if current != change.old {
+			return Err(Error::new(format!("{} was modified while planning the migration", path_abs.display())));
+		}
+		files.push((file, path_abs));
+	}
+
+	for ((file, path_abs), change) in files.iter_mut().zip(changes) {
+		util::overwrite_contents(file, path_abs, &change.new)?;
+	}
+
+	let paths: Vec<_> = changes.iter().map(|x| &x.path).collect();
+	util::add_commit(registry.index_repo(), &format!("Migrate {} index file(s) to the current format", changes.len()), &paths)?;
+	Ok(())
+}
+
+fn lowercase_checksum(_registry: &Registry, entry: &mut Entry) -> Result<(), Error> {
+	entry.checksum_sha256.make_ascii_lowercase();
+	Ok(())
+}
+
+fn split_features(_registry: &Registry, entry: &mut Entry) -> Result<(), Error> {
+	let mut features = std::mem::take(&mut entry.features);
+	features.append(&mut entry.features2);
+	let (features, features2) = index::split_features(features);
+	entry.v = index::format_version(&features2).or(entry.v);
+	entry.features = features;
+	entry.features2 = features2;
+	Ok(())
+}
+
+fn add_rust_version(registry: &Registry, entry: &mut Entry) -> Result<(), Error> {
+	if entry.rust_version.is_some() {
+		return Ok(());
+	}
+
+	// If the crate file is missing, there is nothing we can do.
+	let data = match registry.read_crate(&entry.name, &entry.version)? {
+		Some(x) => x,
+		None => return Ok(()),
+	};
+	let manifest = manifest::extract(data.as_slice())?;
+	entry.rust_version = manifest.package.rust_version;
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn diff_has_hunks_with_context() {
+		let old: String = (1..=10).map(|i| format!("{}\n", i)).collect();
+		let change = FileChange {
+			path: PathBuf::from("3/f/foo"),
+			new: old.replace("1\n", "one\n").replace("10\n", "ten\n"),
+			old,
+		};
+		assert_eq!(change.diff(), concat!(
+			"--- a/3/f/foo\n",
+			"+++ b/3/f/foo\n",
+			"@@ -1,4 +1,4 @@\n",
+			"-1\n",
+			"+one\n",
+			" 2\n",
+			" 3\n",
+			" 4\n",
+			"@@ -7,4 +7,4 @@\n",
+			" 7\n",
+			" 8\n",
+			" 9\n",
+			"-10\n",
+			"+ten\n",
+		));
+	}
+
+	#[test]
+	fn diff_marks_missing_newline() {
+		let change = FileChange {
+			path: PathBuf::from("1/a"),
+			old: String::from("a"),
+			new: String::from("a\nb\n"),
+		};
+		assert_eq!(change.diff(), concat!(
+			"--- a/1/a\n",
+			"+++ b/1/a\n",
+			"@@ -1,1 +1,2 @@\n",
+			"-a\n",
+			"\\ No newline at end of file\n",
+			"+a\n",
+			"+b\n",
+		));
+	}
+}
@@ -0,0 +1,284 @@
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::path::Path;
+
+use crate::error::Error;
+use crate::index::{Dependency, DependencyKind, Entry};
+use crate::{canonical_crate_name, metadata, util, validate_crate_name, Registry};
+
+/// A filter for the crates to mirror from another registry.
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+	/// Only mirror crates with these names.
+	///
+	/// Names are compared in their canonical form.
+	/// If empty, all crates are mirrored.
+	pub names: Vec<String>,
+
+	/// Only mirror these versions.
+	///
+	/// If empty, all versions are mirrored.
+	pub versions: Vec<String>,
+}
+
+impl Filter {
+	/// Check if an index entry matches the filter.
+	pub fn matches(&self, entry: &Entry) -> bool {
+		let name_matches = self.names.is_empty() || {
+			let name = canonical_crate_name(&entry.name);
+			self.names.iter().any(|x| canonical_crate_name(x) == name)
+		};
+		let version_matches = self.versions.is_empty() || self.versions.contains(&entry.version);
+		name_matches && version_matches
+	}
+}
+
+/// The index entries to mirror from another registry.
+#[derive(Debug, Clone, Default)]
+pub struct Plan {
+	/// The entries that are not in the registry yet, in the order they should be added.
+	pub entries: Vec<Entry>,
+
+	/// The number of matching entries that are already in the registry.
+	pub existing: usize,
+
+	/// The matching entries that are skipped because their crate name is not valid,
+	/// with the reason why the name is not valid.
+	pub invalid: Vec<(Entry, String)>,
+}
+
+/// Determine which entries of another registry index should be mirrored.
+///
+/// The source must be a directory with the layout of a registry index,
+/// like a clone of a git index or a copy of a sparse index.
+/// Entries that match the filter and that are not in the registry yet are returned in the order they appear in the source index.
+/// Entries with a crate name that is not valid are skipped, because old registries may contain names that are no longer allowed.
+pub fn plan(registry: &Registry, source: &Path, filter: &Filter) -> Result<Plan, Error> {
+	let mut plan = Plan::default();
+	for path in util::iter_files(source) {
+		let path = path?;
+		if path.strip_prefix(source).map(|x| x == Path::new("config.json")).unwrap_or(false) {
+			continue;
+		}
+
+		let mut entries = Vec::new();
+		for entry in read_foreign_index(&path)? {
+			if !filter.matches(&entry) {
+				continue;
+			}
+			match validate_crate_name(&entry.name) {
+				Ok(()) => entries.push(entry),
+				Err(e) => plan.invalid.push((entry, e.to_string())),
+			}
+		}
+
+		// All entries in an index file are for the same crate.
+		let existing_versions: Vec<_> = match entries.first() {
+			Some(first) if registry.contains_crate(&first.name) => {
+				registry.read_index(&first.name)?.into_iter().map(|x| x.version).collect()
+			},
+			_ => Vec::new(),
+		};
+
+		for entry in entries {
+			if existing_versions.contains(&entry.version) {
+				plan.existing += 1;
+			} else {
+				plan.entries.push(entry);
+			}
+		}
+	}
+	Ok(plan)
+}
+
+/// An index entry of another registry.
+///
+/// Unlike [`Entry`], this accepts fields that we do not know about, such as fields added by newer versions of Cargo.
+#[derive(serde::Deserialize)]
+struct ForeignEntry {
+	name: String,
+	vers: String,
+	deps: Vec<ForeignDependency>,
+	cksum: String,
+	features: BTreeMap<String, Vec<String>>,
+	#[serde(default)]
+	features2: BTreeMap<String, Vec<String>>,
+	yanked: bool,
+	links: Option<String>,
+	rust_version: Option<String>,
+	v: Option<u32>,
+}
+
+/// A dependency in an index entry of another registry.
+#[derive(serde::Deserialize)]
+struct ForeignDependency {
+	name: String,
+	req: String,
+	features: Vec<String>,
+	optional: bool,
+	default_features: bool,
+	target: Option<String>,
+	kind: DependencyKind,
+	registry: Option<String>,
+	package: Option<String>,
+}
+
+impl From<ForeignEntry> for Entry {
+	fn from(other: ForeignEntry) -> Self {
+		Self {
+			name: other.name,
+			version: other.vers,
+			dependencies: other.deps.into_iter().map(Dependency::from).collect(),
+			checksum_sha256: other.cksum,
+			features: other.features,
+			features2: other.features2,
+			yanked: other.yanked,
+			links: other.links,
+			rust_version: other.rust_version,
+			v: other.v,
+		}
+	}
+}
+
+impl From<ForeignDependency> for Dependency {
+	fn from(other: ForeignDependency) -> Self {
+		Self {
+			name: other.name,
+			version: other.req,
+			features: other.features,
+			optional: other.optional,
+			default_features: other.default_features,
+			target: other.target,
+			kind: other.kind,
+			registry: other.registry,
+			package: other.package,
+		}
+	}
+}
+
+/// Read an index file of another registry.
+///
+/// Fields that we do not know about are ignored.
+fn read_foreign_index(path: &Path) -> Result<Vec<Entry>, Error> {
+	let mut data = Vec::new();
+	util::open_file_read(path)?
+		.read_to_end(&mut data)
+		.map_err(|e| Error::new(format!("failed to read from {}: {}", path.display(), e)))?;
+
+	data.split(|&c| c == b'\n')
+		.enumerate()
+		.filter(|(_i, line)| !line.is_empty())
+		.map(|(i, line)| {
+			serde_json::from_slice::<ForeignEntry>(line)
+				.map(Entry::from)
+				.map_err(|e| Error::new(format!("failed to parse index entry at {}:{}: {}", path.display(), i, e)))
+		})
+		.collect()
+}
+
+/// Add a crate from another registry with the original index entry.
+///
+/// The checksum of the crate data is verified against the checksum in the entry.
+/// The yank state of the entry is kept.
+///
+/// The crate is added even if the metadata can not be read from the crate file or stored.
+/// In that case, the metadata error is returned as `Ok(Some(error))`.
+pub fn add(registry: &mut Registry, entry: &Entry, data: &[u8]) -> Result<Option<Error>, Error> {
+	let checksum = util::compute_sha256_hex(data);
+	if !checksum.eq_ignore_ascii_case(&entry.checksum_sha256) {
+		return Err(Error::new(format!(
+			"checksum mismatch for {}-{}: expected {}, got {}",
+			entry.name, entry.version, entry.checksum_sha256, checksum,
+		)));
+	}
+
+	let mut entry = entry.clone();
+	entry.checksum_sha256 = checksum;
+	let metadata = metadata::Metadata::from_crate(data);
+	registry.add_crate_with_metadata(&entry, data)?;
+
+	// The crate is in the index now, so a problem with the metadata must not fail the mirror.
+	let result = metadata.and_then(|x| registry.store_metadata(&x));
+	Ok(result.err())
+}
+
+/// Clone a git index repository.
+///
+/// The clone can be used as source for [`plan()`].
+pub fn clone_index(url: &str, path: &Path) -> Result<(), Error> {
+	git2::Repository::clone(url, path)
+		.map_err(|e| Error::new(format!("failed to clone {} to {}: {}", url, path.display(), e)))?;
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_util;
+
+	#[test]
+	fn plan_accepts_unknown_fields() {
+		let dir = tempfile::tempdir().unwrap();
+		let source = tempfile::tempdir().unwrap();
+		let mut registry = test_util::init_registry(dir.path());
+		test_util::add_crate(&mut registry, "foo", "0.1.0", b"foo 0.1.0");
+
+		util::write_new_file(source.path().join("config.json"), "{}").unwrap();
+		util::write_new_file(source.path().join("3/f/foo"), concat!(
+			r#"{"name":"foo","vers":"0.1.0","deps":[],"cksum":"aa","features":{},"yanked":false,"pubtime":"2025-01-01T00:00:00Z"}"#, "\n",
+			r#"{"name":"foo","vers":"0.2.0","deps":[{"name":"bar","req":"^1","features":[],"optional":false,"default_features":true,"target":null,"kind":"normal","public":true}],"cksum":"bb","features":{},"yanked":true,"links":null,"pubtime":"2025-01-02T00:00:00Z"}"#, "\n",
+		)).unwrap();
+
+		let plan = plan(&registry, source.path(), &Filter::default()).unwrap();
+		assert_eq!(plan.existing, 1);
+		assert_eq!(plan.entries.len(), 1);
+		assert_eq!(plan.entries[0].version, "0.2.0");
+		assert_eq!(plan.entries[0].dependencies[0].name, "bar");
+		assert!(plan.entries[0].yanked);
+	}
+
+	#[test]
+	fn add_without_metadata() {
+		let dir = tempfile::tempdir().unwrap();
+		let mut registry = test_util::init_registry(dir.path());
+		let data = b"not a crate file";
+		let entry: Entry = serde_json::from_str::<ForeignEntry>(&format!(
+			r#"{{"name":"foo","vers":"0.1.0","deps":[],"cksum":"{}","features":{{}},"yanked":true}}"#,
+			util::compute_sha256_hex(data),
+		)).unwrap().into();
+
+		let metadata_error = add(&mut registry, &entry, data).unwrap();
+		assert!(metadata_error.is_some());
+		assert!(registry.read_index("foo").unwrap()[0].yanked);
+		assert_eq!(registry.read_crate("foo", "0.1.0").unwrap().unwrap(), data);
+		assert!(registry.read_metadata("foo").unwrap().is_empty());
+
+		// A checksum mismatch is still an error, and nothing is added.
+		let mut entry = entry;
+		entry.version = "0.2.0".into();
+		entry.checksum_sha256 = util::compute_sha256_hex("something else");
+		assert!(add(&mut registry, &entry, data).is_err());
+		assert_eq!(registry.read_index("foo").unwrap().len(), 1);
+	}
+
+	#[test]
+	fn plan_skips_invalid_names() {
+		let dir = tempfile::tempdir().unwrap();
+		let source = tempfile::tempdir().unwrap();
+		let registry = test_util::init_registry(dir.path());
+
+		util::write_new_file(source.path().join("3/f/foo"), concat!(
+			r#"{"name":"../foo","vers":"0.1.0","deps":[],"cksum":"aa","features":{},"yanked":false}"#, "\n",
+		)).unwrap();
+		util::write_new_file(source.path().join("3/b/bar"), concat!(
+			r#"{"name":"bar","vers":"1.0.0","deps":[],"cksum":"bb","features":{},"yanked":false}"#, "\n",
+		)).unwrap();
+
+		let plan = plan(&registry, source.path(), &Filter::default()).unwrap();
+		assert_eq!(plan.entries.len(), 1);
+		assert_eq!(plan.entries[0].name, "bar");
+		assert_eq!(plan.invalid.len(), 1);
+		assert_eq!(plan.invalid[0].0.name, "../foo");
+		assert_eq!(plan.invalid[0].0.version, "0.1.0");
+	}
+}
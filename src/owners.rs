@@ -0,0 +1,39 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::error::Error;
+use crate::util;
+
+/// The owners of all crates, as stored in the owners file.
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct OwnersFile {
+	/// The owners of each crate, keyed by the lowercase crate name.
+	#[serde(default)]
+	crates: BTreeMap<String, Vec<String>>,
+}
+
+/// Read the owners of a crate from the owners file.
+///
+/// If the crate has no recorded owners, an empty list is returned.
+pub fn read_owners(path: &Path, crate_name: &str) -> Result<Vec<String>, Error> {
+	let mut file: OwnersFile = util::read_toml_or_default(path)?;
+	Ok(file.crates.remove(&crate_name.to_ascii_lowercase()).unwrap_or_default())
+}
+
+/// Modify the owners of a crate while holding an exclusive lock on the owners file.
+///
+/// The owners file is created if it does not exist yet.
+/// If the modification leaves the crate without owners, the crate is removed from the owners file.
+pub fn modify_owners<T>(path: &Path, crate_name: &str, modify: impl FnOnce(&mut Vec<String>) -> Result<T, Error>) -> Result<T, Error> {
+	let key = crate_name.to_ascii_lowercase();
+	util::modify_toml(path, |file: &mut OwnersFile| {
+		let mut owners = file.crates.remove(&key).unwrap_or_default();
+		let result = modify(&mut owners)?;
+		if !owners.is_empty() {
+			file.crates.insert(key, owners);
+		}
+		Ok(result)
+	})
+}
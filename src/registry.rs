@@ -1,12 +1,14 @@
-use crate::{index, manifest, util, Config};
+use crate::{bundle, crate_name, fsck, gc, index, manifest, metadata, migrate, mirror, owners, storage, token, util, Config, Token};
 use crate::error::Error;
 
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 pub struct Registry {
 	path: PathBuf,
 	config: Config,
 	repo: git2::Repository,
+	storage: Arc<dyn storage::Storage>,
 }
 
 // I think read-only access from multiple threads is fine.
@@ -21,6 +23,8 @@ impl Registry {
 	pub fn init(path: impl AsRef<Path>, config: Config) -> Result<Self, Error> {
 		let path = path.as_ref().to_path_buf();
 
+		let storage = storage::open(&config.storage, &path.join(&config.crate_dir))?;
+
 		// Write palletizer config file.
 		util::write_new_file(
 			path.join("palletizer.toml"),
@@ -39,7 +43,7 @@ impl Registry {
 		// Commit the created files.
 		util::add_commit(&repo, "Initialize empty registry index.", &["config.json"])?;
 
-		Ok(Self { path, config, repo })
+		Ok(Self { path, config, repo, storage })
 	}
 
 	/// Open an existing registry.
@@ -51,7 +55,8 @@ impl Registry {
 
 		let repo = git2::Repository::open(&index_path)
 			.map_err(|e| Error::new(format!("failed to open git repository at {}: {}", index_path.display(), e)))?;
-		Ok(Self { path, config, repo })
+		let storage = storage::open(&config.storage, &path.join(&config.crate_dir))?;
+		Ok(Self { path, config, repo, storage })
 	}
 
 	/// Get the API URL of the registry.
@@ -64,6 +69,11 @@ impl Registry {
 		&self.config.download_url
 	}
 
+	/// Check if the registry requires authentication for all operations.
+	pub fn auth_required(&self) -> bool {
+		self.config.auth_required
+	}
+
 	/// Get the path of the registry.
 	pub fn path(&self) -> &Path {
 		&self.path
@@ -80,10 +90,182 @@ impl Registry {
 	}
 
 	/// Get the absolute path of the crate directory.
+	///
+	/// This is used by the filesystem storage backend and for temporary files.
 	pub fn crate_dir(&self) -> PathBuf {
 		self.path.join(&self.config.crate_dir)
 	}
 
+	/// Get the directory for temporary files, such as uploaded crates that are not published yet.
+	///
+	/// This is a hidden directory in the crate directory, so it is ignored by the storage backend.
+	/// The directory may not exist yet.
+	pub fn temp_dir(&self) -> PathBuf {
+		self.crate_dir().join(".tmp")
+	}
+
+	/// Get the storage backend for crate files.
+	pub fn storage(&self) -> &Arc<dyn storage::Storage> {
+		&self.storage
+	}
+
+	/// Get the absolute path of the metadata directory.
+	pub fn metadata_dir(&self) -> PathBuf {
+		self.path.join(&self.config.metadata_dir)
+	}
+
+	/// Get the absolute path of the configuration file.
+	pub fn config_file(&self) -> PathBuf {
+		self.path.join("palletizer.toml")
+	}
+
+	/// Update `config.json` in the index repository to match the registry configuration.
+	///
+	/// Only `config.json` is created when the registry is initialized,
+	/// so this must be called when settings such as `auth_required` change for an existing registry.
+	///
+	/// Returns true if `config.json` was changed and committed.
+	pub fn update_cargo_config(&self) -> Result<bool, Error> {
+		let _lock = self.lock_shared()?;
+		let path = self.index_dir().join("config.json");
+		let cargo_json = self.config.cargo_json();
+		match std::fs::read(&path) {
+			Ok(data) if data == cargo_json.as_bytes() => return Ok(false),
+			Ok(_) => (),
+			Err(e) if e.kind() == std::io::ErrorKind::NotFound => (),
+			Err(e) => return Err(Error::new(format!("failed to read {}: {}", path.display(), e))),
+		}
+
+		util::overwrite_file(&path, cargo_json)?;
+		util::add_commit(&self.repo, "Update config.json.", &["config.json"])
+			.map_err(|e| Error::new(format!("failed to commit changes: {}", e)))?;
+		Ok(true)
+	}
+
+	/// Get the absolute path of the token file.
+	pub fn token_file(&self) -> PathBuf {
+		self.path.join("tokens.toml")
+	}
+
+	/// Create a new API token for a user.
+	///
+	/// Returns the token in plaintext.
+	/// Only a hash of the token is stored, so it can not be retrieved again later.
+	pub fn create_token(&self, name: &str, user: &str) -> Result<String, Error> {
+		let _lock = self.lock_shared()?;
+		let token = token::generate_token()?;
+		token::modify_tokens(&self.token_file(), |tokens| {
+			if tokens.iter().any(|x| x.name == name) {
+				return Err(Error::new(format!("a token named {:?} already exists", name)));
+			}
+			tokens.push(Token {
+				name: name.into(),
+				user: user.into(),
+				sha256: token::hash_token(&token),
+			});
+			Ok(())
+		})?;
+		Ok(token)
+	}
+
+	/// Get a list of all API tokens.
+	pub fn list_tokens(&self) -> Result<Vec<Token>, Error> {
+		token::read_tokens(&self.token_file())
+	}
+
+	/// Revoke an API token.
+	///
+	/// Returns true if the token was revoked,
+	/// and false if there was no token with the given name.
+	pub fn revoke_token(&self, name: &str) -> Result<bool, Error> {
+		let _lock = self.lock_shared()?;
+		token::modify_tokens(&self.token_file(), |tokens| {
+			let len = tokens.len();
+			tokens.retain(|x| x.name != name);
+			Ok(tokens.len() != len)
+		})
+	}
+
+	/// Look up the user that an API token belongs to.
+	///
+	/// Returns None if the token is not known.
+	pub fn authenticate(&self, token: &str) -> Result<Option<String>, Error> {
+		let tokens = token::read_tokens(&self.token_file())?;
+		Ok(tokens.into_iter().find(|x| x.matches(token)).map(|x| x.user))
+	}
+
+	/// Get the absolute path of the owners file.
+	pub fn owners_file(&self) -> PathBuf {
+		self.path.join("owners.toml")
+	}
+
+	/// Get the owners of a crate.
+	///
+	/// Returns an empty list if the crate has no recorded owners.
+	pub fn crate_owners(&self, crate_name: &str) -> Result<Vec<String>, Error> {
+		owners::read_owners(&self.owners_file(), crate_name)
+	}
+
+	/// Add owners to a crate.
+	///
+	/// Users that already own the crate are ignored.
+	pub fn add_crate_owners(&self, crate_name: &str, users: &[impl AsRef<str>]) -> Result<(), Error> {
+		let _lock = self.lock_shared()?;
+		owners::modify_owners(&self.owners_file(), crate_name, |owners| {
+			for user in users {
+				let user = user.as_ref();
+				if !owners.iter().any(|x| x == user) {
+					owners.push(user.into());
+				}
+			}
+			Ok(())
+		})
+	}
+
+	/// Remove owners from a crate.
+	///
+	/// This fails if it would leave the crate without any owners.
+	pub fn remove_crate_owners(&self, crate_name: &str, users: &[impl AsRef<str>]) -> Result<(), Error> {
+		let _lock = self.lock_shared()?;
+		owners::modify_owners(&self.owners_file(), crate_name, |owners| {
+			owners.retain(|owner| !users.iter().any(|x| x.as_ref() == owner));
+			if owners.is_empty() {
+				return Err(Error::new(format!("refusing to remove all owners of crate {}", crate_name)));
+			}
+			Ok(())
+		})
+	}
+
+	/// Forget all owners of a crate.
+	///
+	/// Unlike [`Self::remove_crate_owners()`], this leaves the crate without owners.
+	pub fn clear_crate_owners(&self, crate_name: &str) -> Result<(), Error> {
+		let _lock = self.lock_shared()?;
+		owners::modify_owners(&self.owners_file(), crate_name, |owners| {
+			owners.clear();
+			Ok(())
+		})
+	}
+
+	/// Check if a user is known to the registry.
+	///
+	/// A user is known if they have at least one API token.
+	pub fn is_known_user(&self, user: &str) -> Result<bool, Error> {
+		Ok(self.list_tokens()?.iter().any(|x| x.user == user))
+	}
+
+	/// Get the path of the index file for a crate, relative to the index repository.
+	///
+	/// The path follows the directory layout expected by Cargo for both the git and the sparse protocol.
+	pub fn index_path(&self, crate_name: &str) -> PathBuf {
+		self.index_path_rel(crate_name)
+	}
+
+	/// Check if a crate exists in the registry.
+	pub fn contains_crate(&self, crate_name: &str) -> bool {
+		self.index_dir().join(self.index_path_rel(crate_name)).is_file()
+	}
+
 	/// Read the index entries for a specific crate.
 	pub fn read_index(&self, crate_name: &str) -> Result<Vec<index::Entry>, Error> {
 		let path = self.index_dir().join(self.index_path_rel(crate_name));
@@ -91,6 +273,70 @@ impl Registry {
 		read_index(file, &path)
 	}
 
+	/// Read the stored metadata for all versions of a crate.
+	///
+	/// Versions without stored metadata are not included in the result.
+	pub fn read_metadata(&self, crate_name: &str) -> Result<Vec<metadata::Metadata>, Error> {
+		let path = self.metadata_dir().join(self.index_path_rel(crate_name));
+		if !path.exists() {
+			return Ok(Vec::new());
+		}
+		let file = util::open_file_read(&path)?;
+		metadata::read_metadata(file, &path)
+	}
+
+	/// Store the metadata for a crate version.
+	///
+	/// If metadata for the same version is already stored, it is replaced.
+	pub fn store_metadata(&self, entry: &metadata::Metadata) -> Result<(), Error> {
+		let _lock = self.lock_shared()?;
+		let path = self.metadata_dir().join(self.index_path_rel(&entry.name));
+		let mut file = util::open_file_create_read_write(&path)?;
+		let mut entries = metadata::read_metadata(&mut file, &path)?;
+		match entries.iter_mut().find(|x| x.version == entry.version) {
+			Some(existing) => *existing = entry.clone(),
+			None => entries.push(entry.clone()),
+		}
+		util::truncate_file(&mut file, &path)?;
+		metadata::write_metadata(&mut file, &path, &entries)
+	}
+
+	/// Update the stored metadata of a crate version from the stored crate file.
+	///
+	/// The metadata is read from the manifest in the crate file.
+	/// If `overwrite` is false and metadata for the version is already stored, nothing is changed.
+	///
+	/// Returns true if the stored metadata was updated.
+	pub fn reindex_metadata(&self, crate_name: &str, version: &str, overwrite: bool) -> Result<bool, Error> {
+		if !overwrite && self.read_metadata(crate_name)?.iter().any(|x| x.version == version) {
+			return Ok(false);
+		}
+
+		let data = self.read_crate(crate_name, version)?
+			.ok_or_else(|| Error::new(format!("crate file for {}-{} not found", crate_name, version)))?;
+		let metadata = metadata::Metadata::from_crate(&data)?;
+		if metadata.name != crate_name || metadata.version != version {
+			return Err(Error::new(format!(
+				"crate file for {}-{} contains manifest for {}-{}",
+				crate_name, version, metadata.name, metadata.version,
+			)));
+		}
+		self.store_metadata(&metadata)?;
+		Ok(true)
+	}
+
+	/// Get the storage key of the crate file for a crate version.
+	pub fn crate_key(&self, crate_name: &str, version: &str) -> String {
+		format!("{name}/{name}-{version}.crate", name = crate_name, version = version)
+	}
+
+	/// Read the crate file for a crate version from the storage backend.
+	///
+	/// Returns None if the crate file does not exist.
+	pub fn read_crate(&self, crate_name: &str, version: &str) -> Result<Option<Vec<u8>>, Error> {
+		self.storage.get(&self.crate_key(crate_name, version))
+	}
+
 	/// Iterate over the names of all crates in the registry.
 	pub fn iter_crate_names(&self) -> impl Iterator<Item = Result<String, Error>> {
 		let index_dir = self.index_dir();
@@ -106,8 +352,8 @@ impl Registry {
 					Err(e) => return Some(Err(Error::new(format!("Failed to read directory entry: {}", e)))),
 					Ok(x) => x,
 				};
-				// All crates live in at-least two subdirectories
-				if item.depth() < 3 {
+				// All crates live in at-least one subdirectory: `1/a`, `2/ab`, `3/a/abc` and `ab/cd/abcd`
+				if item.depth() < 2 {
 					return None;
 				}
 				if !item.file_type().is_file() {
@@ -122,8 +368,22 @@ impl Registry {
 
 	/// Add a crate to the registry using the supplied metadata.
 	pub fn add_crate_with_metadata(&mut self, metadata: &index::Entry, data: &[u8]) -> Result<(), Error> {
+		self.add_index_entry(metadata, |storage, key| storage.put(key, data))
+	}
+
+	/// Add a crate to the registry using the supplied metadata, with the crate data in a file.
+	///
+	/// The file is streamed to the storage backend without reading it into memory.
+	pub fn add_crate_file_with_metadata(&mut self, metadata: &index::Entry, path: &Path) -> Result<(), Error> {
+		self.add_index_entry(metadata, |storage, key| storage.put_file(key, path))
+	}
+
+	/// Add an index entry after storing the crate file with the given function.
+	fn add_index_entry(&mut self, metadata: &index::Entry, store: impl FnOnce(&dyn storage::Storage, &str) -> Result<(), Error>) -> Result<(), Error> {
 		use std::io::Write;
 
+		let _lock = self.lock_shared()?;
+
 		// Check that all dependencies are in allowed registries.
 		for dep in &metadata.dependencies {
 			if let Some(registry) = &dep.registry && !self.config.allowed_registries.contains(registry) {
@@ -131,6 +391,13 @@ impl Registry {
 			}
 		}
 
+		// Check that the name of a new crate is valid and does not collide with other crates.
+		// Existing crates may have been added before these rules were enforced.
+		if !self.contains_crate(&metadata.name) {
+			crate_name::validate_crate_name(&metadata.name)?;
+			self.check_name_collision(&metadata.name)?;
+		}
+
 		let metadata_json = serde_json::to_string(&metadata)
 			.map_err(|e| Error::new(format!("failed to serialize index metadata: {}", e)))?;
 
@@ -158,14 +425,19 @@ impl Registry {
 			}
 		}
 
-		// Check that the version isn't in the index yet.
+		// Check that the name matches the existing crate exactly (not just after case folding).
 		let index = read_index(&mut index_file, &index_path_abs)?;
+		if let Some(existing) = index.iter().find(|x| x.name != metadata.name) {
+			return Err(Error::new(format!("crate name `{}` conflicts with existing crate `{}`", metadata.name, existing.name)));
+		}
+
+		// Check that the version isn't in the index yet.
 		if index.iter().any(|x| x.version == metadata.version) {
 			return Err(Error::new(format!("duplicate crate: {}-{} already exists in the index", metadata.name, metadata.version)));
 		}
 
-		// Write the crate file.
-		util::write_new_file(self.crate_path_abs(&metadata.name, &metadata.version), data)?;
+		// Store the crate file.
+		store(self.storage.as_ref(), &self.crate_key(&metadata.name, &metadata.version))?;
 
 		// Add the index entry.
 		writeln!(&mut index_file, "{}", &metadata_json)
@@ -181,39 +453,51 @@ impl Registry {
 	/// Add a crate to the registry.
 	///
 	/// You must pass the path to a crate as packaged by `cargo package`.
-	pub fn add_crate(&mut self, data: &[u8]) -> Result<(), Error> {
+	///
+	/// The crate is added even if the metadata can not be stored.
+	/// In that case, the error is returned as `Ok(Some(error))`,
+	/// and the metadata can be stored later with [`Self::reindex_metadata()`].
+	pub fn add_crate(&mut self, data: &[u8]) -> Result<Option<Error>, Error> {
 		// Extract the manifest.
 		let manifest = manifest::extract(data)?;
 		let sha256_hexsum = util::compute_sha256_hex(data);
-		let metadata = index::Entry::from_manifest(manifest, sha256_hexsum)?;
+		let crate_metadata = metadata::Metadata::from_crate(data)?;
+		let index_entry = index::Entry::from_manifest(manifest, sha256_hexsum)?;
 
-		self.add_crate_with_metadata(&metadata, data)
+		self.add_crate_with_metadata(&index_entry, data)?;
+
+		// The crate is in the index now, so a problem with the metadata must not fail the add.
+		Ok(self.store_metadata(&crate_metadata).err())
 	}
 
 	/// Add a crate to the registry.
 	///
 	/// You must pass the path to a crate as packaged by `cargo package`.
-	pub fn add_crate_from_file(&mut self, path: impl AsRef<Path>) -> Result<(), Error> {
+	///
+	/// See [`Self::add_crate()`] for the return value.
+	pub fn add_crate_from_file(&mut self, path: impl AsRef<Path>) -> Result<Option<Error>, Error> {
 		let data = util::read_file(path.as_ref())?;
 		self.add_crate(&data)
 	}
 
 	/// Delete a crate from the registry.
 	///
-	/// This will completely remove the index entry and the crate files.
+	/// This will completely remove the index entry and the crate files of all published versions.
 	/// Normally, you should yank a crate with [`Self::yank-crate()`] instead of deleting it.
 	pub fn delete_crate(&mut self, name: &str) -> Result<(), Error> {
+		let _lock = self.lock_shared()?;
 		let index_path_rel = self.index_path_rel(name);
 		let index_path_abs = self.index_dir().join(&index_path_rel);
-		let crate_dir_abs = self.path().join(self.crate_dir_rel(name));
 
-		let _index_file = if cfg!(unix) {
+		let (index, _index_file) = if cfg!(unix) {
 			// On Unix, lock the index file for exclusive access to prevent racing with `add_crate`.
-			Some(util::open_file_read_write(&index_path_abs)?)
+			let mut index_file = util::open_file_read_write(&index_path_abs)?;
+			let index = read_index(&mut index_file, &index_path_abs)?;
+			(index, Some(index_file))
 		} else {
 			// On Windows, opening the file would prevent deletion so we can't open it here.
 			// But it also means that if `add_crate()` already opened the index, the delete below will fail.
-			None
+			(self.read_index(name)?, None)
 		};
 
 		// NOTE: We have inconsistent behaviour accross platforms when `delete_crate` and `add_crate` are done in parallel.
@@ -230,13 +514,24 @@ impl Registry {
 		}
 
 		// Commit the changes.
-		util::add_commit(&self.repo, &format!("Delete crate {}", name), &[index_path_rel])
+		util::add_commit(&self.repo, &format!("Delete crate {}", name), &[&index_path_rel])
 			.map_err(|e| Error::new(format!("failed to commit changes: {}", e)))?;
 
-		// Delete the crate directory with all crate files.
-		// TODO: Only delete files matching published crate versions?.
-		std::fs::remove_dir_all(&crate_dir_abs)
-			.map_err(|e| Error::new(format!("failed to delete {}: {}", crate_dir_abs.display(), e)))?;
+		// Delete the crate files of all published versions.
+		// Other files in the crate directory are left for `gc::collect()`.
+		for entry in &index {
+			self.storage.delete(&self.crate_key(&entry.name, &entry.version))?;
+		}
+
+		// Delete the stored metadata.
+		let metadata_path_abs = self.metadata_dir().join(&index_path_rel);
+		if metadata_path_abs.exists() {
+			std::fs::remove_file(&metadata_path_abs)
+				.map_err(|e| Error::new(format!("failed to delete {}: {}", metadata_path_abs.display(), e)))?;
+		}
+
+		// Forget the owners of the crate.
+		self.clear_crate_owners(name)?;
 
 		Ok(())
 	}
@@ -249,6 +544,7 @@ impl Registry {
 	/// If the crate is not found or if an other error occures,
 	/// an error is returned.
 	pub fn yank_crate(&mut self, name: &str, version: &str) -> Result<bool, Error> {
+		let _lock = self.lock_shared()?;
 		let index_path_rel = self.index_path_rel(name);
 		let index_path_abs = self.index_dir().join(&index_path_rel);
 		let mut index_file = util::open_file_read_write(&index_path_abs)?;
@@ -291,6 +587,7 @@ impl Registry {
 	/// If the crate is not found or if an other error occures,
 	/// an error is returned.
 	pub fn unyank_crate(&mut self, name: &str, version: &str) -> Result<bool, Error> {
+		let _lock = self.lock_shared()?;
 		let index_path_rel = self.index_path_rel(name);
 		let index_path_abs = self.index_dir().join(&index_path_rel);
 		let mut index_file = util::open_file_read_write(&index_path_abs)?;
@@ -326,6 +623,106 @@ impl Registry {
 
 	}
 
+	/// Check the consistency of the registry.
+	///
+	/// This checks that the index and the crate files agree with each other,
+	/// that all index files are in the right place,
+	/// and that the work tree of the index repository matches HEAD.
+	pub fn fsck(&self) -> Result<fsck::Report, Error> {
+		fsck::check(self)
+	}
+
+	/// Repair the problems from a consistency check that can be repaired safely.
+	///
+	/// Returns the problems that were repaired.
+	pub fn fsck_repair(&mut self, report: &fsck::Report) -> Result<Vec<fsck::Problem>, Error> {
+		let _lock = self.lock_shared()?;
+		fsck::repair(self, report)
+	}
+
+	/// Find and remove crate files and empty directories that are not referenced by the index.
+	///
+	/// See [`gc::Mode`] for the different ways to deal with the garbage.
+	pub fn collect_garbage(&self, mode: &gc::Mode) -> Result<gc::Report, Error> {
+		let _lock = self.lock_shared()?;
+		gc::collect(self, mode)
+	}
+
+	/// Determine the changes needed to migrate all index files to the current format.
+	///
+	/// Nothing is written to disk.
+	/// Use [`Self::apply_index_migration()`] to apply the changes.
+	pub fn plan_index_migration(&self) -> Result<Vec<migrate::FileChange>, Error> {
+		migrate::plan(self)
+	}
+
+	/// Apply the changes from [`Self::plan_index_migration()`] in a single commit.
+	pub fn apply_index_migration(&mut self, changes: &[migrate::FileChange]) -> Result<(), Error> {
+		let _lock = self.lock_shared()?;
+		migrate::apply(self, changes)
+	}
+
+	/// Determine which entries of another registry index should be mirrored into this registry.
+	///
+	/// See [`mirror::plan()`] for details.
+	pub fn plan_mirror(&self, source: impl AsRef<Path>, filter: &mirror::Filter) -> Result<mirror::Plan, Error> {
+		mirror::plan(self, source.as_ref(), filter)
+	}
+
+	/// Add a crate from another registry with the original index entry.
+	///
+	/// The checksum of the crate data is verified against the checksum in the entry.
+	/// See [`mirror::add()`] for how problems with the metadata are reported.
+	pub fn add_mirrored_crate(&mut self, entry: &index::Entry, data: &[u8]) -> Result<Option<Error>, Error> {
+		mirror::add(self, entry, data)
+	}
+
+	/// Export the registry to a gzipped tar archive.
+	///
+	/// See [`bundle::export()`] for the contents of the archive.
+	pub fn export(&self, output: impl std::io::Write) -> Result<bundle::Summary, Error> {
+		bundle::export(self, output)
+	}
+
+	/// Import a registry from an archive created by [`Self::export()`].
+	///
+	/// The checksums of all files are verified before anything is written to `path`.
+	pub fn import(archive: impl AsRef<Path>, path: impl AsRef<Path>) -> Result<(Self, bundle::Summary), Error> {
+		bundle::import(archive.as_ref(), path.as_ref())
+	}
+
+	/// Lock the registry for exclusive access.
+	///
+	/// All operations that modify the registry take a shared lock,
+	/// so while the exclusive lock is held, the registry can not change.
+	///
+	/// The lock is held on the configuration file until the returned file is closed.
+	pub(crate) fn lock_exclusive(&self) -> Result<std::fs::File, Error> {
+		util::open_file_read_write(self.config_file())
+	}
+
+	/// Lock the registry for shared access.
+	///
+	/// See [`Self::lock_exclusive()`].
+	fn lock_shared(&self) -> Result<std::fs::File, Error> {
+		util::open_file_read(self.config_file())
+	}
+
+	/// Check that a new crate name does not collide with an existing crate.
+	///
+	/// Names collide if they have the same canonical name,
+	/// which means they are equal after case folding and treating `-` and `_` as the same character.
+	fn check_name_collision(&self, name: &str) -> Result<(), Error> {
+		let canonical_name = crate_name::canonical_crate_name(name);
+		for existing in self.iter_crate_names() {
+			let existing = existing?;
+			if crate_name::canonical_crate_name(&existing) == canonical_name {
+				return Err(Error::new(format!("crate name `{}` conflicts with existing crate `{}`", name, existing)));
+			}
+		}
+		Ok(())
+	}
+
 	#[allow(clippy::match_ref_pats)]
 	fn index_path_rel(&self, name: &str) -> PathBuf {
 		let mut file = match name.as_bytes() {
@@ -338,18 +735,6 @@ impl Registry {
 		file.make_ascii_lowercase();
 		file.into()
 	}
-
-	fn crate_dir_rel(&self, name: &str) -> PathBuf {
-		self.config.crate_dir.join(name)
-	}
-
-	fn crate_path_rel(&self, name: &str, version: &str) -> PathBuf {
-		self.config.crate_dir.join(format!("{name}/{name}-{version}.crate", name = name, version = version))
-	}
-
-	fn crate_path_abs(&self, name: &str, version: &str) -> PathBuf {
-		self.path().join(self.crate_path_rel(name, version))
-	}
 }
 
 pub fn read_index<R: std::io::Read>(mut stream: R, path: &Path) -> Result<Vec<index::Entry>, Error> {
@@ -365,3 +750,72 @@ pub fn read_index<R: std::io::Read>(mut stream: R, path: &Path) -> Result<Vec<in
 		})
 		.collect()
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_util;
+
+	#[test]
+	fn iter_crate_names_includes_short_names() {
+		let dir = tempfile::tempdir().unwrap();
+		let registry = test_util::init_registry(dir.path());
+		for name in ["a", "ab", "abc", "abcd"] {
+			util::write_new_file(registry.index_dir().join(registry.index_path(name)), "").unwrap();
+		}
+
+		let mut names = registry.iter_crate_names().collect::<Result<Vec<_>, _>>().unwrap();
+		names.sort();
+		assert_eq!(names, ["a", "ab", "abc", "abcd"]);
+	}
+
+	#[test]
+	fn update_cargo_config() {
+		let dir = tempfile::tempdir().unwrap();
+		let registry = test_util::init_registry(dir.path());
+		let config_json = registry.index_dir().join("config.json");
+		assert!(!registry.update_cargo_config().unwrap());
+		assert!(!std::fs::read_to_string(&config_json).unwrap().contains("auth-required"));
+
+		let mut config: Config = util::read_toml(registry.config_file()).unwrap();
+		config.auth_required = true;
+		util::overwrite_file(registry.config_file(), toml::to_string(&config).unwrap()).unwrap();
+		let registry = Registry::open(dir.path()).unwrap();
+		assert!(registry.update_cargo_config().unwrap());
+		assert!(!registry.update_cargo_config().unwrap());
+
+		let json: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&config_json).unwrap()).unwrap();
+		assert_eq!(json["auth-required"], true);
+		let head = registry.index_repo().head().unwrap().peel_to_commit().unwrap();
+		assert_eq!(head.message(), Some("Update config.json."));
+		assert!(registry.index_repo().statuses(None).unwrap().is_empty());
+	}
+
+	#[test]
+	fn add_crate_stores_metadata() {
+		let dir = tempfile::tempdir().unwrap();
+		let mut registry = test_util::init_registry(dir.path());
+		let data = test_util::crate_file("foo", "0.1.0", "A crate.");
+		assert!(registry.add_crate(&data).unwrap().is_none());
+		assert_eq!(registry.read_index("foo").unwrap()[0].version, "0.1.0");
+		assert_eq!(registry.read_metadata("foo").unwrap()[0].description.as_deref(), Some("A crate."));
+	}
+
+	#[test]
+	fn add_crate_without_metadata() {
+		let dir = tempfile::tempdir().unwrap();
+		let mut registry = test_util::init_registry(dir.path());
+
+		// Block the metadata directory of the crate with a file.
+		util::write_new_file(registry.metadata_dir().join("3"), "").unwrap();
+
+		let data = test_util::crate_file("foo", "0.1.0", "A crate.");
+		assert!(registry.add_crate(&data).unwrap().is_some());
+		assert_eq!(registry.read_index("foo").unwrap()[0].version, "0.1.0");
+		assert_eq!(registry.read_crate("foo", "0.1.0").unwrap().unwrap(), data);
+
+		// Something that is not a crate is refused before anything is added.
+		assert!(registry.add_crate(b"not a crate file").is_err());
+		assert_eq!(registry.read_index("foo").unwrap().len(), 1);
+	}
+}
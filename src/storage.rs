@@ -0,0 +1,205 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::error::Error;
+use crate::{util, StorageConfig};
+
+#[cfg(feature = "s3")]
+mod s3;
+
+#[cfg(feature = "s3")]
+pub use s3::S3;
+
+/// A storage backend for crate files.
+///
+/// Objects are identified by a key, which is a relative path using `/` as separator.
+/// Keys with a component that starts with a `.` are hidden:
+/// they can be stored and retrieved, but they are not listed.
+pub trait Storage: Send + Sync {
+	/// Store a new object.
+	///
+	/// This fails if an object with the same key already exists.
+	fn put(&self, key: &str, data: &[u8]) -> Result<(), Error>;
+
+	/// Store a new object with the contents of a file.
+	///
+	/// The file is streamed to the object without reading it into memory.
+	/// This fails if an object with the same key already exists.
+	fn put_file(&self, key: &str, path: &Path) -> Result<(), Error>;
+
+	/// Get the contents of an object.
+	///
+	/// Returns None if the object does not exist.
+	fn get(&self, key: &str) -> Result<Option<Vec<u8>>, Error>;
+
+	/// Delete an object.
+	///
+	/// Returns true if the object was deleted, and false if it did not exist.
+	fn delete(&self, key: &str) -> Result<bool, Error>;
+
+	/// List all objects with a key that starts with the given prefix.
+	///
+	/// Hidden objects are not included.
+	fn list(&self, prefix: &str) -> Result<Vec<Object>, Error>;
+
+	/// Check if an object exists.
+	fn exists(&self, key: &str) -> Result<bool, Error>;
+}
+
+/// An object in a storage backend.
+#[derive(Debug, Clone)]
+pub struct Object {
+	/// The key of the object.
+	pub key: String,
+
+	/// The size of the object in bytes.
+	pub size: u64,
+}
+
+/// Open the storage backend from a registry configuration.
+///
+/// The crate directory is used by the filesystem backend.
+pub fn open(config: &StorageConfig, crate_dir: &Path) -> Result<Arc<dyn Storage>, Error> {
+	match config {
+		StorageConfig::Filesystem => Ok(Arc::new(Filesystem::new(crate_dir))),
+		#[cfg(feature = "s3")]
+		StorageConfig::S3(config) => Ok(Arc::new(S3::new(config)?)),
+		#[cfg(not(feature = "s3"))]
+		StorageConfig::S3(_) => Err(Error::new("S3 storage is not supported: palletizer was compiled without the `s3` feature".into())),
+	}
+}
+
+/// Check if a key is valid.
+///
+/// Valid keys are relative paths using `/` as separator,
+/// without empty, `.` or `..` components.
+pub fn is_valid_key(key: &str) -> bool {
+	!key.is_empty()
+		&& !key.contains('\\')
+		&& key.split('/').all(|x| !x.is_empty() && x != "." && x != "..")
+}
+
+/// Check if a key refers to a hidden object.
+pub fn is_hidden_key(key: &str) -> bool {
+	key.split('/').any(|x| x.starts_with('.'))
+}
+
+fn check_key(key: &str) -> Result<(), Error> {
+	if is_valid_key(key) {
+		Ok(())
+	} else {
+		Err(Error::new(format!("invalid storage key: {:?}", key)))
+	}
+}
+
+/// Storage backend that stores objects as files in a directory.
+pub struct Filesystem {
+	root: PathBuf,
+}
+
+impl Filesystem {
+	/// Create a filesystem backend that stores objects in the given directory.
+	pub fn new(root: impl Into<PathBuf>) -> Self {
+		Self { root: root.into() }
+	}
+
+	fn path(&self, key: &str) -> Result<PathBuf, Error> {
+		check_key(key)?;
+		Ok(self.root.join(key))
+	}
+}
+
+impl Storage for Filesystem {
+	fn put(&self, key: &str, data: &[u8]) -> Result<(), Error> {
+		util::write_new_file(self.path(key)?, data)
+	}
+
+	fn put_file(&self, key: &str, path: &Path) -> Result<(), Error> {
+		let target = self.path(key)?;
+		let mut source = util::open_file_read(path)?;
+		let mut file = util::create_new_file(&target)?;
+		if let Err(e) = std::io::copy(&mut source, &mut file) {
+			// Do not leave a partial object behind.
+			drop(file);
+			let _ = std::fs::remove_file(&target);
+			return Err(Error::new(format!("failed to copy {} to {}: {}", path.display(), target.display(), e)));
+		}
+		Ok(())
+	}
+
+	fn get(&self, key: &str) -> Result<Option<Vec<u8>>, Error> {
+		let path = self.path(key)?;
+		match std::fs::read(&path) {
+			Ok(x) => Ok(Some(x)),
+			Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+			Err(e) => Err(Error::new(format!("failed to read from {}: {}", path.display(), e))),
+		}
+	}
+
+	fn delete(&self, key: &str) -> Result<bool, Error> {
+		let path = self.path(key)?;
+		match std::fs::remove_file(&path) {
+			Ok(()) => (),
+			Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+			Err(e) => return Err(Error::new(format!("failed to delete {}: {}", path.display(), e))),
+		}
+
+		// Delete all empty parent directories.
+		for parent in path.ancestors().skip(1) {
+			if parent == self.root || std::fs::remove_dir(parent).is_err() {
+				break;
+			}
+		}
+		Ok(true)
+	}
+
+	fn list(&self, prefix: &str) -> Result<Vec<Object>, Error> {
+		if !self.root.exists() {
+			return Ok(Vec::new());
+		}
+
+		let mut objects = Vec::new();
+		for path in util::iter_files(&self.root) {
+			let path = path?;
+			let rel_path = path.strip_prefix(&self.root).unwrap_or(&path);
+			let key = rel_path.components()
+				.map(|x| x.as_os_str().to_str())
+				.collect::<Option<Vec<_>>>()
+				.ok_or_else(|| Error::new(format!("invalid UTF-8 in path: {}", path.display())))?
+				.join("/");
+			if !key.starts_with(prefix) {
+				continue;
+			}
+			let size = path.metadata()
+				.map_err(|e| Error::new(format!("failed to get metadata of {}: {}", path.display(), e)))?
+				.len();
+			objects.push(Object { key, size });
+		}
+		Ok(objects)
+	}
+
+	fn exists(&self, key: &str) -> Result<bool, Error> {
+		Ok(self.path(key)?.is_file())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn filesystem_put_file() {
+		let dir = tempfile::tempdir().unwrap();
+		let source = dir.path().join("upload.crate");
+		std::fs::write(&source, "foo 0.1.0").unwrap();
+
+		let storage = Filesystem::new(dir.path().join("crates"));
+		storage.put_file("foo/foo-0.1.0.crate", &source).unwrap();
+		assert_eq!(storage.get("foo/foo-0.1.0.crate").unwrap().unwrap(), b"foo 0.1.0");
+
+		// Existing objects are never overwritten.
+		std::fs::write(&source, "something else").unwrap();
+		assert!(storage.put_file("foo/foo-0.1.0.crate", &source).is_err());
+		assert_eq!(storage.get("foo/foo-0.1.0.crate").unwrap().unwrap(), b"foo 0.1.0");
+	}
+}
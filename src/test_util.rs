@@ -0,0 +1,49 @@
+//! Helpers for unit tests.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::{index, util, Config, Registry};
+
+/// Initialize a new registry in an empty directory.
+///
+/// The index repository gets its own commit author, so the tests do not depend on the git configuration of the user.
+pub fn init_registry(path: &Path) -> Registry {
+	let config = Config::example();
+	let repo = git2::Repository::init(path.join(&config.index_dir)).unwrap();
+	let mut git_config = repo.config().unwrap();
+	git_config.set_str("user.name", "Palletizer").unwrap();
+	git_config.set_str("user.email", "palletizer@example.com").unwrap();
+	Registry::init(path, config).unwrap()
+}
+
+/// Add a crate version to a registry, with arbitrary data as crate file.
+pub fn add_crate(registry: &mut Registry, name: &str, version: &str, data: &[u8]) {
+	let entry = index::Entry {
+		name: name.into(),
+		version: version.into(),
+		dependencies: Vec::new(),
+		checksum_sha256: util::compute_sha256_hex(data),
+		features: BTreeMap::new(),
+		features2: BTreeMap::new(),
+		yanked: false,
+		links: None,
+		rust_version: None,
+		v: None,
+	};
+	registry.add_crate_with_metadata(&entry, data).unwrap();
+}
+
+/// Create a packaged crate with only a manifest.
+pub fn crate_file(name: &str, version: &str, description: &str) -> Vec<u8> {
+	let manifest = format!(
+		"[package]\nname = \"{}\"\nversion = \"{}\"\ndescription = \"{}\"\n",
+		name, version, description,
+	);
+	let mut header = tar::Header::new_gnu();
+	header.set_size(manifest.len() as u64);
+	header.set_mode(0o644);
+	let mut archive = tar::Builder::new(libflate::gzip::Encoder::new(Vec::new()).unwrap());
+	archive.append_data(&mut header, format!("{}-{}/Cargo.toml", name, version), manifest.as_bytes()).unwrap();
+	archive.into_inner().unwrap().finish().into_result().unwrap()
+}
@@ -0,0 +1,127 @@
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::error::Error;
+use crate::util;
+
+/// An API token as stored in the token file.
+///
+/// The token itself is never stored, only the SHA-256 hash of it.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Token {
+	/// The unique name of the token.
+	pub name: String,
+
+	/// The user the token belongs to.
+	pub user: String,
+
+	/// The SHA-256 hash of the token as hex string.
+	pub sha256: String,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct TokenFile {
+	#[serde(default, rename = "token")]
+	tokens: Vec<Token>,
+}
+
+/// Read all tokens from the token file.
+///
+/// If the token file does not exist, an empty list is returned.
+pub fn read_tokens(path: &Path) -> Result<Vec<Token>, Error> {
+	let file: TokenFile = util::read_toml_or_default(path)?;
+	Ok(file.tokens)
+}
+
+/// Modify the tokens in the token file while holding an exclusive lock on the file.
+///
+/// The token file is created if it does not exist yet.
+pub fn modify_tokens<T>(path: &Path, modify: impl FnOnce(&mut Vec<Token>) -> Result<T, Error>) -> Result<T, Error> {
+	util::modify_toml(path, |file: &mut TokenFile| modify(&mut file.tokens))
+}
+
+/// Generate a new random token.
+pub fn generate_token() -> Result<String, Error> {
+	let mut data = [0u8; 32];
+	getrandom::fill(&mut data)
+		.map_err(|e| Error::new(format!("failed to generate random token: {}", e)))?;
+	Ok(util::to_hex(data))
+}
+
+/// Hash a token for storage in the token file.
+pub fn hash_token(token: &str) -> String {
+	util::compute_sha256_hex(token)
+}
+
+impl Token {
+	/// Check if this token matches the given plaintext token.
+	pub fn matches(&self, token: &str) -> bool {
+		self.sha256.eq_ignore_ascii_case(&hash_token(token))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_util;
+
+	#[test]
+	fn authenticate() {
+		let dir = tempfile::tempdir().unwrap();
+		let registry = test_util::init_registry(dir.path());
+		let alice = registry.create_token("alice-laptop", "alice").unwrap();
+		let bob = registry.create_token("bob-ci", "bob").unwrap();
+		assert_ne!(alice, bob);
+
+		assert_eq!(registry.authenticate(&alice).unwrap().as_deref(), Some("alice"));
+		assert_eq!(registry.authenticate(&bob).unwrap().as_deref(), Some("bob"));
+		assert_eq!(registry.authenticate(&alice.to_ascii_uppercase()).unwrap(), None);
+		assert_eq!(registry.authenticate("").unwrap(), None);
+		assert_eq!(registry.authenticate(&generate_token().unwrap()).unwrap(), None);
+
+		// The hash of a token is not a valid token.
+		assert_eq!(registry.authenticate(&hash_token(&alice)).unwrap(), None);
+	}
+
+	#[test]
+	fn revoked_tokens_do_not_authenticate() {
+		let dir = tempfile::tempdir().unwrap();
+		let registry = test_util::init_registry(dir.path());
+		let alice = registry.create_token("alice-laptop", "alice").unwrap();
+		let bob = registry.create_token("bob-ci", "bob").unwrap();
+
+		assert!(registry.revoke_token("alice-laptop").unwrap());
+		assert!(!registry.revoke_token("alice-laptop").unwrap());
+		assert_eq!(registry.authenticate(&alice).unwrap(), None);
+		assert_eq!(registry.authenticate(&bob).unwrap().as_deref(), Some("bob"));
+	}
+
+	#[test]
+	fn duplicate_names_are_refused() {
+		let dir = tempfile::tempdir().unwrap();
+		let registry = test_util::init_registry(dir.path());
+		registry.create_token("laptop", "alice").unwrap();
+		assert!(registry.create_token("laptop", "bob").is_err());
+		assert_eq!(registry.list_tokens().unwrap().len(), 1);
+	}
+
+	#[test]
+	fn only_the_hash_is_stored() {
+		let dir = tempfile::tempdir().unwrap();
+		let registry = test_util::init_registry(dir.path());
+		let token = registry.create_token("alice-laptop", "alice").unwrap();
+
+		let tokens = registry.list_tokens().unwrap();
+		assert_eq!(tokens.len(), 1);
+		assert_eq!(tokens[0].name, "alice-laptop");
+		assert_eq!(tokens[0].user, "alice");
+		assert_eq!(tokens[0].sha256, hash_token(&token));
+		assert_eq!(tokens[0].sha256, util::compute_sha256_hex(&token));
+
+		let file = std::fs::read_to_string(registry.token_file()).unwrap();
+		assert!(file.contains(&hash_token(&token)));
+		assert!(!file.contains(&token));
+	}
+}
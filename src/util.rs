@@ -2,7 +2,7 @@
 
 use std::fs::File;
 use std::io::Write;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use crate::error::Error;
 
@@ -114,6 +114,27 @@ pub fn open_file_read_write(path: impl AsRef<Path>) -> Result<File, Error> {
 	Ok(file)
 }
 
+/// Open a file for reading and writing, locked for exclusive access.
+///
+/// The file and all parent directories are created if they do not yet exist.
+pub fn open_file_create_read_write(path: impl AsRef<Path>) -> Result<File, Error> {
+	let path = path.as_ref();
+
+	if let Some(parent) = path.parent() {
+		create_dirs(parent)?;
+	}
+
+	let file = std::fs::OpenOptions::new()
+		.read(true)
+		.write(true)
+		.create(true)
+		.truncate(false)
+		.open(path)
+		.map_err(|e| Error::new(format!("failed to open {} for reading and writing: {}", path.display(), e)))?;
+	lock_exclusive(&file, path)?;
+	Ok(file)
+}
+
 /// Open a file for writing, truncating it and locked for exclusive access.
 ///
 /// The file and all parent directories are created if they do not yet exist.
@@ -252,6 +273,44 @@ pub fn read_toml<T: serde::de::DeserializeOwned>(path: impl AsRef<Path>) -> Resu
 	Ok(parsed)
 }
 
+/// Read a file containing TOML, or return the default value if the file does not exist.
+pub fn read_toml_or_default<T: serde::de::DeserializeOwned + Default>(path: impl AsRef<Path>) -> Result<T, Error> {
+	let path = path.as_ref();
+	if !path.exists() {
+		return Ok(T::default());
+	}
+	read_toml(path)
+}
+
+/// Modify a file containing TOML while holding an exclusive lock on the file.
+///
+/// If the file does not exist yet, it is created and the modification is applied to the default value.
+/// The file is only written if the modification succeeds.
+pub fn modify_toml<T, R>(path: impl AsRef<Path>, modify: impl FnOnce(&mut T) -> Result<R, Error>) -> Result<R, Error>
+where
+	T: serde::de::DeserializeOwned + serde::Serialize + Default,
+{
+	use std::io::Read;
+	let path = path.as_ref();
+	let mut file = open_file_create_read_write(path)?;
+	let mut data = Vec::new();
+	file.read_to_end(&mut data)
+		.map_err(|e| Error::new(format!("failed to read from {}: {}", path.display(), e)))?;
+
+	let mut value = if data.is_empty() {
+		T::default()
+	} else {
+		parse_toml(&data, &path.display())?
+	};
+
+	let result = modify(&mut value)?;
+
+	let data = toml::to_string(&value)
+		.map_err(|e| Error::new(format!("failed to serialize {}: {}", path.display(), e)))?;
+	overwrite_contents(&mut file, path, data)?;
+	Ok(result)
+}
+
 /// Parse bytes as a TOML structure.
 pub fn parse_toml<'a, T: serde::Deserialize<'a>>(data: &'a [u8], path: &impl std::fmt::Display) -> Result<T, Error> {
 	toml::from_slice(data)
@@ -260,12 +319,38 @@ pub fn parse_toml<'a, T: serde::Deserialize<'a>>(data: &'a [u8], path: &impl std
 
 /// Compute the sha256sum of some data and return it as lowercase hex string.
 pub fn compute_sha256_hex(data: impl AsRef<[u8]>) -> String {
-	use std::fmt::Write;
 	use sha2::{Digest, Sha256};
-	let digest = Sha256::digest(data.as_ref());
-	let mut output = String::with_capacity(digest.len() * 2);
-	for byte in digest {
-		write!(output, "{byte:02X}").unwrap();
+	to_hex(Sha256::digest(data.as_ref()))
+}
+
+/// Format bytes as lowercase hex string.
+pub fn to_hex(data: impl AsRef<[u8]>) -> String {
+	use std::fmt::Write;
+	let data = data.as_ref();
+	let mut output = String::with_capacity(data.len() * 2);
+	for byte in data {
+		write!(output, "{byte:02x}").unwrap();
 	}
 	output
 }
+
+/// Iterate over all regular files in a directory, recursively.
+///
+/// Hidden files and directories are skipped.
+pub fn iter_files(dir: &Path) -> impl Iterator<Item = Result<PathBuf, Error>> {
+	walkdir::WalkDir::new(dir)
+		.into_iter()
+		.filter_entry(|entry| {
+			entry.depth() == 0 || entry.file_name().to_str().map(|name| !name.starts_with('.')).unwrap_or(true)
+		})
+		.filter_map(|item| {
+			let item = match item {
+				Err(e) => return Some(Err(Error::new(format!("Failed to read directory entry: {}", e)))),
+				Ok(x) => x,
+			};
+			if !item.file_type().is_file() {
+				return None;
+			}
+			Some(Ok(item.into_path()))
+		})
+}
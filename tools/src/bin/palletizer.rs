@@ -16,6 +16,18 @@ enum Command {
 	Delete(DeleteCrate),
 	Yank(YankCrate),
 	Unyank(UnyankCrate),
+	#[clap(subcommand)]
+	Token(TokenCommand),
+	#[clap(subcommand)]
+	Owner(OwnerCommand),
+	ReindexMetadata(ReindexMetadata),
+	UpdateConfig(UpdateConfig),
+	Fsck(Fsck),
+	MigrateIndex(MigrateIndex),
+	Gc(Gc),
+	Export(Export),
+	Import(Import),
+	Mirror(Mirror),
 }
 
 /// Initialize a new registry.
@@ -40,6 +52,11 @@ struct Init {
 	#[clap(default_value = "crates")]
 	crate_dir: PathBuf,
 
+	/// Directory to store additional crate metadata.
+	#[clap(long)]
+	#[clap(default_value = "metadata")]
+	metadata_dir: PathBuf,
+
 	/// Custom allowed registries for dependencies.
 	#[clap(long = "allowed-registry")]
 	allowed_registries: Vec<String>,
@@ -47,6 +64,10 @@ struct Init {
 	/// Do not automatically allow dependencies from crates.io.
 	#[clap(long)]
 	no_crates_io: bool,
+
+	/// Require authentication for index and crate downloads too.
+	#[clap(long)]
+	auth_required: bool,
 }
 
 /// Add a crate to the registry.
@@ -103,6 +124,282 @@ struct UnyankCrate {
 	version: String,
 }
 
+/// Manage API tokens.
+#[derive(clap::Subcommand)]
+enum TokenCommand {
+	Create(CreateToken),
+	List(ListTokens),
+	Revoke(RevokeToken),
+}
+
+/// Create a new API token.
+#[derive(clap::Parser)]
+struct CreateToken {
+	/// The root of of registry to work on.
+	#[clap(long, short)]
+	#[clap(default_value = ".")]
+	registry: PathBuf,
+
+	/// The user that the token belongs to.
+	#[clap(long, short)]
+	user: String,
+
+	/// A unique name for the token.
+	name: String,
+}
+
+/// List all API tokens.
+#[derive(clap::Parser)]
+struct ListTokens {
+	/// The root of of registry to work on.
+	#[clap(long, short)]
+	#[clap(default_value = ".")]
+	registry: PathBuf,
+}
+
+/// Revoke an API token.
+#[derive(clap::Parser)]
+struct RevokeToken {
+	/// The root of of registry to work on.
+	#[clap(long, short)]
+	#[clap(default_value = ".")]
+	registry: PathBuf,
+
+	/// The name of the token to revoke.
+	name: String,
+}
+
+/// Manage crate owners.
+#[derive(clap::Subcommand)]
+enum OwnerCommand {
+	List(ListOwners),
+	Add(AddOwners),
+	Remove(RemoveOwners),
+	Backfill(BackfillOwners),
+}
+
+/// List the owners of a crate.
+#[derive(clap::Parser)]
+struct ListOwners {
+	/// The root of of registry to work on.
+	#[clap(long, short)]
+	#[clap(default_value = ".")]
+	registry: PathBuf,
+
+	/// The name of the crate.
+	name: String,
+}
+
+/// Add owners to a crate.
+#[derive(clap::Parser)]
+struct AddOwners {
+	/// The root of of registry to work on.
+	#[clap(long, short)]
+	#[clap(default_value = ".")]
+	registry: PathBuf,
+
+	/// The name of the crate.
+	name: String,
+
+	/// The users to add as owner.
+	#[clap(required = true)]
+	users: Vec<String>,
+}
+
+/// Remove owners from a crate.
+#[derive(clap::Parser)]
+struct RemoveOwners {
+	/// The root of of registry to work on.
+	#[clap(long, short)]
+	#[clap(default_value = ".")]
+	registry: PathBuf,
+
+	/// The name of the crate.
+	name: String,
+
+	/// The users to remove as owner.
+	#[clap(required = true)]
+	users: Vec<String>,
+}
+
+/// Add owners to all crates that have no owners.
+///
+/// Crates without owners, such as crates added with `palletizer add`, can not be modified through the API.
+#[derive(clap::Parser)]
+struct BackfillOwners {
+	/// The root of of registry to work on.
+	#[clap(long, short)]
+	#[clap(default_value = ".")]
+	registry: PathBuf,
+
+	/// Only show which crates would get owners.
+	#[clap(long)]
+	dry_run: bool,
+
+	/// The users to add as owner.
+	#[clap(required = true)]
+	users: Vec<String>,
+}
+
+/// Update the stored metadata of crates from the crate files.
+///
+/// This reads the description, license, keywords, readme and other metadata
+/// from the manifest of each stored crate file.
+#[derive(clap::Parser)]
+struct ReindexMetadata {
+	/// The root of of registry to work on.
+	#[clap(long, short)]
+	#[clap(default_value = ".")]
+	registry: PathBuf,
+
+	/// Also update versions that already have stored metadata.
+	#[clap(long)]
+	overwrite: bool,
+}
+
+/// Update `config.json` in the index to match the registry configuration.
+///
+/// Run this after changing `api_url`, `download_url` or `auth_required` in `palletizer.toml`.
+/// The server also does this when it opens the registry.
+#[derive(clap::Parser)]
+struct UpdateConfig {
+	/// The root of of registry to work on.
+	#[clap(long, short)]
+	#[clap(default_value = ".")]
+	registry: PathBuf,
+}
+
+/// Check the consistency of the registry.
+///
+/// Prints a report in JSON format.
+/// Exits with status 1 if any problems remain.
+#[derive(clap::Parser)]
+struct Fsck {
+	/// The root of of registry to work on.
+	#[clap(long, short)]
+	#[clap(default_value = ".")]
+	registry: PathBuf,
+
+	/// Repair the problems that can be repaired safely.
+	///
+	/// Currently, this only moves misplaced index files to the expected location.
+	#[clap(long)]
+	repair: bool,
+}
+
+/// Migrate all index files to the current index format.
+///
+/// This lowercases checksums, moves features with namespaced or weak dependencies to `features2`,
+/// and adds missing `rust_version` fields.
+/// All changes are committed in a single commit.
+#[derive(clap::Parser)]
+struct MigrateIndex {
+	/// The root of of registry to work on.
+	#[clap(long, short)]
+	#[clap(default_value = ".")]
+	registry: PathBuf,
+
+	/// Only show the changes as diff, without writing anything.
+	#[clap(long)]
+	dry_run: bool,
+}
+
+/// Remove crate files and empty directories that are not referenced by the index.
+///
+/// This cleans up after failed publish attempts, which can leave a crate file without index entry.
+#[derive(clap::Parser)]
+struct Gc {
+	/// The root of of registry to work on.
+	#[clap(long, short)]
+	#[clap(default_value = ".")]
+	registry: PathBuf,
+
+	/// Only report what would be removed, without removing anything.
+	#[clap(long)]
+	dry_run: bool,
+
+	/// Move unreferenced crate files to this directory instead of deleting them.
+	#[clap(long)]
+	#[clap(conflicts_with = "dry_run")]
+	quarantine: Option<PathBuf>,
+}
+
+/// Export the registry to a single archive for backups.
+///
+/// The archive holds the configuration, a git bundle of the index, all crate files and a checksum manifest.
+/// The registry is locked while the archive is written, so it is safe to export a registry while the server is running.
+#[derive(clap::Parser)]
+struct Export {
+	/// The root of of registry to work on.
+	#[clap(long, short)]
+	#[clap(default_value = ".")]
+	registry: PathBuf,
+
+	/// The path of the archive to create.
+	output: PathBuf,
+}
+
+/// Restore a registry from an archive created by `palletizer export`.
+///
+/// The checksums of all files are verified before anything is written.
+#[derive(clap::Parser)]
+struct Import {
+	/// The directory to restore the registry in.
+	///
+	/// The directory must be empty or not exist yet.
+	#[clap(long, short)]
+	#[clap(default_value = ".")]
+	registry: PathBuf,
+
+	/// The archive to import.
+	archive: PathBuf,
+}
+
+/// Mirror crates from another registry.
+///
+/// Crates that are already in the registry are skipped.
+/// The checksum of each downloaded crate is verified against the source index,
+/// and the original index entry is kept, including the yank state.
+#[derive(clap::Parser)]
+struct Mirror {
+	/// The root of of registry to work on.
+	#[clap(long, short)]
+	#[clap(default_value = ".")]
+	registry: PathBuf,
+
+	/// The index of the registry to mirror from.
+	///
+	/// This can be a local directory with the layout of a registry index (like a git clone or a sparse index),
+	/// or the URL of a git index repository.
+	#[clap(long)]
+	from: String,
+
+	/// The download URL template of the registry to mirror from.
+	///
+	/// This uses the same markers as the `dl` field in the `config.json` of a registry index.
+	/// It can also be a local path.
+	#[clap(long)]
+	download_url: String,
+
+	/// Only mirror crates with this name.
+	///
+	/// Can be given multiple times.
+	#[clap(long = "crate")]
+	#[clap(value_name = "NAME")]
+	crate_names: Vec<String>,
+
+	/// Only mirror this version.
+	///
+	/// Can be given multiple times.
+	#[clap(long = "crate-version")]
+	#[clap(value_name = "VERSION")]
+	versions: Vec<String>,
+
+	/// Only show which crates would be mirrored, without downloading or adding anything.
+	#[clap(long)]
+	dry_run: bool,
+}
+
 fn main() {
 	if do_main(clap::Parser::parse()).is_err() {
 		std::process::exit(1);
@@ -116,6 +413,21 @@ fn do_main(options: Options) -> Result<(), ()> {
 		Command::Delete(command) => delete_crate(command),
 		Command::Yank(command) => yank_crate(command),
 		Command::Unyank(command) => unyank_crate(command),
+		Command::Token(TokenCommand::Create(command)) => create_token(command),
+		Command::Token(TokenCommand::List(command)) => list_tokens(command),
+		Command::Token(TokenCommand::Revoke(command)) => revoke_token(command),
+		Command::Owner(OwnerCommand::List(command)) => list_owners(command),
+		Command::Owner(OwnerCommand::Add(command)) => add_owners(command),
+		Command::Owner(OwnerCommand::Remove(command)) => remove_owners(command),
+		Command::Owner(OwnerCommand::Backfill(command)) => backfill_owners(command),
+		Command::ReindexMetadata(command) => reindex_metadata(command),
+		Command::UpdateConfig(command) => update_config(command),
+		Command::Fsck(command) => fsck(command),
+		Command::MigrateIndex(command) => migrate_index(command),
+		Command::Gc(command) => gc(command),
+		Command::Export(command) => export(command),
+		Command::Import(command) => import(command),
+		Command::Mirror(command) => mirror(command),
 	}
 }
 
@@ -134,7 +446,10 @@ fn init(command: &Init) -> Result<(), ()> {
 		api_url,
 		index_dir: command.index_dir.clone(),
 		crate_dir: command.crate_dir.clone(),
+		metadata_dir: command.metadata_dir.clone(),
 		allowed_registries: command.allowed_registries.clone(),
+		auth_required: command.auth_required,
+		storage: Default::default(),
 	};
 
 	let registry = Registry::init(&command.registry, config)
@@ -145,6 +460,11 @@ fn init(command: &Init) -> Result<(), ()> {
 	println!("To use the registry, add this to your Cargo configuration (for example `$HOME/.cargo/config.toml`):");
 	println!();
 	println!("[registries]");
+	println!("my-registry = {{ index = \"sparse+{url}/index/\" }}", url = registry.api_url());
+	println!();
+	println!("If you are using an old version of Cargo without support for the sparse protocol, use the git protocol instead:");
+	println!();
+	println!("[registries]");
 	println!("my-registry = {{ index = \"{url}/index\" }}", url = registry.api_url());
 
 	Ok(())
@@ -153,8 +473,12 @@ fn init(command: &Init) -> Result<(), ()> {
 fn add_crate(command: &AddCrate) -> Result<(), ()> {
 	let mut registry = Registry::open(&command.registry)
 		.map_err(|e| eprintln!("{}", e))?;
-	registry.add_crate_from_file(&command.crate_file)
+	let metadata_error = registry.add_crate_from_file(&command.crate_file)
 		.map_err(|e| eprintln!("{}", e))?;
+	if let Some(e) = metadata_error {
+		eprintln!("Added crate without metadata: {}", e);
+		eprintln!("Run `palletizer reindex-metadata` to store the metadata later.");
+	}
 	Ok(())
 }
 
@@ -181,3 +505,384 @@ fn unyank_crate(command: &UnyankCrate) -> Result<(), ()> {
 		.map_err(|e| eprintln!("{}", e))?;
 	Ok(())
 }
+
+fn create_token(command: &CreateToken) -> Result<(), ()> {
+	let registry = Registry::open(&command.registry)
+		.map_err(|e| eprintln!("{}", e))?;
+	let token = registry.create_token(&command.name, &command.user)
+		.map_err(|e| eprintln!("{}", e))?;
+
+	println!("Created token {:?} for user {:?}.", command.name, command.user);
+	println!("The token will not be shown again, so make sure to save it now:");
+	println!();
+	println!("{}", token);
+	println!();
+	println!("To use the token with Cargo, run `cargo login --registry my-registry` and enter the token.");
+	Ok(())
+}
+
+fn list_tokens(command: &ListTokens) -> Result<(), ()> {
+	let registry = Registry::open(&command.registry)
+		.map_err(|e| eprintln!("{}", e))?;
+	let tokens = registry.list_tokens()
+		.map_err(|e| eprintln!("{}", e))?;
+	for token in tokens {
+		println!("{}\t{}", token.name, token.user);
+	}
+	Ok(())
+}
+
+fn revoke_token(command: &RevokeToken) -> Result<(), ()> {
+	let registry = Registry::open(&command.registry)
+		.map_err(|e| eprintln!("{}", e))?;
+	let revoked = registry.revoke_token(&command.name)
+		.map_err(|e| eprintln!("{}", e))?;
+	if !revoked {
+		eprintln!("No token named {:?}", command.name);
+		return Err(());
+	}
+	Ok(())
+}
+
+fn list_owners(command: &ListOwners) -> Result<(), ()> {
+	let registry = Registry::open(&command.registry)
+		.map_err(|e| eprintln!("{}", e))?;
+	if !registry.contains_crate(&command.name) {
+		eprintln!("No such crate: {}", command.name);
+		return Err(());
+	}
+	let owners = registry.crate_owners(&command.name)
+		.map_err(|e| eprintln!("{}", e))?;
+	for owner in owners {
+		println!("{}", owner);
+	}
+	Ok(())
+}
+
+fn add_owners(command: &AddOwners) -> Result<(), ()> {
+	let registry = Registry::open(&command.registry)
+		.map_err(|e| eprintln!("{}", e))?;
+	if !registry.contains_crate(&command.name) {
+		eprintln!("No such crate: {}", command.name);
+		return Err(());
+	}
+	registry.add_crate_owners(&command.name, &command.users)
+		.map_err(|e| eprintln!("{}", e))?;
+	Ok(())
+}
+
+fn remove_owners(command: &RemoveOwners) -> Result<(), ()> {
+	let registry = Registry::open(&command.registry)
+		.map_err(|e| eprintln!("{}", e))?;
+	registry.remove_crate_owners(&command.name, &command.users)
+		.map_err(|e| eprintln!("{}", e))?;
+	Ok(())
+}
+
+fn backfill_owners(command: &BackfillOwners) -> Result<(), ()> {
+	let registry = Registry::open(&command.registry)
+		.map_err(|e| eprintln!("{}", e))?;
+
+	let mut failed = false;
+	for name in registry.iter_crate_names() {
+		let name = match name {
+			Ok(x) => x,
+			Err(e) => {
+				eprintln!("{}", e);
+				failed = true;
+				continue;
+			},
+		};
+		match registry.crate_owners(&name) {
+			Ok(owners) if owners.is_empty() => (),
+			Ok(_) => continue,
+			Err(e) => {
+				eprintln!("{}", e);
+				failed = true;
+				continue;
+			},
+		}
+		if command.dry_run {
+			println!("Would add {} as owner of {}", command.users.join(", "), name);
+		} else if let Err(e) = registry.add_crate_owners(&name, &command.users) {
+			eprintln!("Failed to add owners to {}: {}", name, e);
+			failed = true;
+		} else {
+			println!("Added {} as owner of {}", command.users.join(", "), name);
+		}
+	}
+
+	if failed {
+		Err(())
+	} else {
+		Ok(())
+	}
+}
+
+fn reindex_metadata(command: &ReindexMetadata) -> Result<(), ()> {
+	let registry = Registry::open(&command.registry)
+		.map_err(|e| eprintln!("{}", e))?;
+
+	let mut updated = 0;
+	let mut failed = 0;
+	for name in registry.iter_crate_names() {
+		let name = match name {
+			Ok(x) => x,
+			Err(e) => {
+				eprintln!("{}", e);
+				failed += 1;
+				continue;
+			},
+		};
+		let entries = match registry.read_index(&name) {
+			Ok(x) => x,
+			Err(e) => {
+				eprintln!("{}", e);
+				failed += 1;
+				continue;
+			},
+		};
+		for entry in entries {
+			match registry.reindex_metadata(&entry.name, &entry.version, command.overwrite) {
+				Ok(true) => {
+					println!("Updated metadata for {}-{}", entry.name, entry.version);
+					updated += 1;
+				},
+				Ok(false) => (),
+				Err(e) => {
+					eprintln!("Failed to update metadata for {}-{}: {}", entry.name, entry.version, e);
+					failed += 1;
+				},
+			}
+		}
+	}
+
+	println!("Updated metadata for {} crate version(s).", updated);
+	if failed > 0 {
+		eprintln!("Encountered {} error(s).", failed);
+		return Err(());
+	}
+	Ok(())
+}
+
+fn update_config(command: &UpdateConfig) -> Result<(), ()> {
+	let registry = Registry::open(&command.registry)
+		.map_err(|e| eprintln!("{}", e))?;
+	let updated = registry.update_cargo_config()
+		.map_err(|e| eprintln!("{}", e))?;
+	if updated {
+		println!("Updated config.json of the index.");
+	} else {
+		println!("The config.json of the index is already up to date.");
+	}
+	Ok(())
+}
+
+fn fsck(command: &Fsck) -> Result<(), ()> {
+	let mut registry = Registry::open(&command.registry)
+		.map_err(|e| eprintln!("{}", e))?;
+	let mut report = registry.fsck()
+		.map_err(|e| eprintln!("{}", e))?;
+
+	if command.repair && report.problems.iter().any(|x| x.is_repairable()) {
+		let repaired = registry.fsck_repair(&report)
+			.map_err(|e| eprintln!("{}", e))?;
+		for problem in &repaired {
+			eprintln!("Repaired: {:?}", problem);
+		}
+		report = registry.fsck()
+			.map_err(|e| eprintln!("{}", e))?;
+	}
+
+	println!("{}", report.to_json());
+	if report.is_ok() {
+		Ok(())
+	} else {
+		Err(())
+	}
+}
+
+fn migrate_index(command: &MigrateIndex) -> Result<(), ()> {
+	let mut registry = Registry::open(&command.registry)
+		.map_err(|e| eprintln!("{}", e))?;
+	let changes = registry.plan_index_migration()
+		.map_err(|e| eprintln!("{}", e))?;
+
+	if command.dry_run {
+		for change in &changes {
+			print!("{}", change.diff());
+		}
+		eprintln!("Would migrate {} index file(s).", changes.len());
+		return Ok(());
+	}
+
+	registry.apply_index_migration(&changes)
+		.map_err(|e| eprintln!("{}", e))?;
+	println!("Migrated {} index file(s).", changes.len());
+	Ok(())
+}
+
+fn gc(command: &Gc) -> Result<(), ()> {
+	let registry = Registry::open(&command.registry)
+		.map_err(|e| eprintln!("{}", e))?;
+
+	let mode = if command.dry_run {
+		palletizer::gc::Mode::DryRun
+	} else if let Some(dir) = &command.quarantine {
+		palletizer::gc::Mode::Quarantine(dir.clone())
+	} else {
+		palletizer::gc::Mode::Delete
+	};
+	let report = registry.collect_garbage(&mode)
+		.map_err(|e| eprintln!("{}", e))?;
+
+	println!("{}", report.to_json());
+	let action = match mode {
+		palletizer::gc::Mode::DryRun => "Would free",
+		palletizer::gc::Mode::Delete => "Freed",
+		palletizer::gc::Mode::Quarantine(_) => "Quarantined",
+	};
+	eprintln!("{} {} in {} file(s).", action, format_size(report.freed_bytes), report.files.len());
+	Ok(())
+}
+
+fn export(command: &Export) -> Result<(), ()> {
+	use std::io::Write;
+	let registry = Registry::open(&command.registry)
+		.map_err(|e| eprintln!("{}", e))?;
+	let output = std::fs::OpenOptions::new()
+		.write(true)
+		.create_new(true)
+		.open(&command.output)
+		.map_err(|e| eprintln!("Failed to create {}: {}", command.output.display(), e))?;
+	let mut output = std::io::BufWriter::new(output);
+
+	let summary = registry.export(&mut output)
+		.map_err(|e| eprintln!("{}", e))?;
+	output.flush()
+		.map_err(|e| eprintln!("Failed to write to {}: {}", command.output.display(), e))?;
+
+	println!("Exported {} crate file(s) to {}.", summary.crates, command.output.display());
+	Ok(())
+}
+
+fn import(command: &Import) -> Result<(), ()> {
+	let (_registry, summary) = Registry::import(&command.archive, &command.registry)
+		.map_err(|e| eprintln!("{}", e))?;
+	println!("Imported {} crate file(s) into {}.", summary.crates, command.registry.display());
+	Ok(())
+}
+
+fn mirror(command: &Mirror) -> Result<(), ()> {
+	let mut registry = Registry::open(&command.registry)
+		.map_err(|e| eprintln!("{}", e))?;
+
+	// Clone remote indexes to a temporary directory.
+	let is_url = command.from.contains("://");
+	let source = if is_url {
+		let path = std::env::temp_dir().join(format!("palletizer-mirror-{}", std::process::id()));
+		eprintln!("Cloning {}", command.from);
+		palletizer::mirror::clone_index(&command.from, &path)
+			.map_err(|e| eprintln!("{}", e))?;
+		path
+	} else {
+		PathBuf::from(&command.from)
+	};
+
+	let result = mirror_from(&mut registry, &source, command);
+	if is_url {
+		std::fs::remove_dir_all(&source)
+			.map_err(|e| eprintln!("Failed to remove {}: {}", source.display(), e))
+			.ok();
+	}
+	result
+}
+
+fn mirror_from(registry: &mut Registry, source: &std::path::Path, command: &Mirror) -> Result<(), ()> {
+	let filter = palletizer::mirror::Filter {
+		names: command.crate_names.clone(),
+		versions: command.versions.clone(),
+	};
+	let plan = registry.plan_mirror(source, &filter)
+		.map_err(|e| eprintln!("{}", e))?;
+	for (entry, reason) in &plan.invalid {
+		eprintln!("Skipping {}-{}: {}", entry.name, entry.version, reason);
+	}
+
+	if command.dry_run {
+		for entry in &plan.entries {
+			println!("{}-{}", entry.name, entry.version);
+		}
+		eprintln!(
+			"Would mirror {} crate version(s), skipping {} existing version(s) and {} version(s) with an invalid name.",
+			plan.entries.len(),
+			plan.existing,
+			plan.invalid.len(),
+		);
+		return Ok(());
+	}
+
+	let mut failed = 0;
+	for entry in &plan.entries {
+		let url = palletizer::index::expand_download_url(&command.download_url, &entry.name, &entry.version, &entry.checksum_sha256);
+		let result = download(&url)
+			.and_then(|data| registry.add_mirrored_crate(entry, &data).map_err(|e| e.to_string()));
+		match result {
+			Ok(None) => eprintln!("Mirrored {}-{}", entry.name, entry.version),
+			Ok(Some(e)) => eprintln!("Mirrored {}-{} without metadata: {}", entry.name, entry.version, e),
+			Err(e) => {
+				eprintln!("Failed to mirror {}-{}: {}", entry.name, entry.version, e);
+				failed += 1;
+			},
+		}
+	}
+
+	eprintln!(
+		"Mirrored {} crate version(s), skipped {} existing version(s) and {} version(s) with an invalid name, {} failed.",
+		plan.entries.len() - failed,
+		plan.existing,
+		plan.invalid.len(),
+		failed,
+	);
+	if failed == 0 {
+		Ok(())
+	} else {
+		Err(())
+	}
+}
+
+/// Download a file from a URL or read it from a local path.
+fn download(location: &str) -> Result<Vec<u8>, String> {
+	use std::io::Read;
+	if !location.starts_with("http://") && !location.starts_with("https://") {
+		return std::fs::read(location)
+			.map_err(|e| format!("failed to read {}: {}", location, e));
+	}
+
+	let response = ureq::get(location).call()
+		.map_err(|e| format!("failed to download {}: {}", location, e))?;
+	let mut data = Vec::new();
+	response.into_body()
+		.into_reader()
+		.read_to_end(&mut data)
+		.map_err(|e| format!("failed to read response from {}: {}", location, e))?;
+	Ok(data)
+}
+
+/// Format a size in bytes for humans.
+fn format_size(bytes: u64) -> String {
+	let units = ["KiB", "MiB", "GiB", "TiB"];
+	if bytes < 1024 {
+		return format!("{} bytes", bytes);
+	}
+	let mut size = bytes as f64;
+	let mut unit = "";
+	for x in units {
+		if size < 1024.0 {
+			break;
+		}
+		size /= 1024.0;
+		unit = x;
+	}
+	format!("{:.1} {}", size, unit)
+}